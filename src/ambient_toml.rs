@@ -1,34 +1,257 @@
 use anyhow::Context;
 use semver::VersionReq;
-use serde::Deserialize;
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::{path::Path, str::FromStr};
 
 // This is a subset of the actual ambient.toml, so that it will be compatible with as many different versions as possible.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct AmbientToml {
     pub package: Package,
 }
 impl AmbientToml {
     pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
-        Ok(toml::from_str(
-            std::fs::read_to_string(path.as_ref())?.as_str(),
-        )?)
+        let path = std::path::absolute(path.as_ref())?;
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&content, &path)
     }
+    /// Errors name `path` and point at the offending line
+    pub fn parse(content: &str, path: &Path) -> anyhow::Result<Self> {
+        let err = match toml::from_str(content) {
+            Ok(toml) => return Ok(toml),
+            Err(err) => err,
+        };
+        let message = match content.parse::<toml::Table>() {
+            Ok(table) => match table.get("package") {
+                // A workspace root has its settings, like ambient_version, in `[workspace]`
+                None if table.contains_key("workspace") => {
+                    if let Ok(package) = table["workspace"].clone().try_into() {
+                        return Ok(Self { package });
+                    }
+                    err.message().to_string()
+                }
+                None => anyhow::bail!(
+                    "{} doesn't look like an Ambient package manifest: it has no [package] table",
+                    path.display()
+                ),
+                Some(package) => match package.get("ambient_version") {
+                    Some(version) if !version.is_str() => format!(
+                        "ambient_version must be a quoted version requirement like \"0.3.0\" or \"^0.3\", not {}",
+                        version
+                    ),
+                    _ => err.message().to_string(),
+                },
+            },
+            Err(_) => err.message().to_string(),
+        };
+        let Some(span) = err.span() else {
+            anyhow::bail!("Invalid {}: {}", path.display(), message);
+        };
+        let (line, column) = line_column(content, span.start);
+        anyhow::bail!(
+            "Invalid {}:{}:{}: {}\n{:>4} | {}",
+            path.display(),
+            line,
+            column,
+            message,
+            line,
+            content.lines().nth(line - 1).unwrap_or_default()
+        )
+    }
+}
+
+impl FromStr for AmbientToml {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s, Path::new("ambient.toml"))
+    }
+}
+
+/// 1-based line and column of the byte `offset` in `content`
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Package {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub version: Option<String>,
     pub ambient_version: Option<VersionReq>,
 }
+impl Package {
+    /// `<name> <version> — <description>`, leaving out what isn't set
+    pub fn summary(&self) -> String {
+        let mut summary = self.name.clone().unwrap_or_else(|| "(unnamed)".to_string());
+        if let Some(version) = &self.version {
+            summary += &format!(" {}", version);
+        }
+        if let Some(description) = &self.description {
+            summary += &format!(" — {}", description);
+        }
+        summary
+    }
+    /// Checks that the package has a name and a semver version
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self
+            .name
+            .as_deref()
+            .is_none_or(|name| name.trim().is_empty())
+        {
+            anyhow::bail!("ambient.toml has no package name");
+        }
+        if let Some(version) = &self.version {
+            semver::Version::parse(version).map_err(|err| {
+                anyhow::anyhow!(
+                    "Package version {:?} is not a valid semver version: {}",
+                    version,
+                    err
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Only the `ambient_version` value changes; comments and formatting around it are kept as they are.
+/// A workspace root without a `[package]` table has it in `[workspace]`
+pub fn set_ambient_toml_runtime_version(doc: &mut toml_edit::Document, version: &str) {
+    let table = if doc.get("package").is_none() && doc.get("workspace").is_some() {
+        "workspace"
+    } else {
+        "package"
+    };
+    let item = &mut doc[table]["ambient_version"];
+    let decor = item.as_value().map(|value| value.decor().clone());
+    *item = toml_edit::value(version);
+    if let (Some(decor), Some(value)) = (decor, item.as_value_mut()) {
+        *value.decor_mut() = decor;
+    }
+}
+
+#[test]
+fn test_package_fields() {
+    let toml: AmbientToml = toml::from_str(
+        r#"[package]
+name = "My Project"
+description = "A test project"
+version = "0.0.1"
+ambient_version = "0.3.0"
+"#,
+    )
+    .unwrap();
+    assert_eq!(toml.package.summary(), "My Project 0.0.1 — A test project");
+    assert!(toml.package.validate().is_ok());
+
+    // Older ambient.toml files only have some of the fields
+    let toml: AmbientToml = toml::from_str("[package]\nambient_version = \"0.3.0\"\n").unwrap();
+    assert_eq!(toml.package.summary(), "(unnamed)");
+    assert_eq!(
+        toml.package.validate().unwrap_err().to_string(),
+        "ambient.toml has no package name"
+    );
+
+    let package = Package {
+        name: Some("My Project".to_string()),
+        version: Some("1".to_string()),
+        ..Default::default()
+    };
+    assert!(package.validate().is_err());
+}
+
+#[test]
+fn test_parse_errors() {
+    let path = Path::new("/project/ambient.toml");
+    let err = |content: &str| AmbientToml::parse(content, path).unwrap_err().to_string();
+
+    let syntax = err("[package]\nname = \"My Project\nambient_version = \"0.3.0\"\n");
+    assert!(
+        syntax.starts_with("Invalid /project/ambient.toml:2:"),
+        "{}",
+        syntax
+    );
+    assert!(syntax.ends_with("   2 | name = \"My Project"), "{}", syntax);
+
+    let float = err("[package]\nname = \"My Project\"\nambient_version = 0.3\n");
+    assert_eq!(
+        float,
+        "Invalid /project/ambient.toml:3:19: ambient_version must be a quoted version requirement like \"0.3.0\" or \"^0.3\", not 0.3\n   3 | ambient_version = 0.3"
+    );
+
+    let workspace = AmbientToml::parse(
+        "[workspace]\nmembers = [\"game\"]\nambient_version = \"0.3.0\"\n",
+        path,
+    )
+    .unwrap();
+    assert_eq!(
+        workspace.package.ambient_version,
+        Some(VersionReq::parse("0.3.0").unwrap())
+    );
+
+    assert_eq!(
+        err("[dependencies]\nfoo = \"1\"\n"),
+        "/project/ambient.toml doesn't look like an Ambient package manifest: it has no [package] table"
+    );
+
+    let invalid_req = err("[package]\nambient_version = \"not a version\"\n");
+    assert!(
+        invalid_req.starts_with("Invalid /project/ambient.toml:2:19: "),
+        "{}",
+        invalid_req
+    );
+}
+
+#[test]
+fn test_from_str() {
+    let toml: AmbientToml =
+        "[package]\nname = \"My Project\"\nambient_version = \"0.3.0-nightly-2023-10-02\"\n"
+            .parse()
+            .unwrap();
+    assert_eq!(
+        toml.package.ambient_version,
+        Some(VersionReq::parse("^0.3.0-nightly-2023-10-02").unwrap())
+    );
+    assert!(AmbientToml::from_str("[package]\nambient_version = 0.3\n").is_err());
+}
+
+#[test]
+fn test_set_ambient_toml_runtime_version_roundtrip() {
+    let toml: AmbientToml = "[package]\nname = \"My Project\"\nambient_version = \"0.3.0\"\n"
+        .parse()
+        .unwrap();
+    let mut doc = toml::to_string(&toml)
+        .unwrap()
+        .parse::<toml_edit::Document>()
+        .unwrap();
+    set_ambient_toml_runtime_version(&mut doc, "0.3.1");
+    let toml: AmbientToml = doc.to_string().parse().unwrap();
+    assert_eq!(toml.package.name.as_deref(), Some("My Project"));
+    assert_eq!(
+        toml.package.ambient_version,
+        Some(VersionReq::parse("0.3.1").unwrap())
+    );
+}
+
+#[test]
+fn test_set_ambient_toml_runtime_version_preserves_formatting() {
+    let toml = r#"# managed by ambient-cli
+[package]
+# The package id
+id = "my_project"
+name = "My Project"
+ambient_version = "0.3.0"   # managed by ambient-cli
 
-pub fn set_ambient_toml_runtime_version(
-    path: impl AsRef<Path>,
-    version: &str,
-) -> anyhow::Result<()> {
-    use toml_edit::{value, Document};
-    let toml = std::fs::read_to_string(&path).context("Failed to read ambient.toml")?;
-    let mut doc = toml.parse::<Document>().context("Invalid ambient.toml")?;
-    doc["package"]["ambient_version"] = value(version);
-    std::fs::write(path, doc.to_string())?;
-    Ok(())
+[dependencies]
+base_assets = { path = "../base_assets" } # local
+"#;
+    let mut doc = toml.parse::<toml_edit::Document>().unwrap();
+    set_ambient_toml_runtime_version(&mut doc, "0.3.1");
+    assert_eq!(doc.to_string(), toml.replace(r#""0.3.0""#, r#""0.3.1""#));
 }
@@ -0,0 +1,396 @@
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use std::{
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+/// Extracting more than this multiple of the archive size is treated as a zip bomb
+const MAX_COMPRESSION_RATIO: u64 = 20;
+
+/// Extracts the zip in `data` into `dest`. Entries that could end up outside of `dest` (absolute paths, `..`,
+/// symlinks with an absolute or `..` target, entries written through a symlink from the archive) abort the
+/// extraction, as does decompressing to more than `MAX_COMPRESSION_RATIO` times the archive size. When extraction
+/// fails, what it created is removed again: all of `dest` if it didn't exist yet, else only the new files and
+/// directories in it, since `dest` may be a directory of the user's like with `runtime install --dest`.
+pub(crate) fn extract_zip(data: &[u8], dest: &Path) -> anyhow::Result<()> {
+    let existed = dest.exists();
+    let mut created = Vec::new();
+    let result = extract_zip_entries(data, dest, &mut created);
+    if result.is_err() {
+        let cleanup = if existed {
+            remove_created(&created)
+        } else if dest.exists() {
+            std::fs::remove_dir_all(dest)
+        } else {
+            Ok(())
+        };
+        if let Err(err) = cleanup {
+            log::warn!(
+                "Failed to remove the partially extracted {:?}: {}",
+                dest,
+                err
+            );
+        }
+    }
+    result
+}
+
+/// Removes the `created` files, symlinks and directories, the newest first so that directories are empty by then
+fn remove_created(created: &[PathBuf]) -> std::io::Result<()> {
+    for path in created.iter().rev() {
+        if path.symlink_metadata()?.is_dir() {
+            std::fs::remove_dir(path)?;
+        } else {
+            std::fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Like `create_dir_all`, adding the directories it creates to `created`
+fn create_dirs(dir: &Path, created: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if dir.exists() {
+        return Ok(());
+    }
+    if let Some(parent) = dir.parent() {
+        create_dirs(parent, created)?;
+    }
+    std::fs::create_dir(dir)?;
+    created.push(dir.to_path_buf());
+    Ok(())
+}
+
+fn extract_zip_entries(data: &[u8], dest: &Path, created: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let mut arch = zip::ZipArchive::new(std::io::Cursor::new(data))?;
+    let max_size = (data.len() as u64).saturating_mul(MAX_COMPRESSION_RATIO);
+    let mut total_size = 0u64;
+    // Writing below a symlink follows it, so the symlinks extracted so far can't be a part of later entries
+    let mut symlinks = Vec::new();
+    std::fs::create_dir_all(dest).with_context(|| format!("Failed to create {:?}", dest))?;
+    for i in 0..arch.len() {
+        let mut file = arch.by_index(i)?;
+        let name = file.name().to_string();
+        let reject = |reason: &str| {
+            anyhow::anyhow!(
+                "Refusing to extract {:?} from the runtime archive: {}",
+                name,
+                reason
+            )
+        };
+        let relative = enclosed_path(&name)
+            .ok_or_else(|| reject("it is an absolute path or leaves the install directory"))?;
+        if let Some(symlink) = relative
+            .ancestors()
+            .find(|ancestor| symlinks.iter().any(|symlink| symlink == ancestor))
+        {
+            return Err(reject(&format!(
+                "it would be written through the symlink {:?}",
+                symlink
+            )));
+        }
+        let out_path = dest.join(&relative);
+        if file.is_dir() {
+            create_dirs(&out_path, created)?;
+            continue;
+        }
+        if let Some(parent) = out_path.parent() {
+            create_dirs(parent, created)?;
+        }
+        let mut contents = (&mut file).take(max_size - total_size + 1);
+        let is_symlink = contents
+            .get_ref()
+            .unix_mode()
+            .is_some_and(|mode| mode & 0o170000 == 0o120000);
+        if is_symlink {
+            let mut target = String::new();
+            contents
+                .read_to_string(&mut target)
+                .map_err(|_| reject("invalid symlink"))?;
+            // Checking the target lexically isn't enough, since it may go through other symlinks
+            if !is_plain_relative(&target) {
+                return Err(reject(&format!(
+                    "it is a symlink to {:?}, which may be outside of the install directory",
+                    target
+                )));
+            }
+            write_symlink(&target, &out_path)?;
+            created.push(out_path);
+            symlinks.push(relative);
+            continue;
+        }
+        let is_new = !out_path.exists();
+        let mut out = std::fs::File::create(&out_path)
+            .with_context(|| format!("Failed to create {:?}", out_path))?;
+        if is_new {
+            created.push(out_path.clone());
+        }
+        total_size += std::io::copy(&mut contents, &mut out)
+            .with_context(|| format!("Failed to write {:?}", out_path))?;
+        if total_size > max_size {
+            return Err(reject(&format!(
+                "the archive decompresses to more than {} times its size",
+                MAX_COMPRESSION_RATIO
+            )));
+        }
+        #[cfg(unix)]
+        if let Some(mode) = file.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&out_path, std::fs::Permissions::from_mode(mode))?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the files extracted from the zip in `data` into `dest` are still there, with the same SHA-256
+/// checksums as in the archive
+pub(crate) fn verify_extracted(data: &[u8], dest: &Path) -> anyhow::Result<()> {
+    let mut arch = zip::ZipArchive::new(std::io::Cursor::new(data))?;
+    for i in 0..arch.len() {
+        let mut file = arch.by_index(i)?;
+        let is_symlink = file
+            .unix_mode()
+            .is_some_and(|mode| mode & 0o170000 == 0o120000);
+        if file.is_dir() || is_symlink {
+            continue;
+        }
+        let relative = enclosed_path(file.name())
+            .with_context(|| format!("Invalid path {:?} in the runtime archive", file.name()))?;
+        let path = dest.join(relative);
+        let mut expected = Sha256::new();
+        std::io::copy(&mut file, &mut expected)?;
+        let expected = expected.finalize();
+        let mut actual = Sha256::new();
+        let mut installed =
+            std::fs::File::open(&path).with_context(|| format!("{:?} is missing", path))?;
+        std::io::copy(&mut installed, &mut actual)
+            .with_context(|| format!("Failed to read {:?}", path))?;
+        let actual = actual.finalize();
+        if actual != expected {
+            anyhow::bail!(
+                "{:?} differs from the runtime archive: its SHA-256 is {:x}, expected {:x}",
+                path,
+                actual,
+                expected
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Makes the extracted runtime binary executable, since archives made on Windows don't carry Unix permissions
+#[cfg(unix)]
+pub(crate) fn set_executable(path: &Path) -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("Failed to make {:?} executable", path))
+}
+#[cfg(not(unix))]
+pub(crate) fn set_executable(_path: &Path) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// `name` as a relative path without `..`, or `None` if it would leave the directory it's extracted into
+fn enclosed_path(name: &str) -> Option<PathBuf> {
+    let name = name.replace('\\', "/");
+    // Windows drive letters aren't recognized as such on other platforms
+    if name.as_bytes().get(1) == Some(&b':') {
+        return None;
+    }
+    let mut path = PathBuf::new();
+    for component in Path::new(&name).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !path.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(path)
+}
+
+/// Whether `target` is a relative path without `..`
+fn is_plain_relative(target: &str) -> bool {
+    let target = target.replace('\\', "/");
+    target.as_bytes().get(1) != Some(&b':')
+        && Path::new(&target)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+#[cfg(unix)]
+fn write_symlink(target: &str, path: &Path) -> anyhow::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+        .with_context(|| format!("Failed to create the symlink {:?}", path))
+}
+/// Symlinks need extra privileges on Windows, so they're extracted as files holding the target, like before
+#[cfg(not(unix))]
+fn write_symlink(target: &str, path: &Path) -> anyhow::Result<()> {
+    std::fs::write(path, target).with_context(|| format!("Failed to write {:?}", path))
+}
+
+#[cfg(test)]
+use std::io::Write;
+
+#[cfg(test)]
+fn zip_with(add: impl FnOnce(&mut zip::ZipWriter<std::io::Cursor<Vec<u8>>>)) -> Vec<u8> {
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    add(&mut writer);
+    writer.finish().unwrap().into_inner()
+}
+
+#[test]
+fn test_extract_zip() {
+    let options = zip::write::FileOptions::default().unix_permissions(0o755);
+    let data = zip_with(|zip| {
+        zip.add_directory("bin/", options).unwrap();
+        zip.start_file("bin/ambient", options).unwrap();
+        zip.write_all(b"binary").unwrap();
+        zip.start_file("./docs/../README.md", options).unwrap();
+        zip.write_all(b"readme").unwrap();
+        zip.add_symlink("latest", "bin/ambient", options).unwrap();
+    });
+    let dir = tempfile::tempdir().unwrap();
+    let dest = dir.path().join("0.3.0");
+    extract_zip(&data, &dest).unwrap();
+    assert_eq!(std::fs::read(dest.join("bin/ambient")).unwrap(), b"binary");
+    assert_eq!(std::fs::read(dest.join("README.md")).unwrap(), b"readme");
+    assert_eq!(std::fs::read(dest.join("latest")).unwrap(), b"binary");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_set_executable() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let options = zip::write::FileOptions::default().unix_permissions(0o644);
+    let data = zip_with(|zip| {
+        zip.start_file("ambient", options).unwrap();
+        zip.write_all(b"binary").unwrap();
+    });
+    let dir = tempfile::tempdir().unwrap();
+    let dest = dir.path().join("0.3.0");
+    extract_zip(&data, &dest).unwrap();
+    let exe_path = dest.join("ambient");
+    let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode(&exe_path), 0o644);
+    set_executable(&exe_path).unwrap();
+    assert_eq!(mode(&exe_path), 0o755);
+}
+
+#[test]
+fn test_verify_extracted() {
+    let options = zip::write::FileOptions::default();
+    let data = zip_with(|zip| {
+        zip.start_file("bin/ambient", options).unwrap();
+        zip.write_all(b"binary").unwrap();
+        zip.add_symlink("latest", "bin/ambient", options).unwrap();
+    });
+    let dir = tempfile::tempdir().unwrap();
+    let dest = dir.path().join("0.3.0");
+    extract_zip(&data, &dest).unwrap();
+    verify_extracted(&data, &dest).unwrap();
+
+    std::fs::write(dest.join("bin/ambient"), "tampered").unwrap();
+    let err = verify_extracted(&data, &dest).unwrap_err().to_string();
+    assert!(err.contains("differs from the runtime archive"), "{}", err);
+    std::fs::remove_file(dest.join("bin/ambient")).unwrap();
+    let err = verify_extracted(&data, &dest).unwrap_err().to_string();
+    assert!(err.ends_with("is missing"), "{}", err);
+}
+
+#[test]
+fn test_extract_zip_rejects_malicious_archives() {
+    let options = zip::write::FileOptions::default();
+    let file = |name: &'static str| {
+        zip_with(move |zip| {
+            zip.start_file("ambient", options).unwrap();
+            zip.start_file(name, options).unwrap();
+            zip.write_all(b"evil").unwrap();
+        })
+    };
+    let symlink = |target: &'static str| {
+        zip_with(move |zip| {
+            zip.add_symlink("bin/link", target, options).unwrap();
+        })
+    };
+    let bomb = zip_with(|zip| {
+        zip.start_file(
+            "bomb",
+            options.compression_method(zip::CompressionMethod::Deflated),
+        )
+        .unwrap();
+        zip.write_all(&vec![0; 1024 * 1024]).unwrap();
+    });
+    let dir = tempfile::tempdir().unwrap();
+    let dest = dir.path().join("0.3.0");
+    let cases = [
+        (file("../evil"), "\"../evil\""),
+        (file("bin/../../evil"), "\"bin/../../evil\""),
+        (file("/etc/evil"), "\"/etc/evil\""),
+        (file("C:\\evil"), "\"C:\\\\evil\""),
+        (symlink("../../etc/passwd"), "\"bin/link\""),
+        (symlink("/etc/passwd"), "\"bin/link\""),
+        (symlink("../ambient"), "\"bin/link\""),
+        // Each symlink stays inside on its own, but together they lead outside
+        (
+            zip_with(|zip| {
+                zip.add_symlink("l", ".", options).unwrap();
+                zip.add_symlink("m", "l/..", options).unwrap();
+                zip.start_file("m/evil", options).unwrap();
+                zip.write_all(b"evil").unwrap();
+            }),
+            "\"m\"",
+        ),
+        (
+            zip_with(|zip| {
+                zip.add_symlink("l", ".", options).unwrap();
+                zip.add_directory("l/sub/", options).unwrap();
+            }),
+            "\"l/sub/\"",
+        ),
+        (
+            zip_with(|zip| {
+                zip.add_symlink("l", "bin", options).unwrap();
+                zip.start_file("l", options).unwrap();
+                zip.write_all(b"evil").unwrap();
+            }),
+            "\"l\"",
+        ),
+        (bomb, "\"bomb\""),
+    ];
+    for (data, entry) in cases {
+        let err = extract_zip(&data, &dest).unwrap_err().to_string();
+        assert!(
+            err.starts_with(&format!("Refusing to extract {}", entry)),
+            "{}",
+            err
+        );
+        assert!(!dest.exists());
+    }
+    assert!(!dir.path().join("evil").exists());
+
+    // An existing destination keeps what was in it, and only loses what the extraction added
+    std::fs::create_dir_all(dest.join("bin")).unwrap();
+    std::fs::write(dest.join("keep.txt"), "mine").unwrap();
+    std::fs::write(dest.join("bin/ambient"), "old").unwrap();
+    let data = zip_with(|zip| {
+        zip.start_file("bin/ambient", options).unwrap();
+        zip.write_all(b"new").unwrap();
+        zip.start_file("bin/extra", options).unwrap();
+        zip.write_all(b"new").unwrap();
+        zip.start_file("docs/guide/README.md", options).unwrap();
+        zip.write_all(b"new").unwrap();
+        zip.start_file("../evil", options).unwrap();
+        zip.write_all(b"evil").unwrap();
+    });
+    extract_zip(&data, &dest).unwrap_err();
+    assert_eq!(std::fs::read(dest.join("keep.txt")).unwrap(), b"mine");
+    assert!(dest.join("bin/ambient").exists());
+    assert!(!dest.join("bin/extra").exists());
+    assert!(!dest.join("docs").exists());
+}
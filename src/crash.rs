@@ -0,0 +1,148 @@
+use std::process::ExitStatus;
+
+/// How a process ended abnormally: killed by a signal on Unix, or an exception code on Windows
+#[derive(Debug, Clone, PartialEq)]
+pub enum Crash {
+    Signal { signal: i32, core_dumped: bool },
+    Exception { code: u32 },
+}
+impl Crash {
+    /// The crash behind `status`, or `None` if the process exited normally, even with an error code
+    #[cfg(unix)]
+    pub fn from_status(status: &ExitStatus) -> Option<Self> {
+        use std::os::unix::process::ExitStatusExt;
+        status.signal().map(|signal| Crash::Signal {
+            signal,
+            core_dumped: status.core_dumped(),
+        })
+    }
+    /// The crash behind `status`, or `None` if the process exited normally, even with an error code. Exit codes
+    /// with the NTSTATUS error severity are exceptions
+    #[cfg(not(unix))]
+    pub fn from_status(status: &ExitStatus) -> Option<Self> {
+        let code = status.code()? as u32;
+        (code >= 0xC000_0000).then_some(Crash::Exception { code })
+    }
+}
+impl std::fmt::Display for Crash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Crash::Signal {
+                signal,
+                core_dumped,
+            } => {
+                write!(f, "killed by signal {}", signal)?;
+                if let Some((name, meaning)) = describe_signal(*signal) {
+                    write!(f, " ({}, {})", name, meaning)?;
+                }
+                if *core_dumped {
+                    write!(f, ", core dumped")?;
+                }
+                Ok(())
+            }
+            Crash::Exception { code } => {
+                write!(f, "exception 0x{:08X}", code)?;
+                if let Some((name, meaning)) = describe_ntstatus(*code) {
+                    write!(f, " ({}, {})", name, meaning)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn describe_signal(signal: i32) -> Option<(&'static str, &'static str)> {
+    Some(match signal {
+        1 => ("SIGHUP", "the terminal was closed"),
+        2 => ("SIGINT", "interrupted"),
+        4 => (
+            "SIGILL",
+            "illegal instruction, the build may not support this CPU",
+        ),
+        6 => ("SIGABRT", "the runtime aborted"),
+        7 => ("SIGBUS", "bus error"),
+        8 => ("SIGFPE", "arithmetic error"),
+        9 => ("SIGKILL", "killed, possibly by the out-of-memory killer"),
+        11 => ("SIGSEGV", "segmentation fault"),
+        15 => ("SIGTERM", "terminated"),
+        _ => return None,
+    })
+}
+
+fn describe_ntstatus(code: u32) -> Option<(&'static str, &'static str)> {
+    Some(match code {
+        0xC000_0005 => ("STATUS_ACCESS_VIOLATION", "the runtime accessed invalid memory"),
+        0xC000_001D => (
+            "STATUS_ILLEGAL_INSTRUCTION",
+            "illegal instruction, the build may not support this CPU",
+        ),
+        0xC000_00FD => ("STATUS_STACK_OVERFLOW", "the runtime overflowed its stack"),
+        0xC000_0135 => (
+            "STATUS_DLL_NOT_FOUND",
+            "a DLL the runtime needs is missing, e.g. the Visual C++ redistributable or a graphics driver DLL",
+        ),
+        0xC000_0139 => (
+            "STATUS_ENTRYPOINT_NOT_FOUND",
+            "a DLL the runtime needs is too old",
+        ),
+        0xC000_0142 => (
+            "STATUS_DLL_INIT_FAILED",
+            "a DLL the runtime needs failed to initialize",
+        ),
+        0xC000_013A => ("STATUS_CONTROL_C_EXIT", "interrupted with Ctrl+C"),
+        0xC000_0409 => (
+            "STATUS_STACK_BUFFER_OVERRUN",
+            "the runtime requested a fail-fast exit, usually after a panic or a detected memory corruption",
+        ),
+        _ => return None,
+    })
+}
+
+#[test]
+fn test_crash_display() {
+    assert_eq!(
+        Crash::Signal {
+            signal: 11,
+            core_dumped: true
+        }
+        .to_string(),
+        "killed by signal 11 (SIGSEGV, segmentation fault), core dumped"
+    );
+    assert_eq!(
+        Crash::Signal {
+            signal: 64,
+            core_dumped: false
+        }
+        .to_string(),
+        "killed by signal 64"
+    );
+    assert_eq!(
+        Crash::Exception { code: 0xC0000135 }.to_string(),
+        "exception 0xC0000135 (STATUS_DLL_NOT_FOUND, a DLL the runtime needs is missing, \
+         e.g. the Visual C++ redistributable or a graphics driver DLL)"
+    );
+    assert_eq!(
+        Crash::Exception { code: 0xC0000001 }.to_string(),
+        "exception 0xC0000001"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_crash_from_status() {
+    let status = |script| {
+        std::process::Command::new("sh")
+            .arg("-c")
+            .arg(script)
+            .status()
+            .unwrap()
+    };
+    assert_eq!(Crash::from_status(&status("exit 3")), None);
+    assert_eq!(
+        Crash::from_status(&status("kill -TERM $$")),
+        Some(Crash::Signal {
+            signal: 15,
+            core_dumped: false
+        })
+    );
+}
@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Where the deprecations are published in the bucket, next to the builds
+pub const DEPRECATIONS_PATH: &str = "ambient-builds/deprecations.json";
+
+/// Why the team steers people away from a published version
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Deprecation {
+    pub reason: String,
+    /// Yanked versions are fundamentally broken: they're only installed with `--allow-yanked`, and only resolved
+    /// when no other version satisfies a requirement
+    #[serde(default)]
+    pub yanked: bool,
+}
+impl std::fmt::Display for Deprecation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.yanked {
+            write!(f, "yanked: {}", self.reason)
+        } else {
+            write!(f, "deprecated: {}", self.reason)
+        }
+    }
+}
+
+/// The published `deprecations.json`, mapping versions to their deprecation, e.g.
+/// `{"0.3.2-nightly-2023-10-05": {"reason": "crashes on startup", "yanked": true}}`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Deprecations(pub BTreeMap<semver::Version, Deprecation>);
+impl Deprecations {
+    pub fn get(&self, version: &semver::Version) -> Option<&Deprecation> {
+        self.0.get(version)
+    }
+    pub fn is_yanked(&self, version: &semver::Version) -> bool {
+        self.get(version).is_some_and(|d| d.yanked)
+    }
+    pub fn yanked(&self) -> Vec<semver::Version> {
+        self.0
+            .iter()
+            .filter(|(_, deprecation)| deprecation.yanked)
+            .map(|(version, _)| version.clone())
+            .collect()
+    }
+}
+
+#[test]
+fn test_deprecations() {
+    let deprecations: Deprecations = serde_json::from_str(
+        r#"{
+            "0.3.2-nightly-2023-10-05": {"reason": "crashes on startup", "yanked": true},
+            "0.3.0": {"reason": "use 0.3.1, which fixes audio"}
+        }"#,
+    )
+    .unwrap();
+    let version = |v| semver::Version::parse(v).unwrap();
+    assert!(deprecations.is_yanked(&version("0.3.2-nightly-2023-10-05")));
+    assert!(!deprecations.is_yanked(&version("0.3.0")));
+    assert!(!deprecations.is_yanked(&version("0.3.1")));
+    assert_eq!(deprecations.yanked(), [version("0.3.2-nightly-2023-10-05")]);
+    assert_eq!(
+        deprecations.get(&version("0.3.0")).unwrap().to_string(),
+        "deprecated: use 0.3.1, which fixes audio"
+    );
+    assert_eq!(
+        deprecations
+            .get(&version("0.3.2-nightly-2023-10-05"))
+            .unwrap()
+            .to_string(),
+        "yanked: crashes on startup"
+    );
+}
@@ -0,0 +1,237 @@
+use ambient::{
+    environment::{dir_size, Os, PackagePath},
+    find_broken_runtime_dirs, glibc, http, list_installed_runtimes, matches_exact,
+    quarantine::{gatekeeper_help, is_quarantined},
+    resolve_runtime,
+    versions::bucket_list_url,
+    RuntimeVersion, Settings,
+};
+use anyhow::Context;
+use colored::Colorize;
+
+/// Runs a series of checks and reports each of them; returns whether they all passed
+pub fn run_doctor(settings: &Settings, package_path: Option<&PackagePath>) -> bool {
+    let mut all_ok = true;
+    let mut report = |name: &str, result| all_ok &= report_check(name, result);
+
+    report(
+        "Settings",
+        settings.validate().and_then(|warnings| {
+            if !warnings.is_empty() {
+                anyhow::bail!("{}", warnings.join("; "));
+            }
+            Ok(match &settings.profile {
+                Some(profile) => format!("no problems found, using profile `{}`", profile),
+                None => "no problems found".to_string(),
+            })
+        }),
+    );
+    let proxies = http::proxy_env_vars();
+    report(
+        "Proxy",
+        Ok(if proxies.is_empty() {
+            "no proxy configured".to_string()
+        } else {
+            proxies
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join(", ")
+        }),
+    );
+    report(
+        "CA bundle",
+        http::probe_ca_bundle(settings).map(|bundle| match bundle {
+            Some((path, count)) => format!("{} certificates loaded from {:?}", count, path),
+            None => "using the built-in root certificates".to_string(),
+        }),
+    );
+    let list_url = bucket_list_url(settings);
+    let host = url::Url::parse(&list_url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.to_string()))
+        .unwrap_or_else(|| list_url.clone());
+    // With a proxy, the proxy resolves the host for us
+    if proxies.is_empty() {
+        report(
+            "DNS",
+            http::probe_dns(&host).map(|ip| format!("{} resolves to {}", host, ip)),
+        );
+    }
+    report(
+        "HTTPS",
+        http::probe_https(settings, &list_url)
+            .map(|status| format!("{} responded with status {}", host, status)),
+    );
+
+    report("Runtimes", check_broken_runtime_dirs(settings));
+    if cfg!(target_os = "macos") {
+        report("Quarantine", check_quarantine(settings));
+    }
+    if cfg!(target_os = "linux") {
+        report("glibc", check_glibc(settings));
+    }
+
+    if let Some(package_path) = package_path {
+        report(
+            "Package",
+            package_path.ambient_toml().get_content().and_then(|toml| {
+                let package = toml.context("No ambient.toml found")?.package;
+                package.validate()?;
+                Ok(package.summary())
+            }),
+        );
+    }
+
+    all_ok
+}
+
+/// Checks that ambient.toml and Cargo.toml of the package ask for the same runtime version, and that the version
+/// can be resolved; returns whether they do
+pub fn run_check_project(settings: &Settings, package_path: &PackagePath) -> bool {
+    let requirement = match package_path.ambient_toml().get_content() {
+        Ok(Some(toml)) => toml.package.ambient_version,
+        Ok(None) => return report_check("ambient.toml", Err(anyhow::anyhow!("not found"))),
+        Err(err) => return report_check("ambient.toml", Err(err)),
+    };
+    let mut all_ok = report_check(
+        "ambient.toml",
+        Ok(match &requirement {
+            Some(requirement) => format!("ambient_version = \"{}\"", requirement),
+            None => "no ambient_version, so the default runtime is used".to_string(),
+        }),
+    );
+    all_ok &= report_check(
+        "Cargo.toml",
+        package_path
+            .cargo_toml()
+            .ambient_api_version()
+            .and_then(|api| {
+                let api = api.context("not found")?;
+                match &requirement {
+                    Some(requirement) if !matches_exact(requirement, &api) => anyhow::bail!(
+                        "ambient_api {} doesn't match ambient_version = \"{}\" in ambient.toml. \
+                         Run `ambient runtime update-local --fix` to set it to the version the package resolves to",
+                        api,
+                        requirement
+                    ),
+                    _ => Ok(format!("ambient_api {}", api)),
+                }
+            }),
+    );
+    all_ok &= report_check(
+        "Resolution",
+        resolve_runtime(Some(&package_path.0), settings)
+            .map(|runtime| format!("resolves to runtime {}", runtime)),
+    );
+    all_ok
+}
+
+/// Removes the leftovers of failed installs from the runtime stores, or only lists them with `dry_run`
+pub fn fix_broken_runtime_dirs(settings: &Settings, dry_run: bool) -> anyhow::Result<()> {
+    let broken = find_broken_runtime_dirs(settings)?;
+    let mut total = 0;
+    for dir in &broken {
+        let size = if dir.path.is_dir() {
+            dir_size(&dir.path)?
+        } else {
+            std::fs::metadata(&dir.path)?.len()
+        };
+        total += size;
+        if dry_run {
+            println!("Would remove {} ({})", dir.path.display(), dir.reason);
+        } else {
+            if dir.path.is_dir() {
+                std::fs::remove_dir_all(&dir.path)
+            } else {
+                std::fs::remove_file(&dir.path)
+            }
+            .with_context(|| format!("Failed to remove {:?}", dir.path))?;
+            println!("Removed {} ({})", dir.path.display(), dir.reason);
+        }
+    }
+    let action = if dry_run { "Would remove" } else { "Removed" };
+    println!(
+        "{} {} leftovers of failed installs, {:.1} MB",
+        action,
+        broken.len(),
+        total as f64 / (1024. * 1024.)
+    );
+    Ok(())
+}
+
+/// Prints the outcome of a check; returns whether it passed
+fn report_check(name: &str, result: anyhow::Result<String>) -> bool {
+    match result {
+        Ok(details) => {
+            println!("{} {}: {}", "ok".green().bold(), name, details);
+            true
+        }
+        Err(err) => {
+            println!("{} {}: {}", "failed".red().bold(), name, err);
+            false
+        }
+    }
+}
+
+/// Interrupted installs leave directories behind that look installed, but have no usable runtime binary
+fn check_broken_runtime_dirs(settings: &Settings) -> anyhow::Result<String> {
+    let broken = find_broken_runtime_dirs(settings)?;
+    if !broken.is_empty() {
+        anyhow::bail!(
+            "found leftovers of failed installs: {}. Run `ambient runtime doctor --fix` to remove them",
+            broken
+                .iter()
+                .map(|dir| format!("{} ({})", dir.path.display(), dir.reason))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(format!(
+        "{} installed, no leftovers of failed installs",
+        list_installed_runtimes(settings)?.len()
+    ))
+}
+
+/// The default runtime fails to start when it needs a newer glibc than the system has. Skipped when the system's
+/// glibc version is unknown, e.g. with musl or without `ldd`, like the check before running a runtime
+fn check_glibc(settings: &Settings) -> anyhow::Result<String> {
+    let system = match glibc::system_glibc() {
+        Ok(system) => system,
+        Err(err) => return Ok(format!("skipped: {:#}", err)),
+    };
+    let Some(default) = &settings.default_runtime else {
+        return Ok(format!("system glibc {}.{}", system.0, system.1));
+    };
+    let runtime = RuntimeVersion::without_builds(default.clone());
+    if !runtime.is_installed(settings)? {
+        return Ok(format!(
+            "system glibc {}.{}; the default runtime {} is not installed",
+            system.0, system.1, default
+        ));
+    }
+    glibc::ensure_glibc_compatible(&runtime.exe_path(settings)?)?;
+    Ok(format!(
+        "system glibc {}.{} can run the default runtime {}",
+        system.0, system.1, default
+    ))
+}
+
+/// Runtimes that still carry the quarantine attribute may be blocked by Gatekeeper
+fn check_quarantine(settings: &Settings) -> anyhow::Result<String> {
+    let runtimes = list_installed_runtimes(settings)?;
+    for runtime in &runtimes {
+        let exe_path = runtime.dir.join(Os::current().ambient_bin_name());
+        if is_quarantined(&exe_path)? {
+            anyhow::bail!(
+                "runtime {} is quarantined. {}",
+                runtime.version,
+                gatekeeper_help(&exe_path)
+            );
+        }
+    }
+    Ok(format!(
+        "none of the {} installed runtimes are quarantined",
+        runtimes.len()
+    ))
+}
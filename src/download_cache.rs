@@ -0,0 +1,153 @@
+use crate::{environment::download_cache_dir, environment::Os, Settings};
+use anyhow::Context;
+use base64::Engine;
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+pub const DEFAULT_DOWNLOAD_CACHE_MAX_MB: u64 = 2048;
+
+/// Downloaded runtime zips, kept so that reinstalling a version doesn't download it again.
+/// Entries are keyed by version, OS and the MD5 checksum reported by the server, and the least recently used
+/// ones are removed when the cache grows beyond its size cap.
+pub struct DownloadCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+impl DownloadCache {
+    pub fn new(settings: &Settings) -> anyhow::Result<Self> {
+        Ok(Self {
+            dir: download_cache_dir()?,
+            max_bytes: settings
+                .download_cache_max_mb
+                .unwrap_or(DEFAULT_DOWNLOAD_CACHE_MAX_MB)
+                .saturating_mul(1024 * 1024),
+        })
+    }
+    /// `md5` is base64 encoded, as Google Storage reports it
+    fn path(&self, version: &semver::Version, os: Os, md5: &str) -> anyhow::Result<PathBuf> {
+        let md5 = base64::engine::general_purpose::STANDARD
+            .decode(md5)
+            .with_context(|| format!("Invalid MD5 checksum {:?}", md5))?;
+        let hex = md5.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        Ok(self.dir.join(format!("{}-{}-{}.zip", version, os, hex)))
+    }
+    /// The cached zip, if there is one and it still matches its checksum
+    pub fn get(&self, version: &semver::Version, os: Os, md5: &str) -> Option<Vec<u8>> {
+        let path = self.path(version, os, md5).ok()?;
+        let data = std::fs::read(&path).ok()?;
+        if !matches_md5(&data, md5) {
+            log::warn!("Removing corrupted download {:?} from the cache", path);
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+        // The modification time is what eviction goes by
+        if let Err(err) = std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+        {
+            log::info!("Failed to mark {:?} as used: {}", path, err);
+        }
+        Some(data)
+    }
+    pub fn put(
+        &self,
+        version: &semver::Version,
+        os: Os,
+        md5: &str,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {:?}", self.dir))?;
+        let path = self.path(version, os, md5)?;
+        std::fs::write(&path, data).with_context(|| format!("Failed to write {:?}", path))?;
+        self.evict()
+    }
+    /// Removes the least recently used zips until the cache fits its size cap
+    fn evict(&self) -> anyhow::Result<()> {
+        let mut entries = self.entries()?;
+        entries.sort_by_key(|(_, _, used)| *used);
+        let mut total = entries.iter().map(|(_, size, _)| size).sum::<u64>();
+        for (path, size, _) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            std::fs::remove_file(&path).with_context(|| format!("Failed to remove {:?}", path))?;
+            total -= size;
+        }
+        Ok(())
+    }
+    /// The cached zips, with their sizes and when they were last used
+    fn entries(&self) -> anyhow::Result<Vec<(PathBuf, u64, SystemTime)>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                entries.push((entry.path(), metadata.len(), metadata.modified()?));
+            }
+        }
+        Ok(entries)
+    }
+    /// The total size of the cached zips in bytes
+    pub fn size(&self) -> anyhow::Result<u64> {
+        Ok(self.entries()?.iter().map(|(_, size, _)| size).sum())
+    }
+    /// Removes all cached zips and returns how many bytes that freed
+    pub fn clean(&self) -> anyhow::Result<u64> {
+        let size = self.size()?;
+        if self.dir.exists() {
+            std::fs::remove_dir_all(&self.dir)
+                .with_context(|| format!("Failed to remove {:?}", self.dir))?;
+        }
+        Ok(size)
+    }
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+/// Whether `data` has the base64 encoded MD5 checksum `md5`
+pub fn matches_md5(data: &[u8], md5: &str) -> bool {
+    base64::engine::general_purpose::STANDARD.encode(md5::compute(data).0) == md5
+}
+
+#[test]
+fn test_download_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = DownloadCache {
+        dir: dir.path().join("downloads"),
+        max_bytes: 10,
+    };
+    let version = |v: &str| semver::Version::parse(v).unwrap();
+    let md5 = |data: &[u8]| base64::engine::general_purpose::STANDARD.encode(md5::compute(data).0);
+    let (a, b) = (b"aaaaaa".as_slice(), b"bbbbbb".as_slice());
+
+    assert!(cache.get(&version("0.3.0"), Os::Linux, &md5(a)).is_none());
+    cache.put(&version("0.3.0"), Os::Linux, &md5(a), a).unwrap();
+    assert_eq!(
+        cache.get(&version("0.3.0"), Os::Linux, &md5(a)).as_deref(),
+        Some(a)
+    );
+    assert!(cache.get(&version("0.3.0"), Os::Windows, &md5(a)).is_none());
+
+    // Both don't fit, so the least recently used one is evicted
+    cache.put(&version("0.3.1"), Os::Linux, &md5(b), b).unwrap();
+    assert!(cache.get(&version("0.3.0"), Os::Linux, &md5(a)).is_none());
+    assert_eq!(cache.size().unwrap(), 6);
+
+    // A zip that no longer matches its checksum is dropped
+    let path = cache.path(&version("0.3.1"), Os::Linux, &md5(b)).unwrap();
+    std::fs::write(path, "corrupted").unwrap();
+    assert!(cache.get(&version("0.3.1"), Os::Linux, &md5(b)).is_none());
+    assert_eq!(cache.size().unwrap(), 0);
+
+    cache.put(&version("0.3.1"), Os::Linux, &md5(b), b).unwrap();
+    assert_eq!(cache.clean().unwrap(), 6);
+    assert_eq!(cache.size().unwrap(), 0);
+}
@@ -1,19 +1,34 @@
 use crate::ambient_toml::{set_ambient_toml_runtime_version, AmbientToml};
 use anyhow::Context;
 use directories::ProjectDirs;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
 use std::{
     path::{Path, PathBuf},
     str::FromStr,
 };
-use toml_edit::{value, Document, InlineTable};
+use tempfile::NamedTempFile;
+use toml_edit::{value, Document, InlineTable, Item, Table};
 
 pub fn app_dir() -> anyhow::Result<ProjectDirs> {
-    ProjectDirs::from("com", "Ambient", "AmbientCli")
-        .context("Failed to created project dirs")
+    ProjectDirs::from("com", "Ambient", "AmbientCli").context("Failed to created project dirs")
 }
 pub fn runtimes_dir() -> anyhow::Result<PathBuf> {
     Ok(app_dir()?.data_dir().join("runtimes"))
 }
+pub fn version_cache_path() -> anyhow::Result<PathBuf> {
+    Ok(app_dir()?.data_dir().join("version-cache.json"))
+}
+pub fn download_cache_dir() -> anyhow::Result<PathBuf> {
+    Ok(app_dir()?.cache_dir().join("downloads"))
+}
+pub fn logs_dir() -> anyhow::Result<PathBuf> {
+    Ok(app_dir()?.data_dir().join("logs"))
+}
+pub fn history_path() -> anyhow::Result<PathBuf> {
+    Ok(app_dir()?.data_dir().join("default-runtime-history.json"))
+}
 pub fn settings_dir() -> anyhow::Result<PathBuf> {
     Ok(app_dir()?.config_dir().to_path_buf())
 }
@@ -21,10 +36,40 @@ pub fn settings_path() -> anyhow::Result<PathBuf> {
     Ok(settings_dir()?.join("settings.json"))
 }
 
+/// Where an installed runtime lives: the system-wide shared dir, the per-user dir, or a locally built binary
+/// linked with `runtime link`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuntimeStore {
+    Shared,
+    User,
+    Linked,
+}
+impl std::fmt::Display for RuntimeStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeStore::Shared => write!(f, "shared"),
+            RuntimeStore::User => write!(f, "user"),
+            RuntimeStore::Linked => write!(f, "linked"),
+        }
+    }
+}
+
+/// Checks that we can create files in `dir`, creating it if needed
+pub fn is_dir_writable(dir: &Path) -> bool {
+    if std::fs::create_dir_all(dir).is_err() {
+        return false;
+    }
+    let probe = dir.join(".ambient-write-test");
+    let writable = std::fs::write(&probe, b"").is_ok();
+    let _ = std::fs::remove_file(&probe);
+    writable
+}
+
 pub struct PackagePath(pub PathBuf);
 impl PackagePath {
-    pub fn get(args: &[String]) -> Option<Self> {
-        if let Some(pp) = Self::from_args(args) {
+    /// The package at `path_arg` if it has an ambient.toml, else the one in the current directory
+    pub fn get(path_arg: Option<&str>) -> Option<Self> {
+        if let Some(pp) = path_arg.and_then(Self::from_arg) {
             return Some(pp);
         } else {
             let local = std::env::current_dir().unwrap();
@@ -34,30 +79,132 @@ impl PackagePath {
         }
         None
     }
-    fn from_args(args: &[String]) -> Option<Self> {
-        let maybe_path = args.get(1)?;
+    fn from_arg(maybe_path: &str) -> Option<Self> {
         if maybe_path.starts_with("--") {
             return None;
         }
-        let dir = Path::new(&maybe_path);
+        let dir = Path::new(maybe_path);
         if dir.join("ambient.toml").exists() {
             Some(Self(dir.to_path_buf()))
         } else {
             None
         }
     }
+    /// Walks up from `start` to the ambient.toml with a `[workspace]` table. Stops at a package's ambient.toml above
+    /// `start`, since packages don't nest in other packages
+    pub fn find_workspace_root(start: &Path) -> Option<PackagePath> {
+        for dir in start.ancestors() {
+            let package_path = PackagePath(dir.to_path_buf());
+            if !package_path.ambient_toml().0.exists() {
+                continue;
+            }
+            if package_path.is_workspace_root() {
+                return Some(package_path);
+            }
+            if dir != start {
+                return None;
+            }
+        }
+        None
+    }
+    /// Whether ambient.toml has a `[workspace]` table, as opposed to being a standalone package
+    pub fn is_workspace_root(&self) -> bool {
+        std::fs::read_to_string(self.ambient_toml().0)
+            .ok()
+            .and_then(|toml| toml.parse::<toml::Table>().ok())
+            .is_some_and(|toml| toml.contains_key("workspace"))
+    }
     pub fn ambient_toml(&self) -> AmbientTomlPath {
         AmbientTomlPath(self.0.join("ambient.toml"))
     }
     pub fn cargo_toml(&self) -> CargoTomlPath {
         CargoTomlPath(self.0.join("Cargo.toml"))
     }
-    pub fn set_runtime(&self, version: &semver::Version) -> anyhow::Result<()> {
-        self.ambient_toml().set_runtime(version)?;
-        self.cargo_toml().set_ambient_api(version)?;
-        Ok(())
+    /// The package name from ambient.toml, if it has one and can be read
+    pub fn package_name(&self) -> Option<String> {
+        self.ambient_toml().get_content().ok()??.package.name
+    }
+    /// Updates both ambient.toml and Cargo.toml, or neither of them. Cargo.toml is left alone when ambient_api
+    /// points to a local path, unless `force_remote` is set. With `update_cargo_lock`, `cargo update` runs after both
+    /// files are written, so when it fails they stay updated
+    pub fn set_runtime(
+        &self,
+        version: &semver::Version,
+        options: &SetRuntimeOptions,
+    ) -> anyhow::Result<RuntimeUpdate> {
+        let ambient_toml = self.ambient_toml();
+        let cargo_toml = self.cargo_toml();
+        let mut files = vec![(
+            ambient_toml.0.as_path(),
+            ambient_toml.with_runtime(version)?,
+        )];
+        let spec = match &options.ambient_api_branch {
+            Some(branch) => AmbientApiSpec::GitBranch {
+                repo: AMBIENT_REPO.to_string(),
+                branch: branch.clone(),
+            },
+            None => AmbientApiSpec::for_version(version),
+        };
+        let cargo_toml_content = cargo_toml.with_ambient_api(&spec, options.force_remote)?;
+        let cargo_toml_updated = cargo_toml_content.is_some();
+        if let Some(content) = cargo_toml_content {
+            files.push((cargo_toml.0.as_path(), content));
+        }
+        write_files_transactionally(&files)?;
+        let cargo_update_output = if cargo_toml_updated && options.update_cargo_lock {
+            self.update_cargo_lock()?
+        } else {
+            None
+        };
+        Ok(RuntimeUpdate {
+            cargo_toml_updated,
+            cargo_update_output,
+        })
+    }
+    /// Runs `cargo update --package ambient_api` if the package has a Cargo.lock, returning cargo's output
+    pub fn update_cargo_lock(&self) -> anyhow::Result<Option<String>> {
+        if !self.0.join("Cargo.lock").exists() {
+            return Ok(None);
+        }
+        let output = std::process::Command::new("cargo")
+            .args(["update", "--package", "ambient_api"])
+            .current_dir(&self.0)
+            .output()
+            .context("Failed to run `cargo update --package ambient_api`")?;
+        let text = format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        if !output.status.success() {
+            anyhow::bail!(
+                "`cargo update --package ambient_api` failed in {:?}:\n{}",
+                self.0,
+                text.trim_end()
+            );
+        }
+        Ok(Some(text))
     }
 }
+
+/// How `PackagePath::set_runtime` updates a package
+#[derive(Debug, Clone, Default)]
+pub struct SetRuntimeOptions {
+    /// Update ambient_api in Cargo.toml even when it points to a local path
+    pub force_remote: bool,
+    /// Refresh ambient_api in Cargo.lock with `cargo update` after updating Cargo.toml
+    pub update_cargo_lock: bool,
+    /// Point ambient_api at this branch of the Ambient repository instead of the version, e.g. `main`
+    pub ambient_api_branch: Option<String>,
+}
+
+/// What `PackagePath::set_runtime` changed besides ambient.toml
+#[derive(Debug, Clone)]
+pub struct RuntimeUpdate {
+    pub cargo_toml_updated: bool,
+    /// The output of `cargo update`, if Cargo.lock was updated
+    pub cargo_update_output: Option<String>,
+}
 pub struct AmbientTomlPath(pub PathBuf);
 impl AmbientTomlPath {
     pub fn get_content(&self) -> anyhow::Result<Option<AmbientToml>> {
@@ -67,14 +214,13 @@ impl AmbientTomlPath {
             Ok(None)
         }
     }
-    pub fn set_runtime(&self, version: &semver::Version) -> anyhow::Result<()> {
+    /// The content of ambient.toml with the runtime version set to `version`
+    pub fn with_runtime(&self, version: &semver::Version) -> anyhow::Result<String> {
         if self.0.exists() {
-            set_ambient_toml_runtime_version(&self.0, &format!("{}", version))?;
-            println!(
-                "Runtime version set to ambient_version=\"{}\" in ambient.toml",
-                version
-            );
-            Ok(())
+            let toml = std::fs::read_to_string(&self.0).context("Failed to read ambient.toml")?;
+            let mut doc = toml.parse::<Document>().context("Invalid ambient.toml")?;
+            set_ambient_toml_runtime_version(&mut doc, &format!("{}", version));
+            Ok(doc.to_string())
         } else {
             anyhow::bail!("No ambient.toml found at path {:?}", self.0);
         }
@@ -82,37 +228,407 @@ impl AmbientTomlPath {
 }
 pub struct CargoTomlPath(pub PathBuf);
 impl CargoTomlPath {
-    pub fn set_ambient_api(&self, version: &semver::Version) -> anyhow::Result<()> {
+    /// The content of Cargo.toml with the ambient_api dependency set to `spec`, or `None` if it points to a
+    /// local path and `force_remote` isn't set. Fails if the dependency is inherited from the workspace, since it
+    /// has to be changed in the workspace's Cargo.toml
+    pub fn with_ambient_api(
+        &self,
+        spec: &AmbientApiSpec,
+        force_remote: bool,
+    ) -> anyhow::Result<Option<String>> {
         if self.0.exists() {
             let toml = std::fs::read_to_string(&self.0).context("Failed to read Cargo.toml")?;
             let mut doc = toml.parse::<Document>().context("Invalid Cargo.toml")?;
-            set_cargo_toml_ambient_api(&mut doc, version);
-            std::fs::write(&self.0, doc.to_string())?;
-            println!(
-                "Runtime version set to ambient_version=\"{}\" in Cargo.toml",
-                version
-            );
-            Ok(())
+            let updated = set_cargo_toml_ambient_api(&mut doc, spec, force_remote)?;
+            if !updated && !cargo_toml_ambient_api_is_local(&doc) {
+                match self.workspace_cargo_toml() {
+                    Some(workspace) => anyhow::bail!(
+                        "ambient_api in {:?} is inherited from the workspace; set it in [workspace.dependencies] of {:?}",
+                        self.0,
+                        workspace.0
+                    ),
+                    None => anyhow::bail!(
+                        "ambient_api in {:?} is inherited from the workspace; set it in [workspace.dependencies] of the workspace's Cargo.toml",
+                        self.0
+                    ),
+                }
+            }
+            Ok(updated.then(|| doc.to_string()))
         } else {
-            anyhow::bail!("No ambient.toml found at path {:?}", self.0);
+            anyhow::bail!("No Cargo.toml found at path {:?}", self.0);
+        }
+    }
+    /// The closest Cargo.toml above this one with a `[workspace]` table
+    fn workspace_cargo_toml(&self) -> Option<CargoTomlPath> {
+        self.0.parent()?.ancestors().skip(1).find_map(|dir| {
+            let path = dir.join("Cargo.toml");
+            std::fs::read_to_string(&path)
+                .ok()?
+                .parse::<toml::Table>()
+                .ok()?
+                .contains_key("workspace")
+                .then_some(CargoTomlPath(path))
+        })
+    }
+    /// The version of the ambient_api dependency, if Cargo.toml exists
+    pub fn ambient_api_version(&self) -> anyhow::Result<Option<semver::Version>> {
+        if !self.0.exists() {
+            return Ok(None);
+        }
+        let toml = std::fs::read_to_string(&self.0).context("Failed to read Cargo.toml")?;
+        let doc = toml.parse::<Document>().context("Invalid Cargo.toml")?;
+        cargo_toml_ambient_api(&doc).map(Some)
+    }
+}
+
+/// Writes every file or none of them. The new contents are first written to temp files next to their targets,
+/// which are then renamed over the targets. If a rename fails, the files already replaced are restored from
+/// backups of their original contents.
+fn write_files_transactionally(files: &[(&Path, String)]) -> anyhow::Result<()> {
+    let none_updated = || {
+        format!(
+            "None of {} were modified",
+            files
+                .iter()
+                .map(|(path, _)| format!("{:?}", path))
+                .join(", ")
+        )
+    };
+    let backups = files
+        .iter()
+        .map(|(path, _)| std::fs::read(path).with_context(|| format!("Failed to read {:?}", path)))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .with_context(none_updated)?;
+    let temp_files = files
+        .iter()
+        .map(|(path, content)| temp_file_next_to(path, content))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .with_context(none_updated)?;
+    for (i, temp_file) in temp_files.into_iter().enumerate() {
+        let (path, _) = files[i];
+        if let Err(err) = temp_file.persist(path) {
+            let mut message = format!("Failed to write {:?}.", path);
+            for ((restore_path, _), backup) in files[..i].iter().zip(&backups) {
+                match std::fs::write(restore_path, backup) {
+                    Ok(()) => message += &format!(" {:?} was restored.", restore_path),
+                    Err(err) => {
+                        message += &format!(
+                            " {:?} was updated but could not be restored ({}).",
+                            restore_path, err
+                        )
+                    }
+                }
+            }
+            for (skipped_path, _) in &files[i + 1..] {
+                message += &format!(" {:?} was not modified.", skipped_path);
+            }
+            return Err(anyhow::Error::new(err.error).context(message));
         }
     }
+    Ok(())
 }
-fn set_cargo_toml_ambient_api(doc: &mut toml_edit::Document, version: &semver::Version) {
-    let rec = &mut doc["dependencies"]["ambient_api"];
-    if version.pre.is_empty() {
-        *rec = value(format!("{}", version));
-    } else {
-        let mut table = InlineTable::default();
-        table.insert(
-            "git",
-            value("https://github.com/AmbientRun/Ambient.git")
-                .into_value()
-                .unwrap(),
+
+/// Replaces `path` with `content` by renaming a temp file over it, so that readers never see a partial write.
+/// If the rename fails, falls back to copying the temp file over `path`, which is not atomic
+pub fn write_file_atomically(path: &Path, content: &str) -> anyhow::Result<()> {
+    if let Err(err) = temp_file_next_to(path, content)?.persist(path) {
+        log::warn!(
+            "Failed to rename a temp file over {:?} ({}); copying it instead",
+            path,
+            err.error
         );
-        table.insert("tag", value(format!("v{}", version)).into_value().unwrap());
-        *rec = value(table);
+        std::fs::copy(err.file.path(), path)
+            .with_context(|| format!("Failed to write {:?}", path))?;
     }
+    Ok(())
+}
+
+/// Takes an exclusive lock on `<path>.lock`, waiting for other processes holding it. The lock is released when
+/// the returned file is dropped
+pub fn lock_file(path: &Path) -> anyhow::Result<std::fs::File> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("Failed to open {:?}", lock_path))?;
+    fs2::FileExt::lock_exclusive(&file)
+        .with_context(|| format!("Failed to lock {:?}", lock_path))?;
+    Ok(file)
+}
+
+/// The total size of the files in `dir` and its subdirectories
+pub fn dir_size(dir: &Path) -> anyhow::Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Failed to read {:?}", dir))? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
+/// A temp file with `content` in the same directory as `path`, so that it can be renamed over it
+fn temp_file_next_to(path: &Path, content: &str) -> anyhow::Result<NamedTempFile> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let mut temp_file = NamedTempFile::new_in(dir)?;
+    temp_file.write_all(content.as_bytes())?;
+    temp_file.as_file().sync_all()?;
+    Ok(temp_file)
+}
+
+#[test]
+fn test_write_files_transactionally() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = dir.path().join("a.toml");
+    let b = dir.path().join("b.toml");
+    std::fs::write(&a, "a = 1").unwrap();
+    std::fs::write(&b, "b = 1").unwrap();
+    write_files_transactionally(&[(&a, "a = 2".to_string()), (&b, "b = 2".to_string())]).unwrap();
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), "a = 2");
+    assert_eq!(std::fs::read_to_string(&b).unwrap(), "b = 2");
+
+    let missing = dir.path().join("missing").join("c.toml");
+    assert!(write_files_transactionally(&[
+        (&a, "a = 3".to_string()),
+        (&missing, "c = 3".to_string())
+    ])
+    .is_err());
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), "a = 2");
+}
+
+#[test]
+fn test_set_runtime_skips_cargo_update() {
+    let dir = tempfile::tempdir().unwrap();
+    let package = PackagePath(dir.path().to_path_buf());
+    std::fs::write(
+        package.ambient_toml().0,
+        "[package]\nid = \"game\"\nambient_version = \"0.3.0\"\n",
+    )
+    .unwrap();
+    std::fs::write(
+        package.cargo_toml().0,
+        "[dependencies]\nambient_api = \"0.3.0\"\n",
+    )
+    .unwrap();
+    let version = semver::Version::parse("0.3.1").unwrap();
+    let options = SetRuntimeOptions {
+        update_cargo_lock: true,
+        ..Default::default()
+    };
+    // Without a Cargo.lock there's nothing for cargo to update
+    let update = package.set_runtime(&version, &options).unwrap();
+    assert!(update.cargo_toml_updated);
+    assert_eq!(update.cargo_update_output, None);
+
+    // Nor when Cargo.toml is left alone
+    std::fs::write(dir.path().join("Cargo.lock"), "").unwrap();
+    std::fs::write(
+        package.cargo_toml().0,
+        "[dependencies]\nambient_api = { path = \"../api\" }\n",
+    )
+    .unwrap();
+    let update = package.set_runtime(&version, &options).unwrap();
+    assert!(!update.cargo_toml_updated);
+    assert_eq!(update.cargo_update_output, None);
+}
+
+pub const AMBIENT_REPO: &str = "https://github.com/AmbientRun/Ambient.git";
+
+/// What the `ambient_api` dependency in Cargo.toml is set to
+#[derive(Debug, Clone, PartialEq)]
+pub enum AmbientApiSpec {
+    /// A release on crates.io
+    Version(semver::Version),
+    GitTag {
+        repo: String,
+        tag: String,
+    },
+    /// Follows a branch, e.g. `main` while developing against an unreleased runtime
+    GitBranch {
+        repo: String,
+        branch: String,
+    },
+}
+impl AmbientApiSpec {
+    /// Releases come from crates.io; pre-releases aren't published there, so they use their tag in the Ambient
+    /// repository
+    pub fn for_version(version: &semver::Version) -> Self {
+        if version.pre.is_empty() {
+            AmbientApiSpec::Version(version.clone())
+        } else {
+            AmbientApiSpec::GitTag {
+                repo: AMBIENT_REPO.to_string(),
+                tag: format!("v{}", version),
+            }
+        }
+    }
+    fn to_item(&self) -> Item {
+        let git = |repo: &str, key, reference: &str| {
+            let mut table = InlineTable::default();
+            table.insert("git", repo.into());
+            table.insert(key, reference.into());
+            value(table)
+        };
+        match self {
+            AmbientApiSpec::Version(version) => value(version.to_string()),
+            AmbientApiSpec::GitTag { repo, tag } => git(repo, "tag", tag),
+            AmbientApiSpec::GitBranch { repo, branch } => git(repo, "branch", branch),
+        }
+    }
+}
+
+/// Updates `ambient_api` in `[dependencies]` and/or `[workspace.dependencies]`, wherever it's declared.
+/// Adds `[dependencies]` and `ambient_api` if it's declared in neither.
+/// A local checkout (`ambient_api = { path = "../ambient/api" }`) is left alone unless `force_remote` is set, and
+/// so is `ambient_api = { workspace = true }` when `doc` doesn't declare the workspace's version;
+/// returns whether `doc` was updated
+pub fn set_cargo_toml_ambient_api(
+    doc: &mut toml_edit::Document,
+    spec: &AmbientApiSpec,
+    force_remote: bool,
+) -> anyhow::Result<bool> {
+    if !force_remote && cargo_toml_ambient_api_is_local(doc) {
+        return Ok(false);
+    }
+    let rec = spec.to_item();
+    let package_dependency = doc
+        .get("dependencies")
+        .and_then(|deps| deps.get("ambient_api"));
+    // `ambient_api = { workspace = true }` inherits the workspace's version, so it's left alone
+    let inherited = package_dependency
+        .and_then(|dep| dep.get("workspace"))
+        .and_then(|workspace| workspace.as_bool())
+        == Some(true);
+    let in_package = package_dependency.is_some() && !inherited;
+    let in_workspace = doc
+        .get("workspace")
+        .and_then(|workspace| workspace.get("dependencies"))
+        .and_then(|deps| deps.get("ambient_api"))
+        .is_some();
+    if inherited && !in_workspace {
+        return Ok(false);
+    }
+    if in_package && in_workspace {
+        log::warn!(
+            "ambient_api is declared in both [dependencies] and [workspace.dependencies] of Cargo.toml; updating both"
+        );
+    }
+    if in_workspace {
+        doc["workspace"]["dependencies"]["ambient_api"] = rec.clone();
+    }
+    if in_package || (!in_workspace && !inherited) {
+        if doc.get("dependencies").is_none() {
+            doc.insert("dependencies", Item::Table(Table::new()));
+        }
+        doc["dependencies"]
+            .as_table_like_mut()
+            .context("[dependencies] in Cargo.toml is not a table")?
+            .insert("ambient_api", rec);
+    }
+    Ok(true)
+}
+
+/// Whether `ambient_api` in `[dependencies]` or `[workspace.dependencies]` points to a local path
+pub fn cargo_toml_ambient_api_is_local(doc: &toml_edit::Document) -> bool {
+    let is_local = |deps: Option<&Item>| {
+        deps.and_then(|deps| deps.get("ambient_api"))
+            .and_then(|dep| dep.get("path"))
+            .is_some()
+    };
+    is_local(doc.get("dependencies"))
+        || is_local(
+            doc.get("workspace")
+                .and_then(|workspace| workspace.get("dependencies")),
+        )
+}
+
+/// The version the `ambient_api` dependency is pinned to, as written by [`set_cargo_toml_ambient_api`]: a version
+/// for releases, or a `v`-prefixed git tag for nightlies
+pub fn cargo_toml_ambient_api(doc: &toml_edit::Document) -> anyhow::Result<semver::Version> {
+    let workspace_dependency = || {
+        doc.get("workspace")
+            .and_then(|workspace| workspace.get("dependencies"))
+            .and_then(|deps| deps.get("ambient_api"))
+    };
+    let mut dependency = doc
+        .get("dependencies")
+        .and_then(|deps| deps.get("ambient_api"))
+        .or_else(workspace_dependency)
+        .context("Cargo.toml has no ambient_api dependency")?;
+    if dependency.get("workspace").and_then(|w| w.as_bool()) == Some(true) {
+        dependency = workspace_dependency().context(
+            "ambient_api is inherited from the Cargo.toml of the workspace, which isn't checked",
+        )?;
+    }
+    let version = match (
+        dependency.as_str(),
+        dependency.get("tag").and_then(|tag| tag.as_str()),
+        dependency
+            .get("version")
+            .and_then(|version| version.as_str()),
+    ) {
+        (Some(version), _, _) | (None, None, Some(version)) => {
+            version.trim_start_matches(['=', '^'])
+        }
+        (None, Some(tag), _) => tag.trim_start_matches('v'),
+        (None, None, None) => {
+            if let Some(branch) = dependency.get("branch").and_then(|branch| branch.as_str()) {
+                anyhow::bail!(
+                    "ambient_api in Cargo.toml follows the branch `{}`, so its version is unknown",
+                    branch
+                );
+            }
+            anyhow::bail!("ambient_api in Cargo.toml is not pinned to a version or git tag")
+        }
+    };
+    semver::Version::parse(version).with_context(|| {
+        format!(
+            "Can't tell which version ambient_api {:?} in Cargo.toml is",
+            version
+        )
+    })
+}
+
+#[test]
+fn test_cargo_toml_ambient_api() {
+    let version = |toml: &str| {
+        cargo_toml_ambient_api(&toml.parse::<Document>().unwrap()).map(|v| v.to_string())
+    };
+    assert_eq!(
+        version("[dependencies]\nambient_api = \"0.3.1\"").unwrap(),
+        "0.3.1"
+    );
+    assert_eq!(
+        version("[dependencies]\nambient_api = { version = \"=0.3.1\" }").unwrap(),
+        "0.3.1"
+    );
+    assert_eq!(
+        version(r#"[dependencies]
+ambient_api = { git = "https://github.com/AmbientRun/Ambient.git", tag = "v0.3.2-nightly-2023-10-05" }"#)
+        .unwrap(),
+        "0.3.2-nightly-2023-10-05"
+    );
+    assert_eq!(
+        version("[workspace.dependencies]\nambient_api = \"0.3.1\"\n[dependencies]\nambient_api = { workspace = true }")
+            .unwrap(),
+        "0.3.1"
+    );
+    assert!(version("[dependencies]\nambient_api = { workspace = true }").is_err());
+    assert!(version(
+        "[dependencies]\nambient_api = { git = \"https://github.com/AmbientRun/Ambient.git\" }"
+    )
+    .is_err());
+    assert!(version("[dependencies]\nserde = \"1.0\"").is_err());
 }
 
 #[test]
@@ -123,8 +639,10 @@ ambient_api = { git = "https://github.com/AmbientRun/Ambient.git", tag = "v0.3.0
 "#.parse::<Document>().unwrap();
     set_cargo_toml_ambient_api(
         &mut doc,
-        &semver::Version::parse("0.3.0-nightly-2023-09-28").unwrap(),
-    );
+        &AmbientApiSpec::for_version(&semver::Version::parse("0.3.0-nightly-2023-09-28").unwrap()),
+        false,
+    )
+    .unwrap();
     assert_eq!(
         doc.to_string(),
         r#"
@@ -134,6 +652,40 @@ ambient_api = { git = "https://github.com/AmbientRun/Ambient.git", tag = "v0.3.0
     );
 }
 
+#[test]
+fn test_set_cargo_toml_ambient_api_git() {
+    let mut doc = "[dependencies]\nambient_api = \"0.3.0\"\n"
+        .parse::<Document>()
+        .unwrap();
+    let tag = AmbientApiSpec::GitTag {
+        repo: "https://github.com/me/Ambient.git".into(),
+        tag: "v0.3.1-rc.1".into(),
+    };
+    set_cargo_toml_ambient_api(&mut doc, &tag, false).unwrap();
+    assert_eq!(
+        doc.to_string(),
+        "[dependencies]\nambient_api = { git = \"https://github.com/me/Ambient.git\", tag = \"v0.3.1-rc.1\" }\n"
+    );
+    assert_eq!(
+        cargo_toml_ambient_api(&doc).unwrap(),
+        semver::Version::parse("0.3.1-rc.1").unwrap()
+    );
+
+    let branch = AmbientApiSpec::GitBranch {
+        repo: AMBIENT_REPO.into(),
+        branch: "main".into(),
+    };
+    set_cargo_toml_ambient_api(&mut doc, &branch, false).unwrap();
+    assert_eq!(
+        doc.to_string(),
+        "[dependencies]\nambient_api = { git = \"https://github.com/AmbientRun/Ambient.git\", branch = \"main\" }\n"
+    );
+    assert_eq!(
+        cargo_toml_ambient_api(&doc).unwrap_err().to_string(),
+        "ambient_api in Cargo.toml follows the branch `main`, so its version is unknown"
+    );
+}
+
 #[test]
 fn test_set_cargo_toml_ambient_api_point() {
     let mut doc = r#"
@@ -142,7 +694,12 @@ ambient_api = "0.3.0"
 "#
     .parse::<Document>()
     .unwrap();
-    set_cargo_toml_ambient_api(&mut doc, &semver::Version::parse("0.4.0").unwrap());
+    set_cargo_toml_ambient_api(
+        &mut doc,
+        &AmbientApiSpec::for_version(&semver::Version::parse("0.4.0").unwrap()),
+        false,
+    )
+    .unwrap();
     assert_eq!(
         doc.to_string(),
         r#"
@@ -152,10 +709,68 @@ ambient_api = "0.4.0"
     );
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[test]
+fn test_set_cargo_toml_ambient_api_without_dependencies() {
+    let mut doc = r#"[package]
+name = "my_project"
+"#
+    .parse::<Document>()
+    .unwrap();
+    set_cargo_toml_ambient_api(
+        &mut doc,
+        &AmbientApiSpec::for_version(&semver::Version::parse("0.3.0").unwrap()),
+        false,
+    )
+    .unwrap();
+    assert_eq!(
+        doc.to_string(),
+        r#"[package]
+name = "my_project"
+
+[dependencies]
+ambient_api = "0.3.0"
+"#
+    );
+}
+
+#[test]
+fn test_set_cargo_toml_ambient_api_without_ambient_api() {
+    let mut doc = r#"[dependencies]
+serde = "1.0"
+"#
+    .parse::<Document>()
+    .unwrap();
+    set_cargo_toml_ambient_api(
+        &mut doc,
+        &AmbientApiSpec::for_version(&semver::Version::parse("0.3.0").unwrap()),
+        false,
+    )
+    .unwrap();
+    assert_eq!(
+        doc.to_string(),
+        r#"[dependencies]
+serde = "1.0"
+ambient_api = "0.3.0"
+"#
+    );
+
+    let mut doc = "dependencies = 1\n".parse::<Document>().unwrap();
+    assert!(set_cargo_toml_ambient_api(
+        &mut doc,
+        &AmbientApiSpec::for_version(&semver::Version::parse("0.3.0").unwrap()),
+        false
+    )
+    .is_err());
+}
+
+/// Serialized as the names of the builds in the bucket, like its `Display`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum Os {
+    #[serde(rename = "macos-latest")]
     Macos,
+    #[serde(rename = "windows-latest")]
     Windows,
+    #[serde(rename = "ubuntu-22.04")]
     Linux,
 }
 impl Os {
@@ -168,12 +783,52 @@ impl Os {
             Os::Linux
         }
     }
+    /// Whether this is Linux running in WSL. `current` is still `Linux` there, so the Linux build is installed
+    pub fn is_wsl() -> bool {
+        cfg!(target_os = "linux")
+            && ["WSL_DISTRO_NAME", "WSL_INTEROP"]
+                .iter()
+                .any(|name| std::env::var_os(name).is_some())
+    }
+    /// How to try the Windows build instead when the Linux build doesn't work in WSL
+    pub fn wsl_help(version: Option<&semver::Version>) -> String {
+        let version = match version {
+            Some(version) => version.to_string(),
+            None => "<version>".to_string(),
+        };
+        format!(
+            "This looks like WSL. If the Linux runtime doesn't work here, e.g. because there's no GPU access, try the \
+             Windows build: install it with `ambient runtime install {} --os windows-latest` and run its ambient.exe, \
+             which WSL starts on Windows",
+            version
+        )
+    }
     pub fn ambient_bin_name(&self) -> &'static str {
         match self {
             Os::Windows => "ambient.exe",
             _ => "ambient",
         }
     }
+    /// The OS of a Rust target triple like `x86_64-unknown-linux-gnu`, `aarch64-apple-darwin` or
+    /// `x86_64-pc-windows-msvc`
+    pub fn from_target_triple(triple: &str) -> anyhow::Result<Self> {
+        let parts = triple.split('-').collect_vec();
+        if parts.len() < 3 || parts.iter().any(|part| part.is_empty()) {
+            anyhow::bail!(
+                "Invalid target triple {:?}, expected <arch>-<vendor>-<os>[-<env>]",
+                triple
+            );
+        }
+        parts[2..]
+            .iter()
+            .find_map(|part| match *part {
+                "linux" => Some(Os::Linux),
+                "darwin" => Some(Os::Macos),
+                "windows" => Some(Os::Windows),
+                _ => None,
+            })
+            .with_context(|| format!("Unsupported OS in target triple {:?}", triple))
+    }
 }
 impl std::fmt::Display for Os {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -192,7 +847,146 @@ impl FromStr for Os {
             "macos-latest" => Ok(Os::Macos),
             "windows-latest" => Ok(Os::Windows),
             "ubuntu-22.04" => Ok(Os::Linux),
-            _ => Err(anyhow::anyhow!("Invalid OS")),
+            _ => Os::from_target_triple(s).map_err(|_| {
+                anyhow::anyhow!(
+                    "Invalid OS {:?}, expected one of macos-latest, windows-latest, ubuntu-22.04, \
+                     or a target triple like x86_64-unknown-linux-gnu",
+                    s
+                )
+            }),
         }
     }
 }
+
+#[test]
+fn test_dir_size() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a"), "12345").unwrap();
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    std::fs::write(dir.path().join("sub").join("b"), "123").unwrap();
+    assert_eq!(dir_size(dir.path()).unwrap(), 8);
+}
+
+#[test]
+fn test_os_from_target_triple() {
+    for (triple, os) in [
+        ("x86_64-unknown-linux-gnu", Os::Linux),
+        ("x86_64-unknown-linux-musl", Os::Linux),
+        ("aarch64-unknown-linux-gnu", Os::Linux),
+        ("x86_64-apple-darwin", Os::Macos),
+        ("aarch64-apple-darwin", Os::Macos),
+        ("x86_64-pc-windows-msvc", Os::Windows),
+        ("x86_64-pc-windows-gnu", Os::Windows),
+        ("aarch64-pc-windows-msvc", Os::Windows),
+    ] {
+        assert_eq!(Os::from_target_triple(triple).unwrap(), os, "{}", triple);
+        assert_eq!(triple.parse::<Os>().unwrap(), os, "{}", triple);
+    }
+    assert_eq!("ubuntu-22.04".parse::<Os>().unwrap(), Os::Linux);
+    assert!(Os::from_target_triple("wasm32-unknown-unknown").is_err());
+    assert!(Os::from_target_triple("x86_64-linux").is_err());
+    assert!("freebsd".parse::<Os>().is_err());
+}
+
+#[test]
+fn test_set_cargo_toml_ambient_api_local_path() {
+    let toml = r#"[dependencies]
+ambient_api = { path = "../ambient/guest/rust/api" }
+"#;
+    let version = semver::Version::parse("0.3.1").unwrap();
+    let mut doc = toml.parse::<Document>().unwrap();
+    assert!(
+        !set_cargo_toml_ambient_api(&mut doc, &AmbientApiSpec::for_version(&version), false)
+            .unwrap()
+    );
+    assert_eq!(doc.to_string(), toml);
+    assert!(
+        set_cargo_toml_ambient_api(&mut doc, &AmbientApiSpec::for_version(&version), true).unwrap()
+    );
+    assert_eq!(doc.to_string(), "[dependencies]\nambient_api = \"0.3.1\"\n");
+
+    let toml = "[workspace.dependencies]\nambient_api = { path = \"../ambient/guest/rust/api\" }\n";
+    let mut doc = toml.parse::<Document>().unwrap();
+    assert!(
+        !set_cargo_toml_ambient_api(&mut doc, &AmbientApiSpec::for_version(&version), false)
+            .unwrap()
+    );
+    assert_eq!(doc.to_string(), toml);
+}
+
+#[test]
+fn test_set_cargo_toml_ambient_api_workspace() {
+    let spec = AmbientApiSpec::for_version(&semver::Version::parse("0.3.1").unwrap());
+    let toml = r#"[workspace]
+members = ["game"]
+
+[workspace.dependencies]
+ambient_api = "0.3.0"
+"#;
+    let mut doc = toml.parse::<Document>().unwrap();
+    assert!(set_cargo_toml_ambient_api(&mut doc, &spec, false).unwrap());
+    assert_eq!(doc.to_string(), toml.replace("0.3.0", "0.3.1"));
+
+    // Members that inherit the workspace's version are left alone
+    let member = r#"[package]
+name = "game"
+
+[dependencies]
+ambient_api = { workspace = true }
+"#;
+    let mut doc = member.parse::<Document>().unwrap();
+    assert!(!set_cargo_toml_ambient_api(&mut doc, &spec, false).unwrap());
+    assert_eq!(doc.to_string(), member);
+
+    // and updating them points to the workspace's Cargo.toml instead
+    let dir = tempfile::tempdir().unwrap();
+    let game = dir.path().join("game");
+    std::fs::create_dir_all(&game).unwrap();
+    std::fs::write(dir.path().join("Cargo.toml"), toml).unwrap();
+    std::fs::write(game.join("Cargo.toml"), member).unwrap();
+    let err = CargoTomlPath(game.join("Cargo.toml"))
+        .with_ambient_api(&spec, false)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "ambient_api in {:?} is inherited from the workspace; set it in [workspace.dependencies] of {:?}",
+            game.join("Cargo.toml"),
+            dir.path().join("Cargo.toml")
+        )
+    );
+}
+
+#[test]
+fn test_find_workspace_root() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("workspace");
+    let game = root.join("packages").join("game");
+    std::fs::create_dir_all(&game).unwrap();
+    std::fs::write(
+        root.join("ambient.toml"),
+        "[workspace]\nmembers = [\"packages/game\"]\n",
+    )
+    .unwrap();
+    std::fs::write(game.join("ambient.toml"), "[package]\nname = \"game\"\n").unwrap();
+    assert_eq!(PackagePath::find_workspace_root(&game).unwrap().0, root);
+    assert_eq!(
+        PackagePath::find_workspace_root(&root.join("packages"))
+            .unwrap()
+            .0,
+        root
+    );
+    assert!(!PackagePath(game.clone()).is_workspace_root());
+
+    // A package inside another package isn't part of a workspace further up
+    let nested = game.join("nested");
+    std::fs::create_dir_all(&nested).unwrap();
+    std::fs::write(
+        nested.join("ambient.toml"),
+        "[package]\nname = \"nested\"\n",
+    )
+    .unwrap();
+    assert!(PackagePath::find_workspace_root(&nested).is_none());
+
+    assert!(PackagePath::find_workspace_root(dir.path()).is_none());
+}
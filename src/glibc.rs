@@ -0,0 +1,128 @@
+use anyhow::Context;
+use itertools::Itertools;
+use std::path::Path;
+
+/// A glibc version, e.g. `(2, 35)`
+pub type GlibcVersion = (u32, u32);
+
+/// The glibc versions of some common distributions, to tell users where a runtime works
+const DISTRO_GLIBC_VERSIONS: &[(&str, GlibcVersion)] = &[
+    ("Ubuntu 20.04", (2, 31)),
+    ("Ubuntu 22.04", (2, 35)),
+    ("Ubuntu 24.04", (2, 39)),
+    ("Debian 11", (2, 31)),
+    ("Debian 12", (2, 36)),
+    ("Fedora 36", (2, 35)),
+    ("Fedora 40", (2, 39)),
+    ("RHEL 8", (2, 28)),
+    ("RHEL 9", (2, 34)),
+];
+
+/// The newest glibc symbol version `binary` links against, from the `GLIBC_x.y` version names in its dynamic
+/// string table
+pub fn required_glibc(binary: &[u8]) -> Option<GlibcVersion> {
+    const PREFIX: &[u8] = b"GLIBC_";
+    let mut newest = None;
+    let mut rest = binary;
+    while let Some(start) = rest.windows(PREFIX.len()).position(|w| w == PREFIX) {
+        rest = &rest[start + PREFIX.len()..];
+        let version = rest
+            .iter()
+            .take_while(|b| b.is_ascii_digit() || **b == b'.')
+            .map(|b| *b as char)
+            .collect::<String>();
+        if let Some(version) = parse_version(&version) {
+            newest = newest.max(Some(version));
+        }
+    }
+    newest
+}
+
+/// The glibc version of this system, from `ldd --version`
+pub fn system_glibc() -> anyhow::Result<GlibcVersion> {
+    let output = std::process::Command::new("ldd")
+        .arg("--version")
+        .output()
+        .context("Failed to run `ldd --version`")?;
+    parse_ldd_version(&String::from_utf8_lossy(&output.stdout))
+        .context("Could not determine the glibc version from `ldd --version`")
+}
+
+/// The first line of `ldd --version` ends with the version, e.g. `ldd (Ubuntu GLIBC 2.35-0ubuntu3.1) 2.35`
+fn parse_ldd_version(output: &str) -> Option<GlibcVersion> {
+    parse_version(output.lines().next()?.split_whitespace().last()?)
+}
+
+fn parse_version(version: &str) -> Option<GlibcVersion> {
+    let mut parts = version.split('.');
+    Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
+
+/// Fails with an explanation when `exe_path` needs a newer glibc than this system has. Does nothing on other
+/// platforms, or when either version can't be determined
+pub fn ensure_glibc_compatible(exe_path: &Path) -> anyhow::Result<()> {
+    if !cfg!(target_os = "linux") {
+        return Ok(());
+    }
+    let binary =
+        std::fs::read(exe_path).with_context(|| format!("Failed to read {:?}", exe_path))?;
+    let Some(required) = required_glibc(&binary) else {
+        return Ok(());
+    };
+    let system = match system_glibc() {
+        Ok(system) => system,
+        Err(err) => {
+            log::info!("Skipping the glibc check: {:#}", err);
+            return Ok(());
+        }
+    };
+    check_compatible(required, system)
+}
+
+fn check_compatible(required: GlibcVersion, system: GlibcVersion) -> anyhow::Result<()> {
+    if system >= required {
+        return Ok(());
+    }
+    let supported = DISTRO_GLIBC_VERSIONS
+        .iter()
+        .filter(|(_, version)| *version >= required)
+        .map(|(distro, _)| *distro)
+        .join(", ");
+    anyhow::bail!(
+        "This runtime requires glibc {}.{}, but this system has glibc {}.{}. \
+         It runs on distributions with glibc {}.{} or newer, such as {}",
+        required.0,
+        required.1,
+        system.0,
+        system.1,
+        required.0,
+        required.1,
+        supported
+    )
+}
+
+#[test]
+fn test_required_glibc() {
+    let binary = b"\0libc.so.6\0GLIBC_2.2.5\0GLIBC_2.34\0GLIBC_2.17\0GLIBC_PRIVATE\0GLIBC_";
+    assert_eq!(required_glibc(binary), Some((2, 34)));
+    assert_eq!(required_glibc(b"\0libc.so.6\0"), None);
+}
+
+#[test]
+fn test_parse_ldd_version() {
+    assert_eq!(
+        parse_ldd_version("ldd (Ubuntu GLIBC 2.35-0ubuntu3.1) 2.35\nCopyright (C) 2022"),
+        Some((2, 35))
+    );
+    assert_eq!(parse_ldd_version("musl libc (x86_64)"), None);
+}
+
+#[test]
+fn test_check_compatible() {
+    assert!(check_compatible((2, 31), (2, 35)).is_ok());
+    assert_eq!(
+        check_compatible((2, 35), (2, 31)).unwrap_err().to_string(),
+        "This runtime requires glibc 2.35, but this system has glibc 2.31. \
+         It runs on distributions with glibc 2.35 or newer, such as Ubuntu 22.04, Ubuntu 24.04, Debian 12, Fedora 36, Fedora 40"
+    );
+}
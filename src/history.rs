@@ -0,0 +1,123 @@
+use crate::environment::{history_path, write_file_atomically};
+use serde::{Deserialize, Serialize};
+
+/// Older changes are dropped once the history is this long
+const MAX_CHANGES: usize = 100;
+
+/// A change of the default runtime version
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DefaultRuntimeChange {
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub at: chrono::DateTime<chrono::Utc>,
+    pub old: Option<semver::Version>,
+    pub new: semver::Version,
+    /// The command that made the change, e.g. `update-default`
+    pub command: String,
+    /// The profile whose default runtime changed, `None` for the top-level settings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+}
+impl std::fmt::Display for DefaultRuntimeChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}  {:<14}  {} -> {}",
+            self.at.format("%Y-%m-%d %H:%M:%S UTC"),
+            self.command,
+            self.old
+                .as_ref()
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| "(none)".to_string()),
+            self.new
+        )
+    }
+}
+
+/// Every change of the default runtime version, oldest first, stored in the data dir
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DefaultRuntimeHistory {
+    pub changes: Vec<DefaultRuntimeChange>,
+}
+impl DefaultRuntimeHistory {
+    pub fn load() -> anyhow::Result<Self> {
+        let path = history_path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = history_path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        write_file_atomically(&path, &serde_json::to_string_pretty(self)?)
+    }
+    pub fn record(&mut self, change: DefaultRuntimeChange) {
+        self.changes.push(change);
+        let excess = self.changes.len().saturating_sub(MAX_CHANGES);
+        self.changes.drain(..excess);
+    }
+    /// The changes of the default runtime of `profile`, oldest first
+    pub fn changes_for<'a>(
+        &'a self,
+        profile: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a DefaultRuntimeChange> + 'a {
+        self.changes
+            .iter()
+            .filter(move |change| change.profile.as_deref() == profile)
+    }
+    /// The default runtime of `profile` before its last change
+    pub fn previous(&self, profile: Option<&str>) -> Option<&semver::Version> {
+        self.changes
+            .iter()
+            .rev()
+            .find(|change| change.profile.as_deref() == profile)?
+            .old
+            .as_ref()
+    }
+}
+
+#[test]
+fn test_default_runtime_history() {
+    let version = |v: &str| semver::Version::parse(v).unwrap();
+    let mut history = DefaultRuntimeHistory::default();
+    assert_eq!(history.previous(None), None);
+    for patch in 0..MAX_CHANGES as u64 + 5 {
+        history.record(DefaultRuntimeChange {
+            at: chrono::Utc::now(),
+            old: patch.checked_sub(1).map(|p| semver::Version::new(0, 3, p)),
+            new: semver::Version::new(0, 3, patch),
+            command: "update-default".to_string(),
+            profile: None,
+        });
+    }
+    assert_eq!(history.changes.len(), MAX_CHANGES);
+    assert_eq!(history.changes[0].new, version("0.3.5"));
+    assert_eq!(history.previous(None), Some(&version("0.3.103")));
+
+    // Each profile rolls back to its own previous default
+    history.record(DefaultRuntimeChange {
+        at: chrono::Utc::now(),
+        old: Some(version("0.4.0-nightly-2023-10-01")),
+        new: version("0.4.0-nightly-2023-10-05"),
+        command: "update-default".to_string(),
+        profile: Some("work".to_string()),
+    });
+    assert_eq!(history.previous(None), Some(&version("0.3.103")));
+    assert_eq!(
+        history.previous(Some("work")),
+        Some(&version("0.4.0-nightly-2023-10-01"))
+    );
+    assert_eq!(history.previous(Some("home")), None);
+    assert_eq!(history.changes_for(Some("work")).count(), 1);
+
+    let json = serde_json::to_string(&history).unwrap();
+    let loaded: DefaultRuntimeHistory = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.changes.len(), MAX_CHANGES);
+    assert_eq!(loaded.previous(None), history.previous(None));
+    assert_eq!(
+        loaded.previous(Some("work")),
+        history.previous(Some("work"))
+    );
+}
@@ -0,0 +1,117 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Commands run around installing and removing runtimes, e.g. to register them with a symbol server. They can
+/// use the placeholders `{version}`, `{path}` (the runtime binary) and `{dir}` (the directory holding its files)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Hooks {
+    /// Run after a runtime has been installed. The runtime is removed again when it fails, unless
+    /// `post_install_optional` is set
+    pub post_install: Option<String>,
+    #[serde(default)]
+    pub post_install_optional: bool,
+    /// Run before a runtime is removed; the runtime is kept when it fails
+    pub pre_uninstall: Option<String>,
+}
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hook {
+    PostInstall,
+    PreUninstall,
+}
+impl std::fmt::Display for Hook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Hook::PostInstall => write!(f, "post_install"),
+            Hook::PreUninstall => write!(f, "pre_uninstall"),
+        }
+    }
+}
+
+/// `template` with its placeholders replaced by the quoted values for the runtime at `exe_path`
+pub fn expand_hook(template: &str, version: &semver::Version, exe_path: &Path) -> String {
+    let dir = exe_path.parent().unwrap_or(Path::new(""));
+    template
+        .replace("{version}", &quote(&version.to_string()))
+        .replace("{path}", &quote(&exe_path.to_string_lossy()))
+        .replace("{dir}", &quote(&dir.to_string_lossy()))
+}
+
+/// Runs the expanded hook `command` with the system shell, its output going straight to the terminal
+pub fn run_hook(hook: Hook, command: &str) -> anyhow::Result<()> {
+    let status = shell_command(command)
+        .status()
+        .with_context(|| format!("Failed to run the {} hook `{}`", hook, command))?;
+    if !status.success() {
+        anyhow::bail!(
+            "The {} hook `{}` failed with {}. Pass --no-hooks to skip it",
+            hook,
+            command,
+            status
+        );
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> std::process::Command {
+    let mut cmd = std::process::Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+#[cfg(not(unix))]
+fn shell_command(command: &str) -> std::process::Command {
+    let mut cmd = std::process::Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+#[cfg(unix)]
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+#[cfg(not(unix))]
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value)
+}
+
+#[test]
+fn test_expand_hook() {
+    let version = semver::Version::parse("0.3.1").unwrap();
+    let expanded = expand_hook(
+        "register {path} --version {version} --dir {dir}",
+        &version,
+        Path::new("/runtimes/0.3.1/ambient"),
+    );
+    if cfg!(unix) {
+        assert_eq!(
+            expanded,
+            "register '/runtimes/0.3.1/ambient' --version '0.3.1' --dir '/runtimes/0.3.1'"
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_run_hook() {
+    let dir = tempfile::tempdir().unwrap();
+    let marker = dir.path().join("it's done");
+    let version = semver::Version::parse("0.3.1").unwrap();
+    let command = expand_hook("touch {dir}/\"it's done\"", &version, &marker);
+    run_hook(Hook::PostInstall, &command).unwrap();
+    assert!(marker.exists());
+
+    let err = run_hook(Hook::PreUninstall, "exit 3")
+        .unwrap_err()
+        .to_string();
+    assert_eq!(
+        err,
+        "The pre_uninstall hook `exit 3` failed with exit status: 3. Pass --no-hooks to skip it"
+    );
+}
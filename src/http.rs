@@ -0,0 +1,309 @@
+use crate::{list_installed_runtimes, Settings};
+use anyhow::Context;
+use std::{
+    io::BufReader,
+    net::ToSocketAddrs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+pub const DEFAULT_TIMEOUT_SECS: u64 = 300;
+
+#[derive(Debug, Clone, Copy)]
+pub struct HttpTimeouts {
+    pub connect: Duration,
+    /// Timeout for the whole request, including transferring the body
+    pub overall: Duration,
+}
+impl HttpTimeouts {
+    /// `AMBIENT_HTTP_TIMEOUT_SECS` overrides the overall timeout from the settings
+    pub fn from_settings(settings: &Settings) -> Self {
+        let overall = std::env::var("AMBIENT_HTTP_TIMEOUT_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .or(settings.http_timeout_secs)
+            .unwrap_or(DEFAULT_TIMEOUT_SECS);
+        Self {
+            connect: Duration::from_secs(
+                settings
+                    .http_connect_timeout_secs
+                    .unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS),
+            ),
+            overall: Duration::from_secs(overall),
+        }
+    }
+}
+
+/// The agent used for all network access, so that they share the same configuration.
+/// Proxies are picked up from `HTTPS_PROXY` and friends.
+pub fn agent(settings: &Settings) -> anyhow::Result<ureq::Agent> {
+    let timeouts = HttpTimeouts::from_settings(settings);
+    let mut builder = ureq::AgentBuilder::new()
+        .timeout_connect(timeouts.connect)
+        .timeout(timeouts.overall)
+        .try_proxy_from_env(true);
+    if let Some(ca_bundle) = ca_bundle_path(settings) {
+        builder = builder.tls_config(Arc::new(tls_config_with_ca_bundle(&ca_bundle)?));
+    }
+    Ok(builder.build())
+}
+
+/// Extra root certificates, e.g. for a corporate proxy that inspects HTTPS traffic.
+/// `AMBIENT_CA_BUNDLE` overrides the `ca_bundle` setting.
+pub fn ca_bundle_path(settings: &Settings) -> Option<PathBuf> {
+    std::env::var_os("AMBIENT_CA_BUNDLE")
+        .map(PathBuf::from)
+        .or_else(|| settings.ca_bundle.clone())
+}
+
+/// Loads the PEM certificates in `path`, returning how many were added
+fn add_ca_bundle(roots: &mut rustls::RootCertStore, path: &Path) -> anyhow::Result<usize> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open CA bundle {:?}", path))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .with_context(|| format!("Invalid CA bundle {:?}", path))?;
+    let (added, _) = roots.add_parsable_certificates(&certs);
+    if added == 0 {
+        anyhow::bail!("No valid certificates found in CA bundle {:?}", path);
+    }
+    Ok(added)
+}
+
+fn tls_config_with_ca_bundle(path: &Path) -> anyhow::Result<rustls::ClientConfig> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+    add_ca_bundle(&mut roots, path)?;
+    Ok(rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+/// The proxy environment variables that are set, in the order ureq looks at them
+pub fn proxy_env_vars() -> Vec<(&'static str, String)> {
+    [
+        "ALL_PROXY",
+        "all_proxy",
+        "HTTPS_PROXY",
+        "https_proxy",
+        "HTTP_PROXY",
+        "http_proxy",
+    ]
+    .into_iter()
+    .filter_map(|name| Some((name, std::env::var(name).ok()?)))
+    .collect()
+}
+
+pub fn ensure_online(settings: &Settings, operation: &str) -> anyhow::Result<()> {
+    if settings.offline {
+        anyhow::bail!(
+            "{} requires network access, but offline mode is on",
+            operation
+        );
+    }
+    Ok(())
+}
+
+/// Adds the failed operation to network errors, and explains timeouts
+pub fn network_error(
+    settings: &Settings,
+    operation: &str,
+    err: impl Into<anyhow::Error>,
+) -> anyhow::Error {
+    let err = err.into();
+    let Some(timeout) = timeout_kind(&err) else {
+        return match failure_kind(&err) {
+            Some(failure) => err.context(format!(
+                "{} failed: {}. Run `ambient runtime doctor` to diagnose your connection",
+                operation,
+                failure.guidance()
+            )),
+            None => err.context(format!("{} failed", operation)),
+        };
+    };
+    let timeouts = HttpTimeouts::from_settings(settings);
+    let (duration, setting) = match timeout {
+        Timeout::Connect => (timeouts.connect, "http_connect_timeout_secs"),
+        Timeout::Overall => (timeouts.overall, "http_timeout_secs"),
+    };
+    let mut message = format!(
+        "{} timed out after {}s. The timeout can be changed with the `{}` setting",
+        operation,
+        duration.as_secs(),
+        setting
+    );
+    if timeout == Timeout::Overall {
+        message += " or the AMBIENT_HTTP_TIMEOUT_SECS environment variable";
+    }
+    if let Some(installed) = list_installed_runtimes(settings)
+        .ok()
+        .and_then(|runtimes| runtimes.into_iter().map(|r| r.version).max())
+    {
+        message += &format!(
+            ". Runtime {} is already installed; pass --offline (or set AMBIENT_OFFLINE=1) to only use installed runtimes",
+            installed
+        );
+    }
+    err.context(message)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionFailure {
+    Certificate,
+    Proxy,
+    Dns,
+}
+impl ConnectionFailure {
+    pub fn guidance(&self) -> String {
+        match self {
+            ConnectionFailure::Certificate => "the server's TLS certificate could not be verified. \
+                If you're behind a proxy that inspects HTTPS traffic, point the CLI at your \
+                organization's CA certificates with AMBIENT_CA_BUNDLE=/path/to/ca.pem or the `ca_bundle` setting"
+                .to_string(),
+            ConnectionFailure::Proxy => {
+                let proxies = proxy_env_vars();
+                let current = if proxies.is_empty() {
+                    "none is set".to_string()
+                } else {
+                    proxies
+                        .iter()
+                        .map(|(name, value)| format!("{}={}", name, value))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+                format!(
+                    "the proxy refused the connection. Check the proxy address and credentials \
+                     in HTTPS_PROXY ({})",
+                    current
+                )
+            }
+            ConnectionFailure::Dns => "the server's host name could not be resolved. \
+                Check that you're connected to the internet, and set HTTPS_PROXY if your network requires a proxy"
+                .to_string(),
+        }
+    }
+}
+pub fn failure_kind(err: &anyhow::Error) -> Option<ConnectionFailure> {
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<ureq::Error>() {
+            match err.kind() {
+                ureq::ErrorKind::Dns => return Some(ConnectionFailure::Dns),
+                ureq::ErrorKind::ProxyConnect
+                | ureq::ErrorKind::ProxyUnauthorized
+                | ureq::ErrorKind::InvalidProxyUrl => return Some(ConnectionFailure::Proxy),
+                _ => {}
+            }
+        }
+        if cause
+            .downcast_ref::<rustls::Error>()
+            .is_some_and(|err| matches!(err, rustls::Error::InvalidCertificate(_)))
+            || cause.to_string().contains("invalid peer certificate")
+        {
+            return Some(ConnectionFailure::Certificate);
+        }
+    }
+    None
+}
+
+/// Checks that `host` resolves, returning its first address
+pub fn probe_dns(host: &str) -> anyhow::Result<String> {
+    let addr = (host, 443)
+        .to_socket_addrs()
+        .with_context(|| format!("Could not resolve {}", host))?
+        .next()
+        .with_context(|| format!("{} resolved to no addresses", host))?;
+    Ok(addr.ip().to_string())
+}
+
+/// Checks that the CA bundle, if any, can be loaded, returning how many certificates it has
+pub fn probe_ca_bundle(settings: &Settings) -> anyhow::Result<Option<(PathBuf, usize)>> {
+    let Some(path) = ca_bundle_path(settings) else {
+        return Ok(None);
+    };
+    let count = add_ca_bundle(&mut rustls::RootCertStore::empty(), &path)?;
+    Ok(Some((path, count)))
+}
+
+/// Makes a request to `url` with the configured agent, explaining failures
+pub fn probe_https(settings: &Settings, url: &str) -> anyhow::Result<u16> {
+    let operation = format!("Connecting to {}", url);
+    match agent(settings)?.get(url).call() {
+        Ok(response) => Ok(response.status()),
+        Err(ureq::Error::Status(status, _)) => Ok(status),
+        Err(err) => {
+            let err = anyhow::Error::from(err);
+            match failure_kind(&err) {
+                Some(failure) => Err(err.context(failure.guidance())),
+                None => Err(network_error(settings, &operation, err)),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Timeout {
+    Connect,
+    Overall,
+}
+fn timeout_kind(err: &anyhow::Error) -> Option<Timeout> {
+    let timed_out = err.chain().any(|cause| {
+        cause.downcast_ref::<std::io::Error>().is_some_and(|err| {
+            matches!(
+                err.kind(),
+                std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+            )
+        })
+    });
+    if !timed_out {
+        return None;
+    }
+    let connecting = err.chain().any(|cause| {
+        cause
+            .downcast_ref::<ureq::Error>()
+            .is_some_and(|err| err.kind() == ureq::ErrorKind::ConnectionFailed)
+    });
+    Some(if connecting {
+        Timeout::Connect
+    } else {
+        Timeout::Overall
+    })
+}
+
+#[test]
+fn test_network_error_timeout() {
+    let settings = Settings {
+        http_timeout_secs: Some(42),
+        ..Default::default()
+    };
+    let err = network_error(
+        &settings,
+        "Downloading runtime 0.3.0",
+        std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out"),
+    );
+    assert!(err
+        .to_string()
+        .starts_with("Downloading runtime 0.3.0 timed out after 42s"));
+
+    let err = network_error(
+        &settings,
+        "Listing runtime versions",
+        std::io::Error::other("broken pipe"),
+    );
+    assert_eq!(err.to_string(), "Listing runtime versions failed");
+}
+
+#[test]
+fn test_failure_kind_certificate() {
+    let err =
+        anyhow::anyhow!("invalid peer certificate: UnknownIssuer").context("https://example.com");
+    assert_eq!(failure_kind(&err), Some(ConnectionFailure::Certificate));
+    assert_eq!(failure_kind(&anyhow::anyhow!("broken pipe")), None);
+}
@@ -0,0 +1,1933 @@
+//! Resolves, installs and locates Ambient runtimes the same way the `ambient` CLI does, so that other tools
+//! (like editor extensions) can find the runtime for a project without shelling out to the CLI.
+
+pub mod ambient_toml;
+mod archive;
+pub mod crash;
+pub mod deprecations;
+pub mod download_cache;
+pub mod environment;
+pub mod glibc;
+pub mod history;
+pub mod hooks;
+pub mod http;
+pub mod lockfile;
+pub mod quarantine;
+pub mod runtime_logs;
+pub mod shell_env;
+pub mod templates;
+pub mod versions;
+
+use anyhow::Context;
+use deprecations::Deprecations;
+use environment::{PackagePath, RuntimeStore};
+use hooks::{expand_hook, run_hook, Hook, Hooks};
+use itertools::Itertools;
+use lockfile::RuntimeLock;
+use semver::VersionReq;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use versions::{cached_versions, parse_mirror_url, BucketSource, VersionSource, VersionsFilter};
+
+pub use environment::{app_dir, runtimes_dir, settings_dir, settings_path, version_cache_path};
+pub use versions::{InstallOptions, InstallProgress, RuntimeVersion};
+
+/// A runtime version found in one of the runtime stores
+pub struct InstalledRuntime {
+    pub version: semver::Version,
+    /// The directory holding the runtime's files
+    pub dir: PathBuf,
+    pub store: RuntimeStore,
+}
+impl InstalledRuntime {
+    /// Removes the runtime's files, after running the `pre_uninstall` hook
+    pub fn uninstall(
+        &self,
+        settings: &Settings,
+        on_progress: &dyn Fn(&InstallProgress),
+    ) -> anyhow::Result<()> {
+        if let Some(template) = settings.hook(Hook::PreUninstall) {
+            let exe_path = self.dir.join(environment::Os::current().ambient_bin_name());
+            let command = expand_hook(template, &self.version, &exe_path);
+            on_progress(&InstallProgress::RunningHook {
+                hook: Hook::PreUninstall,
+                command: command.clone(),
+            });
+            run_hook(Hook::PreUninstall, &command)?;
+        }
+        std::fs::remove_dir_all(&self.dir)
+            .with_context(|| format!("Failed to remove {:?}", self.dir))
+    }
+}
+
+/// Lists installed runtimes across all stores; a version installed in several stores is only listed for the first one
+pub fn list_installed_runtimes(settings: &Settings) -> anyhow::Result<Vec<InstalledRuntime>> {
+    let mut runtimes: Vec<InstalledRuntime> = Vec::new();
+    for (store, runtimes_dir) in settings.runtime_stores()? {
+        if !runtimes_dir.exists() {
+            continue;
+        }
+        for entry in std::fs::read_dir(runtimes_dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.is_dir() {
+                // Skips other entries in the runtimes dir, like the builds installed for other OSes with `--os`
+                let Some(version) = entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| semver::Version::parse(name).ok())
+                else {
+                    continue;
+                };
+                if !runtimes.iter().any(|r| r.version == version) {
+                    runtimes.push(InstalledRuntime {
+                        version,
+                        dir: path,
+                        store,
+                    });
+                }
+            }
+        }
+    }
+    Ok(runtimes)
+}
+
+/// A leftover of a failed or interrupted install in a runtime store
+pub struct BrokenRuntimeDir {
+    pub path: PathBuf,
+    pub reason: &'static str,
+}
+
+/// Finds the leftovers of failed installs in the runtime stores: version directories without a usable runtime
+/// binary, and `.tmp` entries. Complete installs, directories holding a linked runtime, and anything that isn't
+/// named like an install are left out
+pub fn find_broken_runtime_dirs(settings: &Settings) -> anyhow::Result<Vec<BrokenRuntimeDir>> {
+    let mut broken = Vec::new();
+    for (_, runtimes_dir) in settings.runtime_stores()? {
+        if !runtimes_dir.exists() {
+            continue;
+        }
+        for entry in std::fs::read_dir(&runtimes_dir)? {
+            let path = entry?.path();
+            let linked = settings
+                .linked_runtimes
+                .values()
+                .any(|linked| linked.starts_with(&path));
+            if linked {
+                continue;
+            }
+            if let Some(reason) = broken_install_reason(&path) {
+                broken.push(BrokenRuntimeDir { path, reason });
+            }
+        }
+    }
+    broken.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(broken)
+}
+
+/// Why the entry `path` of a runtime store is a broken install, or `None` if it's complete or not an install
+fn broken_install_reason(path: &Path) -> Option<&'static str> {
+    let name = path.file_name()?.to_str()?;
+    if name.ends_with(".tmp") {
+        return Some("leftover temporary files");
+    }
+    if !path.is_dir() {
+        return None;
+    }
+    // Builds installed for other OSes with `--os` are named like `0.3.1@windows-latest`
+    let os = match name.split_once('@') {
+        Some((version, os)) => {
+            semver::Version::parse(version).ok()?;
+            environment::Os::from_str(os).ok()?
+        }
+        None => {
+            semver::Version::parse(name).ok()?;
+            environment::Os::current()
+        }
+    };
+    match std::fs::metadata(path.join(os.ambient_bin_name())) {
+        Ok(metadata) if metadata.len() > 0 => None,
+        Ok(_) => Some("the runtime binary is empty"),
+        Err(_) => Some("no runtime binary"),
+    }
+}
+
+/// The runtimes `runtime prune` removes: all but the `keep` newest in the user's runtimes dir.
+/// The default runtime and the one before it are always kept, and the shared runtimes dir is left alone
+pub fn runtimes_to_prune(
+    settings: &Settings,
+    installed: Vec<InstalledRuntime>,
+    keep: usize,
+) -> Vec<InstalledRuntime> {
+    let protected = [
+        &settings.default_runtime,
+        &settings.previous_default_runtime,
+    ];
+    installed
+        .into_iter()
+        .filter(|r| r.store == RuntimeStore::User)
+        .sorted_by(|a, b| b.version.cmp(&a.version))
+        .skip(keep)
+        .filter(|r| !protected.iter().any(|v| v.as_ref() == Some(&r.version)))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReleaseTrain {
+    Stable,
+    /// Release candidates, betas and alphas, e.g. `0.4.0-rc.1`
+    Beta,
+    Nightly,
+    Internal,
+}
+impl ReleaseTrain {
+    pub fn from_version(version: &semver::Version) -> Self {
+        Self::from_prerelease(&version.pre)
+    }
+    /// `None` if the requirement doesn't pin a release train, i.e. `*` or one that opts into all
+    /// pre-releases like `>=0.0.0-0`
+    pub fn from_version_req(version_req: &semver::VersionReq) -> Option<Self> {
+        if allows_all_prereleases(version_req) {
+            return None;
+        }
+        let comp = version_req.comparators.first()?;
+        Some(Self::from_prerelease(&comp.pre))
+    }
+    fn from_prerelease(pre: &semver::Prerelease) -> Self {
+        if pre.is_empty() {
+            ReleaseTrain::Stable
+        } else if pre.contains("nightly") {
+            ReleaseTrain::Nightly
+        } else if matches!(pre.split(['.', '-']).next(), Some("rc" | "beta" | "alpha")) {
+            ReleaseTrain::Beta
+        } else {
+            ReleaseTrain::Internal
+        }
+    }
+}
+impl std::fmt::Display for ReleaseTrain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReleaseTrain::Stable => write!(f, "stable"),
+            ReleaseTrain::Beta => write!(f, "beta"),
+            ReleaseTrain::Nightly => write!(f, "nightly"),
+            ReleaseTrain::Internal => write!(f, "internal"),
+        }
+    }
+}
+impl FromStr for ReleaseTrain {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stable" => Ok(ReleaseTrain::Stable),
+            "beta" => Ok(ReleaseTrain::Beta),
+            "nightly" => Ok(ReleaseTrain::Nightly),
+            "internal" => Ok(ReleaseTrain::Internal),
+            _ => Err(anyhow::anyhow!(
+                "Invalid release train {:?}, expected one of stable, beta, nightly, internal",
+                s
+            )),
+        }
+    }
+}
+
+#[test]
+fn test_release_train_roundtrip() {
+    for train in [
+        ReleaseTrain::Stable,
+        ReleaseTrain::Beta,
+        ReleaseTrain::Nightly,
+        ReleaseTrain::Internal,
+    ] {
+        assert_eq!(ReleaseTrain::from_str(&train.to_string()).unwrap(), train);
+    }
+    assert!(ReleaseTrain::from_str("unstable").is_err());
+}
+
+#[test]
+fn test_release_train_from_version() {
+    let train = |v| ReleaseTrain::from_version(&semver::Version::parse(v).unwrap());
+    assert_eq!(train("0.3.0"), ReleaseTrain::Stable);
+    assert_eq!(train("0.4.0-rc.1"), ReleaseTrain::Beta);
+    assert_eq!(train("0.4.0-beta"), ReleaseTrain::Beta);
+    assert_eq!(train("0.4.0-alpha.2"), ReleaseTrain::Beta);
+    assert_eq!(train("0.3.0-nightly-2023-10-02"), ReleaseTrain::Nightly);
+    assert_eq!(train("0.3.0-internal-abc"), ReleaseTrain::Internal);
+    assert_eq!(
+        ReleaseTrain::from_version_req(&VersionReq::parse("^0.4.0-rc.1").unwrap()),
+        Some(ReleaseTrain::Beta)
+    );
+    assert_eq!(
+        ReleaseTrain::from_version_req(&VersionReq::parse("0.3").unwrap()),
+        Some(ReleaseTrain::Stable)
+    );
+    assert_eq!(ReleaseTrain::from_version_req(&VersionReq::STAR), None);
+    assert_eq!(
+        ReleaseTrain::from_version_req(&VersionReq::parse(">=0.0.0-0").unwrap()),
+        None
+    );
+}
+
+const DEFAULT_CACHE_TTL_SECONDS: u64 = 60 * 60;
+/// The settings a profile can set; the rest, like the runtime stores, are shared by all profiles
+pub const PROFILE_KEYS: &[&str] = &[
+    "default_runtime",
+    "previous_default_runtime",
+    "channel",
+    "mirror_url",
+    "ca_bundle",
+    "pinned",
+    "pin_reason",
+];
+const DEFAULT_UPDATE_CHECK_INTERVAL_HOURS: u64 = 24;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[non_exhaustive]
+/// The user's settings, stored as JSON at [`settings_path`]
+pub struct Settings {
+    /// The runtime used outside of packages that pin a version
+    pub default_runtime: Option<semver::Version>,
+    /// The default runtime before the last change, for `runtime rollback`
+    pub previous_default_runtime: Option<semver::Version>,
+    /// A system-wide runtimes dir shared between users, e.g. `/opt/ambient/runtimes`
+    pub shared_runtimes_dir: Option<PathBuf>,
+    /// How long the cached list of available versions is used before fetching it again
+    pub cache_ttl_seconds: Option<u64>,
+    /// Timeout for establishing a connection
+    pub http_connect_timeout_secs: Option<u64>,
+    /// Timeout for a whole request, including downloading a runtime
+    pub http_timeout_secs: Option<u64>,
+    /// Extra root certificates (PEM) to trust, e.g. for a proxy that inspects HTTPS traffic
+    pub ca_bundle: Option<PathBuf>,
+    /// Set with `runtime mirror set`: a mirror of the runtime bucket, used instead of
+    /// https://storage.googleapis.com/. The bucket path is appended to it
+    pub mirror_url: Option<String>,
+    /// The release train to follow; defaults to the train of the default runtime
+    pub channel: Option<ReleaseTrain>,
+    /// When `runtime_exec` last checked for a newer stable runtime
+    #[serde(default, with = "chrono::serde::ts_seconds_option")]
+    pub last_update_check: Option<chrono::DateTime<chrono::Utc>>,
+    /// How often to check for a newer stable runtime
+    pub update_check_interval_hours: Option<u64>,
+    /// Set with `runtime pin`; the default runtime can then only be changed with `set-default --force`
+    #[serde(default)]
+    pub pinned: bool,
+    /// Why the default runtime is pinned, shown when a change is refused
+    pub pin_reason: Option<String>,
+    /// Size cap of the cache of downloaded runtime zips, in MB
+    pub download_cache_max_mb: Option<u64>,
+    /// Set with `runtime link`: locally built runtime binaries by name or version, used where they are
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub linked_runtimes: BTreeMap<String, PathBuf>,
+    /// Set with `runtime ignore`; these versions are never offered as updates, e.g. known-broken nightlies
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignored_versions: Vec<semver::Version>,
+    /// Install runtimes without asking for confirmation, like passing `--yes`
+    #[serde(default)]
+    pub assume_yes: bool,
+    /// Replace the CLI process with the runtime on Unix instead of waiting for it, like passing `--exec`
+    #[serde(default)]
+    pub exec_runtime: bool,
+    /// Also write the output of the runtime to a log file per session, listed by `runtime logs`. The CLI has to
+    /// stay around for that, so this takes precedence over `exec_runtime`
+    #[serde(default)]
+    pub capture_logs: bool,
+    /// Size cap of the captured runtime logs, in MB
+    pub logs_max_mb: Option<u64>,
+    /// Environment variables to set for the runtime, on top of the inherited ones. `--env KEY=VALUE` overrides them
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Commands to run after installing and before removing a runtime
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
+    /// Named sets of the [`PROFILE_KEYS`] settings, e.g. a `work` profile with a mirror of internal builds.
+    /// Settings a profile doesn't have fall back to the top-level ones
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, serde_json::Map<String, serde_json::Value>>,
+    /// Set with `runtime profile switch`: the profile used unless another is selected with `--profile` or
+    /// `AMBIENT_PROFILE`
+    pub active_profile: Option<String>,
+    /// The profile in use, whose settings have been applied over the top-level ones; never stored
+    #[serde(skip)]
+    pub profile: Option<String>,
+    /// Set with `--no-hooks` to skip the `hooks`; never stored
+    #[serde(skip)]
+    pub no_hooks: bool,
+    /// Set with `--offline` or `AMBIENT_OFFLINE=1`; never stored
+    #[serde(skip)]
+    pub offline: bool,
+    /// Set with `AMBIENT_VERBOSE=1` to print the download URLs, e.g. to retry a download with curl, and the output
+    /// of `cargo update`; never stored
+    #[serde(skip)]
+    pub verbose: bool,
+    /// Set with `--ignore-lock` to resolve the package's runtime without its `ambient.lock`; never stored
+    #[serde(skip)]
+    pub ignore_lock: bool,
+}
+impl Settings {
+    pub fn load() -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(
+            std::fs::read_to_string(settings_path()?)?.as_str(),
+        )?)
+    }
+    /// The stored settings, or the defaults if there's no settings file yet. Other errors reading or parsing the
+    /// file are returned rather than replaced by the defaults
+    pub fn load_or_default() -> anyhow::Result<Self> {
+        Self::load_or_default_from(&settings_path()?)
+    }
+    fn load_or_default_from(path: &Path) -> anyhow::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("Invalid settings file {:?}", path)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Settings::default()),
+            Err(err) => Err(anyhow::Error::new(err).context(format!("Failed to read {:?}", path))),
+        }
+    }
+    /// Applies the settings of the profile `name`, or else of `AMBIENT_PROFILE` or the active profile, over the
+    /// top-level ones. Done right after loading, since the settings that are never stored are reset
+    pub fn select_profile(&mut self, name: Option<String>) -> anyhow::Result<()> {
+        let name = name
+            .or_else(|| std::env::var("AMBIENT_PROFILE").ok())
+            .filter(|name| !name.is_empty())
+            .or_else(|| self.active_profile.clone());
+        let Some(name) = name else {
+            return Ok(());
+        };
+        *self = self.with_profile(&name)?;
+        self.profile = Some(name);
+        Ok(())
+    }
+    /// These settings as seen through the profile `name`
+    pub fn with_profile(&self, name: &str) -> anyhow::Result<Settings> {
+        let profile = self.profiles.get(name).with_context(|| {
+            format!(
+                "There is no profile `{}`. Create it with `ambient runtime profile create {}`",
+                name, name
+            )
+        })?;
+        let mut settings = settings_object(self)?;
+        for (key, value) in profile {
+            if !PROFILE_KEYS.contains(&key.as_str()) {
+                anyhow::bail!(
+                    "Profile `{}` sets `{}`, which is shared by all profiles. Profiles can set {}",
+                    name,
+                    key,
+                    PROFILE_KEYS.join(", ")
+                );
+            }
+            settings.insert(key.clone(), value.clone());
+        }
+        serde_json::from_value(serde_json::Value::Object(settings))
+            .with_context(|| format!("Invalid profile `{}`", name))
+    }
+    pub fn apply_env(&mut self) {
+        let enabled = |name| std::env::var(name).is_ok_and(|v| !v.is_empty() && v != "0");
+        if enabled("AMBIENT_OFFLINE") {
+            self.offline = true;
+        }
+        if enabled("AMBIENT_VERBOSE") {
+            self.verbose = true;
+        }
+    }
+    pub fn save(&self) -> anyhow::Result<()> {
+        std::fs::create_dir_all(settings_dir()?)?;
+        self.save_to(&settings_path()?)
+    }
+    fn save_to(&self, path: &Path) -> anyhow::Result<()> {
+        environment::write_file_atomically(path, &serde_json::to_string_pretty(self)?)
+    }
+    /// Applies `change` to the stored settings and to `self`. The settings file is locked from loading until
+    /// saving, so that concurrent runs don't overwrite each other's changes
+    pub fn modify(&mut self, change: impl Fn(&mut Settings)) -> anyhow::Result<()> {
+        std::fs::create_dir_all(settings_dir()?)?;
+        self.modify_at(&settings_path()?, change)
+    }
+    fn modify_at(&mut self, path: &Path, change: impl Fn(&mut Settings)) -> anyhow::Result<()> {
+        let _lock = environment::lock_file(path)?;
+        let mut stored = Settings::load_or_default_from(path)?;
+        match &self.profile {
+            Some(name) => stored.modify_profile(name, &change)?,
+            None => change(&mut stored),
+        }
+        stored.save_to(path)?;
+        change(self);
+        Ok(())
+    }
+    /// Applies `change` to these settings as seen through the profile `name`. Changed [`PROFILE_KEYS`] settings
+    /// are stored in the profile, the others at the top level
+    fn modify_profile(&mut self, name: &str, change: impl Fn(&mut Settings)) -> anyhow::Result<()> {
+        let mut effective = self.with_profile(name)?;
+        let before = settings_object(&effective)?;
+        change(&mut effective);
+        let after = settings_object(&effective)?;
+        let changed = before
+            .keys()
+            .chain(after.keys())
+            .unique()
+            .filter(|key| before.get(*key) != after.get(*key))
+            .cloned()
+            .collect_vec();
+        let (profile_keys, shared_keys): (Vec<_>, Vec<_>) = changed
+            .into_iter()
+            .partition(|key| PROFILE_KEYS.contains(&key.as_str()));
+
+        let mut stored = settings_object(self)?;
+        for key in shared_keys {
+            match after.get(&key) {
+                Some(value) => stored.insert(key, value.clone()),
+                None => stored.remove(&key),
+            };
+        }
+        *self = serde_json::from_value(serde_json::Value::Object(stored))?;
+        // The per-profile settings are always serialized, so an unset one is stored as `null` and doesn't fall
+        // back to the top-level setting
+        let profile = self.profiles.entry(name.to_string()).or_default();
+        for key in profile_keys {
+            let value = after.get(&key).cloned().unwrap_or_default();
+            profile.insert(key, value);
+        }
+        Ok(())
+    }
+    pub fn update_check_due(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+        let interval = chrono::Duration::hours(
+            self.update_check_interval_hours
+                .unwrap_or(DEFAULT_UPDATE_CHECK_INTERVAL_HOURS) as i64,
+        );
+        !self.offline
+            && self
+                .last_update_check
+                .is_none_or(|last| now - last >= interval)
+    }
+    /// The command template of `hook`, unless it's unset or hooks are disabled with `--no-hooks`
+    pub fn hook(&self, hook: Hook) -> Option<&str> {
+        if self.no_hooks {
+            return None;
+        }
+        match hook {
+            Hook::PostInstall => self.hooks.post_install.as_deref(),
+            Hook::PreUninstall => self.hooks.pre_uninstall.as_deref(),
+        }
+    }
+    pub fn cache_ttl_seconds(&self) -> u64 {
+        self.cache_ttl_seconds.unwrap_or(DEFAULT_CACHE_TTL_SECONDS)
+    }
+    /// The runtime stores in lookup order: the shared dir (if configured) first, then the per-user dir
+    pub fn runtime_stores(&self) -> anyhow::Result<Vec<(RuntimeStore, PathBuf)>> {
+        let mut stores = Vec::new();
+        if let Some(shared) = &self.shared_runtimes_dir {
+            stores.push((RuntimeStore::Shared, shared.clone()));
+        }
+        stores.push((RuntimeStore::User, runtimes_dir()?));
+        Ok(stores)
+    }
+    /// The binary linked as `name`, failing when it has been moved or deleted since it was linked
+    pub fn linked_runtime(&self, name: &str) -> anyhow::Result<Option<&Path>> {
+        let Some(path) = self.linked_runtimes.get(name) else {
+            return Ok(None);
+        };
+        if !path.is_file() {
+            anyhow::bail!(
+                "Runtime `{name}` is linked to {path:?}, which doesn't exist anymore. Rebuild it, point the link \
+                 at its new location with `ambient runtime link {name} <path>`, or remove the link with \
+                 `ambient runtime unlink {name}`"
+            );
+        }
+        Ok(Some(path))
+    }
+    /// The linked runtimes named by a version, like `0.4.0-dev`, which can be used like installed versions
+    pub fn linked_versions(&self) -> Vec<semver::Version> {
+        self.linked_runtimes
+            .keys()
+            .filter_map(|name| semver::Version::parse(name).ok())
+            .collect()
+    }
+    /// Problems with the settings, as warnings to show. An invalid `mirror_url` is an error instead, since nothing
+    /// can be listed or downloaded with it
+    pub fn validate(&self) -> anyhow::Result<Vec<String>> {
+        let known = cached_versions().map(|mut known| {
+            known.extend(
+                list_installed_runtimes(self)
+                    .into_iter()
+                    .flatten()
+                    .map(|r| r.version),
+            );
+            known.extend(self.linked_versions());
+            known
+        });
+        self.validate_with(known.as_deref())
+    }
+    /// Fails if `mirror_url` is invalid, the one check of [`Self::validate`] that's cheap enough for every run
+    pub fn validate_mirror_url(&self) -> anyhow::Result<()> {
+        if let Some(url) = &self.mirror_url {
+            if let Err(err) = parse_mirror_url(url) {
+                anyhow::bail!(
+                    "{:#}. Set a valid mirror with `ambient runtime mirror set <url>`, or remove it with \
+                     `ambient runtime mirror remove`",
+                    err
+                );
+            }
+        }
+        Ok(())
+    }
+    /// Like [`Self::validate`], with the `known` versions if there's a version listing to check against
+    fn validate_with(&self, known: Option<&[semver::Version]>) -> anyhow::Result<Vec<String>> {
+        self.validate_mirror_url()?;
+        let mut warnings = Vec::new();
+        if let Some(default) = &self.default_runtime {
+            if known.is_some_and(|known| !known.contains(default)) {
+                warnings.push(format!(
+                    "The default runtime {} is neither installed nor available on the server",
+                    default
+                ));
+            }
+            if self.ignored_versions.contains(default) {
+                warnings.push(format!(
+                    "The default runtime {} is also ignored; run `ambient runtime unignore {}` if it shouldn't be",
+                    default, default
+                ));
+            }
+        }
+        if self.cache_ttl_seconds == Some(0) {
+            warnings.push(
+                "cache_ttl_seconds is 0, so the version list is fetched again on every run"
+                    .to_string(),
+            );
+        }
+        Ok(warnings)
+    }
+    /// Fails if the default runtime is pinned and `new_default` would change it
+    pub fn check_pin(&self, new_default: &semver::Version) -> anyhow::Result<()> {
+        let Some(current) = self.default_runtime.as_ref().filter(|_| self.pinned) else {
+            return Ok(());
+        };
+        if current == new_default {
+            return Ok(());
+        }
+        let reason = self
+            .pin_reason
+            .as_ref()
+            .map(|reason| format!(" ({})", reason))
+            .unwrap_or_default();
+        anyhow::bail!(
+            "The default runtime is pinned to {}{}, so it was not changed to {}. \
+             Run `ambient runtime unpin` to allow changes, or use `ambient runtime set-default --force`",
+            current,
+            reason,
+            new_default
+        )
+    }
+    pub fn release_train(&self) -> ReleaseTrain {
+        self.channel.unwrap_or_else(|| {
+            self.default_runtime
+                .as_ref()
+                .map(ReleaseTrain::from_version)
+                .unwrap_or(ReleaseTrain::Stable)
+        })
+    }
+}
+
+fn settings_object(
+    settings: &Settings,
+) -> anyhow::Result<serde_json::Map<String, serde_json::Value>> {
+    match serde_json::to_value(settings)? {
+        serde_json::Value::Object(object) => Ok(object),
+        _ => unreachable!("settings serialize to an object"),
+    }
+}
+
+/// Whether `version_req` opts into every pre-release by using the lowest possible one, as in `>=0.0.0-0`
+fn allows_all_prereleases(version_req: &VersionReq) -> bool {
+    version_req
+        .comparators
+        .iter()
+        .any(|comp| comp.pre.as_str() == "0")
+}
+
+/// Like `comp.matches(version)`, but without semver's rule that pre-releases only match comparators with the
+/// same major.minor.patch. A comparator with a pre-release always has all of major.minor.patch.
+fn matches_any_prerelease(comp: &semver::Comparator, version: &semver::Version) -> bool {
+    let bound = semver::Version {
+        major: comp.major,
+        minor: comp.minor.unwrap_or(0),
+        patch: comp.patch.unwrap_or(0),
+        pre: comp.pre.clone(),
+        build: semver::BuildMetadata::EMPTY,
+    };
+    let release = semver::Version::new(version.major, version.minor, version.patch);
+    match comp.op {
+        semver::Op::Greater if comp.patch.is_some() => *version > bound,
+        semver::Op::GreaterEq => *version >= bound,
+        semver::Op::Less => *version < bound,
+        semver::Op::LessEq if comp.patch.is_some() => *version <= bound,
+        _ => comp.matches(&release) && *version >= bound,
+    }
+}
+
+/// If the version requirement contains a pre-release identifier, only versions with the same pre-release identifier will be considered.
+/// A requirement like `>=0.0.0-0` matches every version, pre-releases included.
+pub fn matches_exact(version_req: &VersionReq, version: &semver::Version) -> bool {
+    if allows_all_prereleases(version_req) {
+        return version_req
+            .comparators
+            .iter()
+            .all(|comp| matches_any_prerelease(comp, version));
+    }
+    for comp in &version_req.comparators {
+        if !comp.pre.is_empty() || !version.pre.is_empty() {
+            return comp.matches(version) && comp.pre == version.pre;
+        }
+    }
+    version_req.matches(version)
+}
+
+#[test]
+fn test_update_check_due() {
+    let now = chrono::Utc::now();
+    let mut settings = Settings::default();
+    assert!(settings.update_check_due(now));
+    settings.last_update_check = Some(now - chrono::Duration::hours(23));
+    assert!(!settings.update_check_due(now));
+    settings.last_update_check = Some(now - chrono::Duration::hours(24));
+    assert!(settings.update_check_due(now));
+    settings.update_check_interval_hours = Some(48);
+    assert!(!settings.update_check_due(now));
+    settings.last_update_check = None;
+    settings.offline = true;
+    assert!(!settings.update_check_due(now));
+}
+
+#[test]
+fn test_save_settings_atomically() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.json");
+    let settings = Settings {
+        default_runtime: Some(semver::Version::parse("0.3.0").unwrap()),
+        ..Default::default()
+    };
+    settings.save_to(&path).unwrap();
+    let saved = std::fs::read_to_string(&path).unwrap();
+
+    // A save that fails, here because the directory is read-only, leaves the saved settings as they were
+    let writable = std::fs::metadata(dir.path()).unwrap().permissions();
+    let mut read_only = writable.clone();
+    read_only.set_readonly(true);
+    std::fs::set_permissions(dir.path(), read_only).unwrap();
+    // Permissions aren't enforced when running as root
+    if !environment::is_dir_writable(dir.path()) {
+        let failing = Settings {
+            default_runtime: Some(semver::Version::parse("0.3.2").unwrap()),
+            ..settings.clone()
+        };
+        assert!(failing.save_to(&path).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), saved);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+    std::fs::set_permissions(dir.path(), writable).unwrap();
+
+    let settings = Settings {
+        default_runtime: Some(semver::Version::parse("0.3.1").unwrap()),
+        ..settings
+    };
+    settings.save_to(&path).unwrap();
+    let loaded: Settings = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(loaded.default_runtime, settings.default_runtime);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn test_runtimes_to_prune() {
+    let runtime = |version: &str, store| InstalledRuntime {
+        version: semver::Version::parse(version).unwrap(),
+        dir: PathBuf::from(version),
+        store,
+    };
+    let installed = vec![
+        runtime("0.2.0", RuntimeStore::User),
+        runtime("0.3.0", RuntimeStore::User),
+        runtime("0.1.0", RuntimeStore::Shared),
+        runtime("0.3.1", RuntimeStore::User),
+        runtime("0.2.1", RuntimeStore::User),
+    ];
+    let settings = Settings {
+        default_runtime: Some(semver::Version::parse("0.2.0").unwrap()),
+        ..Default::default()
+    };
+    let pruned = runtimes_to_prune(&settings, installed, 2)
+        .into_iter()
+        .map(|r| r.version.to_string())
+        .collect_vec();
+    assert_eq!(pruned, ["0.2.1"]);
+}
+
+#[test]
+fn test_load_or_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.json");
+    let settings = Settings::load_or_default_from(&path).unwrap();
+    assert_eq!(settings.default_runtime, None);
+
+    std::fs::write(&path, r#"{"default_runtime": "0.3.0"}"#).unwrap();
+    let settings = Settings::load_or_default_from(&path).unwrap();
+    assert_eq!(
+        settings.default_runtime,
+        Some(semver::Version::new(0, 3, 0))
+    );
+
+    std::fs::write(&path, "{not json").unwrap();
+    let err = Settings::load_or_default_from(&path).unwrap_err();
+    assert!(
+        err.to_string().starts_with("Invalid settings file"),
+        "{}",
+        err
+    );
+    // Other IO errors than a missing file aren't hidden either
+    assert!(Settings::load_or_default_from(dir.path()).is_err());
+}
+
+#[test]
+fn test_profiles() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.json");
+    std::fs::write(
+        &path,
+        r#"{
+            "default_runtime": "0.3.0",
+            "channel": "stable",
+            "assume_yes": true,
+            "profiles": {"work": {"mirror_url": "https://mirror.example.com/", "channel": "internal"}}
+        }"#,
+    )
+    .unwrap();
+    let mut settings = Settings::load_or_default_from(&path).unwrap();
+    settings.select_profile(Some("work".into())).unwrap();
+    assert_eq!(settings.profile.as_deref(), Some("work"));
+    assert_eq!(
+        settings.mirror_url.as_deref(),
+        Some("https://mirror.example.com/")
+    );
+    assert_eq!(settings.channel, Some(ReleaseTrain::Internal));
+    // Settings the profile doesn't have fall back to the top-level ones
+    assert_eq!(
+        settings.default_runtime,
+        Some(semver::Version::new(0, 3, 0))
+    );
+    assert!(settings.assume_yes);
+
+    // Per-profile changes go to the profile, shared ones to the top level
+    settings
+        .modify_at(&path, |settings| {
+            settings.default_runtime = Some(semver::Version::new(0, 4, 0));
+            settings.mirror_url = None;
+            settings.assume_yes = false;
+        })
+        .unwrap();
+    let stored = Settings::load_or_default_from(&path).unwrap();
+    assert_eq!(stored.default_runtime, Some(semver::Version::new(0, 3, 0)));
+    assert!(!stored.assume_yes);
+    let work = stored.with_profile("work").unwrap();
+    assert_eq!(work.default_runtime, Some(semver::Version::new(0, 4, 0)));
+    assert_eq!(work.mirror_url, None);
+    assert_eq!(work.channel, Some(ReleaseTrain::Internal));
+    assert_eq!(settings.default_runtime, work.default_runtime);
+
+    let mut settings = Settings::load_or_default_from(&path).unwrap();
+    let err = settings.select_profile(Some("home".into())).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "There is no profile `home`. Create it with `ambient runtime profile create home`"
+    );
+    let mut settings = Settings::default();
+    settings.profiles.insert(
+        "work".into(),
+        serde_json::from_str(r#"{"assume_yes": true}"#).unwrap(),
+    );
+    assert!(settings.with_profile("work").is_err());
+}
+
+#[test]
+fn test_validate_settings() {
+    let version = |v| semver::Version::parse(v).unwrap();
+    let settings = Settings {
+        default_runtime: Some(version("0.3.0")),
+        ..Default::default()
+    };
+    assert!(settings.validate_with(None).unwrap().is_empty());
+    assert!(settings
+        .validate_with(Some(&[version("0.3.0")]))
+        .unwrap()
+        .is_empty());
+
+    let settings = Settings {
+        default_runtime: Some(version("0.9.0")),
+        ignored_versions: vec![version("0.9.0")],
+        cache_ttl_seconds: Some(0),
+        ..Default::default()
+    };
+    assert_eq!(
+        settings.validate_with(Some(&[version("0.3.0")])).unwrap(),
+        [
+            "The default runtime 0.9.0 is neither installed nor available on the server",
+            "The default runtime 0.9.0 is also ignored; run `ambient runtime unignore 0.9.0` if it shouldn't be",
+            "cache_ttl_seconds is 0, so the version list is fetched again on every run",
+        ]
+    );
+
+    let settings = Settings {
+        mirror_url: Some("ftp://mirror.example.com/".to_string()),
+        ..Default::default()
+    };
+    assert!(settings.validate_with(None).is_err());
+}
+
+#[test]
+fn test_modify_settings_concurrently() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.json");
+    std::thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| {
+                for _ in 0..20 {
+                    Settings::default()
+                        .modify_at(&path, |settings| {
+                            settings.cache_ttl_seconds =
+                                Some(settings.cache_ttl_seconds.unwrap_or(0) + 1)
+                        })
+                        .unwrap();
+                    if let Ok(content) = std::fs::read_to_string(&path) {
+                        serde_json::from_str::<Settings>(&content).unwrap();
+                    }
+                }
+            });
+        }
+    });
+    let settings: Settings =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(settings.cache_ttl_seconds, Some(8 * 20));
+}
+
+#[test]
+fn test_check_pin() {
+    let version = |v: &str| semver::Version::parse(v).unwrap();
+    let mut settings = Settings {
+        default_runtime: Some(version("0.3.0")),
+        ..Default::default()
+    };
+    assert!(settings.check_pin(&version("0.3.1")).is_ok());
+    settings.pinned = true;
+    settings.pin_reason = Some("release cycle".to_string());
+    assert!(settings.check_pin(&version("0.3.0")).is_ok());
+    let err = settings.check_pin(&version("0.3.1")).unwrap_err();
+    assert!(err.to_string().starts_with(
+        "The default runtime is pinned to 0.3.0 (release cycle), so it was not changed to 0.3.1."
+    ));
+}
+
+#[test]
+fn test_matches_exact() {
+    let matches = |req: &str, version: &str| {
+        matches_exact(
+            &VersionReq::parse(req).unwrap(),
+            &semver::Version::parse(version).unwrap(),
+        )
+    };
+    // Plain requirements follow semver and skip pre-releases
+    assert!(matches("0.3", "0.3.1"));
+    assert!(!matches("0.3", "0.3.1-nightly-2023-10-01"));
+    assert!(matches("*", "0.3.1"));
+    assert!(!matches("*", "0.4.0-rc.1"));
+    // A pre-release requirement only matches that pre-release
+    assert!(matches(
+        "0.3.1-nightly-2023-10-01",
+        "0.3.1-nightly-2023-10-01"
+    ));
+    assert!(!matches(
+        "0.3.1-nightly-2023-10-01",
+        "0.3.1-nightly-2023-10-02"
+    ));
+    // `-0` opts into every pre-release
+    for version in ["0.1.0", "0.3.1-nightly-2023-10-01", "0.4.0-rc.1", "1.0.0"] {
+        assert!(matches(">=0.0.0-0", version), "{}", version);
+    }
+    assert!(matches("^0.3.0-0", "0.3.2-nightly-2023-10-01"));
+    assert!(!matches("^0.3.0-0", "0.4.0-rc.1"));
+    assert!(matches(">=0.3.0-0, <0.4.0-0", "0.3.0-nightly-2023-09-01"));
+    assert!(!matches(">=0.3.0-0, <0.4.0-0", "0.4.0-rc.1"));
+}
+
+/// Where a version requirement came from, so that errors can point at it
+#[derive(Debug, Clone, PartialEq)]
+pub enum RequirementOrigin {
+    /// `ambient_version` in this ambient.toml
+    Manifest(PathBuf),
+    Settings,
+    /// A version given on the command line
+    Flag,
+}
+impl std::fmt::Display for RequirementOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequirementOrigin::Manifest(path) => write!(f, "ambient_version in {}", path.display()),
+            RequirementOrigin::Settings => write!(f, "the settings"),
+            RequirementOrigin::Flag => write!(f, "the command line"),
+        }
+    }
+}
+
+/// Where a resolved runtime version came from, in the order resolution tries them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResolutionTier {
+    /// The package's `ambient.lock`
+    Lock,
+    /// The default runtime version in the settings
+    Default,
+    Installed,
+    /// The versions available for download
+    Remote,
+}
+impl std::fmt::Display for ResolutionTier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResolutionTier::Lock => write!(f, "lock"),
+            ResolutionTier::Default => write!(f, "default"),
+            ResolutionTier::Installed => write!(f, "installed"),
+            ResolutionTier::Remote => write!(f, "remote"),
+        }
+    }
+}
+
+fn get_version_satisfying_req(
+    settings: &Settings,
+    source: &dyn VersionSource,
+    version_req: &VersionReq,
+    origin: &RequirementOrigin,
+) -> anyhow::Result<(RuntimeVersion, ResolutionTier)> {
+    let installed = list_installed_runtimes(settings)?
+        .into_iter()
+        .map(|r| r.version)
+        .chain(settings.linked_versions())
+        .collect_vec();
+    resolve_version_req(
+        version_req,
+        origin,
+        settings.default_runtime.as_ref(),
+        &installed,
+        settings.release_train(),
+        source,
+        &source.deprecations()?.yanked(),
+    )
+}
+
+/// `source` without the `excluded` versions
+struct ExcludingSource<'a> {
+    source: &'a dyn VersionSource,
+    excluded: &'a [semver::Version],
+}
+impl VersionSource for ExcludingSource<'_> {
+    fn list(&self, filter: VersionsFilter) -> anyhow::Result<Vec<RuntimeVersion>> {
+        let mut versions = self.source.list(filter)?;
+        versions.retain(|v| !self.excluded.contains(&v.version));
+        Ok(versions)
+    }
+    fn deprecations(&self) -> anyhow::Result<Deprecations> {
+        self.source.deprecations()
+    }
+}
+
+/// Resolves `version_req` like [`resolve_version_req_among`], skipping the `yanked` versions unless nothing else
+/// satisfies it
+fn resolve_version_req(
+    version_req: &VersionReq,
+    origin: &RequirementOrigin,
+    default: Option<&semver::Version>,
+    installed: &[semver::Version],
+    train: ReleaseTrain,
+    source: &dyn VersionSource,
+    yanked: &[semver::Version],
+) -> anyhow::Result<(RuntimeVersion, ResolutionTier)> {
+    if !yanked.is_empty() {
+        let usable = installed
+            .iter()
+            .filter(|v| !yanked.contains(v))
+            .cloned()
+            .collect_vec();
+        let resolved = resolve_version_req_among(
+            version_req,
+            origin,
+            default.filter(|v| !yanked.contains(v)),
+            &usable,
+            train,
+            &ExcludingSource {
+                source,
+                excluded: yanked,
+            },
+        );
+        match resolved {
+            Ok(resolved) => return Ok(resolved),
+            Err(err) => log::info!(
+                "Nothing but yanked versions satisfies {}: {:#}",
+                version_req,
+                err
+            ),
+        }
+    }
+    resolve_version_req_among(version_req, origin, default, installed, train, source)
+}
+
+/// Looks for a version satisfying `version_req` in the default runtime, then the installed ones, then `source`.
+/// `*` instead picks the latest installed version in `train`, or the latest available one.
+fn resolve_version_req_among(
+    version_req: &VersionReq,
+    origin: &RequirementOrigin,
+    default: Option<&semver::Version>,
+    installed: &[semver::Version],
+    train: ReleaseTrain,
+    source: &dyn VersionSource,
+) -> anyhow::Result<(RuntimeVersion, ResolutionTier)> {
+    if version_req.comparators.is_empty() {
+        log::info!(
+            "{} matches any version; using the latest installed {} runtime",
+            version_req,
+            train
+        );
+        let installed = installed
+            .iter()
+            .cloned()
+            .map(RuntimeVersion::without_builds)
+            .collect_vec();
+        if let Some(version) = latest_version_for_train(&installed, train, false) {
+            return Ok((version, ResolutionTier::Installed));
+        }
+        log::info!(
+            "No {} runtime installed, using the latest available one",
+            train
+        );
+        return Ok((
+            get_latest_remote_version_for_train(source, train, false, None, &[])?,
+            ResolutionTier::Remote,
+        ));
+    }
+    log::info!("Looking for version satisfying {}", version_req);
+    if allows_all_prereleases(version_req) {
+        log::info!(
+            "{} opts into pre-releases of every release train",
+            version_req
+        );
+    }
+    if let Some(default_version) = default {
+        log::info!("Checking default version: {}", default_version);
+        if matches_exact(version_req, default_version) {
+            log::info!("Default version matches, returning.");
+            return Ok((
+                RuntimeVersion::without_builds(default_version.clone()),
+                ResolutionTier::Default,
+            ));
+        }
+    }
+    // The newest match wins in each tier, rather than whichever comes first
+    log::info!("Checking installed versions");
+    if let Some(version) = installed
+        .iter()
+        .filter(|v| matches_exact(version_req, v))
+        .max_by(|a, b| train_order_key(a).cmp(&train_order_key(b)))
+    {
+        return Ok((
+            RuntimeVersion::without_builds(version.clone()),
+            ResolutionTier::Installed,
+        ));
+    }
+    log::info!("Checking all versions");
+    let available = source.list(VersionsFilter::all())?;
+    if let Some(version) = available
+        .iter()
+        .filter(|v| matches_exact(version_req, &v.version))
+        .max_by(|a, b| train_order_key(&a.version).cmp(&train_order_key(&b.version)))
+    {
+        return Ok((version.clone(), ResolutionTier::Remote));
+    }
+    Err(unsatisfiable_requirement(version_req, origin, &available))
+}
+
+/// Explains what is available instead of `version_req`, and how to switch to it
+fn unsatisfiable_requirement(
+    version_req: &VersionReq,
+    origin: &RequirementOrigin,
+    available: &[RuntimeVersion],
+) -> anyhow::Error {
+    let mut message = format!(
+        "No version found satisfying {} (from {})",
+        version_req, origin
+    );
+    let latest = |train| latest_version_for_train(available, train, false).map(|v| v.version);
+    let latest_stable = latest(ReleaseTrain::Stable);
+    let latest_nightly = latest(ReleaseTrain::Nightly);
+    for (train, latest) in [("stable", &latest_stable), ("nightly", &latest_nightly)] {
+        if let Some(latest) = latest {
+            message += &format!("\n  Latest {} version: {}", train, latest);
+        }
+    }
+    if let Some(train) = ReleaseTrain::from_version_req(version_req) {
+        if !available
+            .iter()
+            .any(|v| ReleaseTrain::from_version(&v.version) == train)
+        {
+            message += &format!(
+                "\n  {} asks for a {} version, but there are none",
+                version_req, train
+            );
+        }
+    }
+    let versions = available.iter().map(|v| v.version.clone()).collect_vec();
+    let closest = closest_versions(version_req, &versions);
+    if let Some(nearest) = nearest_version(version_req, &closest) {
+        let command = match origin {
+            RequirementOrigin::Manifest(_) => "set-local",
+            RequirementOrigin::Settings => "set-default",
+            RequirementOrigin::Flag => "install",
+        };
+        message += &format!(
+            "\n  The closest versions are {}. To use {}, run `ambient runtime {} {}`",
+            closest.iter().join(", "),
+            nearest,
+            command,
+            nearest
+        );
+    }
+    anyhow::anyhow!(message)
+}
+
+/// Of the `closest` versions, prefers one with the same major.minor as `version_req`, then an older one
+fn nearest_version<'a>(
+    version_req: &VersionReq,
+    closest: &'a [semver::Version],
+) -> Option<&'a semver::Version> {
+    let comp = version_req.comparators.first()?;
+    closest
+        .iter()
+        .filter(|v| v.major == comp.major && Some(v.minor) == comp.minor)
+        .max()
+        .or_else(|| {
+            closest
+                .iter()
+                .filter(|v| (v.major, Some(v.minor)) < (comp.major, comp.minor))
+                .max()
+        })
+        .or_else(|| closest.first())
+}
+
+/// The versions nearest to what `version_req` asks for, ascending: up to two below and two above it
+pub fn closest_versions(
+    version_req: &VersionReq,
+    versions: &[semver::Version],
+) -> Vec<semver::Version> {
+    let Some(comp) = version_req.comparators.first() else {
+        return Vec::new();
+    };
+    let target = semver::Version {
+        major: comp.major,
+        minor: comp.minor.unwrap_or(0),
+        patch: comp.patch.unwrap_or(0),
+        pre: comp.pre.clone(),
+        build: semver::BuildMetadata::EMPTY,
+    };
+    let sorted = versions.iter().sorted().dedup().collect_vec();
+    let split = sorted.partition_point(|v| **v < target);
+    let below = &sorted[split.saturating_sub(2)..split];
+    let above = &sorted[split..(split + 2).min(sorted.len())];
+    below.iter().chain(above).map(|v| (*v).clone()).collect()
+}
+
+#[test]
+fn test_closest_versions() {
+    let versions = ["0.2.0", "0.2.1", "0.3.0", "0.3.1", "0.4.0", "0.5.0"]
+        .map(|v| semver::Version::parse(v).unwrap());
+    let closest = |req| {
+        closest_versions(&VersionReq::parse(req).unwrap(), &versions)
+            .iter()
+            .map(|v| v.to_string())
+            .collect_vec()
+    };
+    assert_eq!(closest("^0.3.2"), ["0.3.0", "0.3.1", "0.4.0", "0.5.0"]);
+    assert_eq!(closest("0.1"), ["0.2.0", "0.2.1"]);
+    assert_eq!(closest("0.9"), ["0.4.0", "0.5.0"]);
+}
+
+/// The latest version in `release_train`, skipping `ignored` versions, and yanked ones unless nothing else is left.
+/// With `compatible_with`, only versions whose major.minor.patch satisfies it are considered, so that pre-releases
+/// can be constrained too.
+pub fn get_latest_remote_version_for_train(
+    source: &dyn VersionSource,
+    release_train: ReleaseTrain,
+    fallback_to_nightly: bool,
+    compatible_with: Option<&VersionReq>,
+    ignored: &[semver::Version],
+) -> anyhow::Result<RuntimeVersion> {
+    latest_remote_version_for_train(
+        source,
+        release_train,
+        fallback_to_nightly,
+        compatible_with,
+        ignored,
+        &source.deprecations()?.yanked(),
+    )
+}
+
+fn latest_remote_version_for_train(
+    source: &dyn VersionSource,
+    release_train: ReleaseTrain,
+    fallback_to_nightly: bool,
+    compatible_with: Option<&VersionReq>,
+    ignored: &[semver::Version],
+    yanked: &[semver::Version],
+) -> anyhow::Result<RuntimeVersion> {
+    let mut filter = VersionsFilter::for_train(release_train);
+    filter.include_nightly |= fallback_to_nightly;
+    let mut versions = source.list(filter)?;
+    versions.retain(|v| !ignored.contains(&v.version));
+    if let Some(req) = compatible_with {
+        versions.retain(|v| {
+            req.matches(&semver::Version::new(
+                v.version.major,
+                v.version.minor,
+                v.version.patch,
+            ))
+        });
+    }
+    let usable = versions
+        .iter()
+        .filter(|v| !yanked.contains(&v.version))
+        .cloned()
+        .collect_vec();
+    latest_version_for_train(&usable, release_train, fallback_to_nightly)
+        .or_else(|| latest_version_for_train(&versions, release_train, fallback_to_nightly))
+        .with_context(|| match compatible_with {
+            Some(req) => format!(
+                "No compatible update found: no {} version matches {}",
+                release_train, req
+            ),
+            None => format!("No versions found for the {} release train", release_train),
+        })
+}
+
+/// The version `update-default` moves to: the latest in `train`, or else in the train of the settings. When the train
+/// was inferred and has no versions, e.g. for an internal default, the error explains how to pick one
+pub fn get_default_update(
+    source: &dyn VersionSource,
+    settings: &Settings,
+    train: Option<ReleaseTrain>,
+) -> anyhow::Result<RuntimeVersion> {
+    if let Some(train) = train {
+        return get_latest_remote_version_for_train(
+            source,
+            train,
+            false,
+            None,
+            &settings.ignored_versions,
+        );
+    }
+    let train = settings.release_train();
+    get_latest_remote_version_for_train(source, train, false, None, &settings.ignored_versions)
+        .with_context(|| {
+            let reason = match (&settings.channel, &settings.default_runtime) {
+                (Some(_), _) | (None, None) => format!("the channel setting is {}", train),
+                (None, Some(default)) => {
+                    format!("the default runtime {} is on the {} release train", default, train)
+                }
+            };
+            format!(
+                "update-default looks for {} versions because {}. \
+                 Pick a release train with `ambient runtime update-default --train stable` or `--train nightly`",
+                train, reason
+            )
+        })
+}
+
+/// Restricts updates of `current` to the same major version (`minor`) or the same major.minor (`patch`)
+pub fn update_constraint(
+    current: &semver::Version,
+    minor: bool,
+    patch: bool,
+) -> Option<VersionReq> {
+    let req = if patch {
+        format!("={}.{}", current.major, current.minor)
+    } else if minor {
+        format!("={}", current.major)
+    } else {
+        return None;
+    };
+    Some(VersionReq::parse(&req).expect("partial versions are valid requirements"))
+}
+
+#[cfg(test)]
+fn resolve_with(
+    req: &str,
+    default: Option<&str>,
+    installed: &[&str],
+    source: &versions::FakeSource,
+) -> anyhow::Result<String> {
+    resolve_with_yanked(req, default, installed, source, &[])
+}
+#[cfg(test)]
+fn resolve_with_yanked(
+    req: &str,
+    default: Option<&str>,
+    installed: &[&str],
+    source: &versions::FakeSource,
+    yanked: &[&str],
+) -> anyhow::Result<String> {
+    let parse = |v: &str| semver::Version::parse(v).unwrap();
+    let default = default.map(parse);
+    let installed = installed.iter().map(|v| parse(v)).collect_vec();
+    let yanked = yanked.iter().map(|v| parse(v)).collect_vec();
+    let train = default
+        .as_ref()
+        .map(ReleaseTrain::from_version)
+        .unwrap_or(ReleaseTrain::Stable);
+    resolve_version_req(
+        &VersionReq::parse(req).unwrap(),
+        &RequirementOrigin::Manifest(PathBuf::from("ambient.toml")),
+        default.as_ref(),
+        &installed,
+        train,
+        source,
+        &yanked,
+    )
+    .map(|(v, _)| v.version.to_string())
+}
+
+#[test]
+fn test_resolve_version_req() {
+    let source = versions::FakeSource::new(&[
+        "0.2.1",
+        "0.3.0",
+        "0.3.1",
+        "0.4.0-rc.1",
+        "0.3.1-nightly-2023-10-01",
+        "0.3.2-nightly-2023-10-05",
+    ]);
+    // The default runtime wins, then installed versions, then the source
+    assert_eq!(
+        resolve_with("0.3", Some("0.3.0"), &["0.3.1"], &source).unwrap(),
+        "0.3.0"
+    );
+    assert_eq!(
+        resolve_with("0.3", Some("0.2.1"), &["0.3.1"], &source).unwrap(),
+        "0.3.1"
+    );
+    assert_eq!(resolve_with("0.2", None, &[], &source).unwrap(), "0.2.1");
+    // Pre-release requirements only match that exact pre-release
+    assert_eq!(
+        resolve_with("0.3.1-nightly-2023-10-01", None, &[], &source).unwrap(),
+        "0.3.1-nightly-2023-10-01"
+    );
+    // `*` follows the train of the default runtime, preferring installed versions
+    assert_eq!(
+        resolve_with("*", Some("0.3.1-nightly-2023-10-01"), &["0.3.0"], &source).unwrap(),
+        "0.3.2-nightly-2023-10-05"
+    );
+    assert_eq!(
+        resolve_with("*", None, &["0.2.1", "0.3.0"], &source).unwrap(),
+        "0.3.0"
+    );
+    assert_eq!(
+        resolve_with(">=0.4.0-0", None, &[], &source).unwrap(),
+        "0.4.0-rc.1"
+    );
+    // The newest of several matches wins, whatever order they're listed in
+    let several = versions::FakeSource::new(&["0.3.1", "0.3.2", "0.3.4", "0.3.3", "0.4.0"]);
+    assert_eq!(
+        resolve_with("^0.3.2", None, &[], &several).unwrap(),
+        "0.3.4"
+    );
+    assert_eq!(
+        resolve_with("^0.3.2", None, &["0.3.2", "0.3.3", "0.3.1"], &several).unwrap(),
+        "0.3.3"
+    );
+
+    let err = resolve_with("0.9", None, &["0.3.0"], &source).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "No version found satisfying ^0.9 (from ambient_version in ambient.toml)
+  Latest stable version: 0.3.1
+  Latest nightly version: 0.3.2-nightly-2023-10-05
+  The closest versions are 0.3.2-nightly-2023-10-05, 0.4.0-rc.1. To use 0.4.0-rc.1, run `ambient runtime set-local 0.4.0-rc.1`"
+    );
+}
+
+#[test]
+fn test_resolve_version_req_skips_yanked() {
+    let source = versions::FakeSource::new(&["0.3.0", "0.3.1", "0.4.0"]);
+    // The yanked default and installed versions are passed over for an available one
+    assert_eq!(
+        resolve_with_yanked("0.3", Some("0.3.0"), &["0.3.0"], &source, &["0.3.0"]).unwrap(),
+        "0.3.1"
+    );
+    assert_eq!(
+        resolve_with_yanked("0.4", None, &[], &source, &["0.4.0"]).unwrap(),
+        "0.4.0"
+    );
+    assert_eq!(
+        resolve_with_yanked("*", None, &["0.4.0"], &source, &["0.4.0"]).unwrap(),
+        "0.3.1"
+    );
+}
+
+#[test]
+fn test_linked_runtimes() {
+    let dir = tempfile::tempdir().unwrap();
+    let exe_path = dir.path().join("ambient");
+    std::fs::write(&exe_path, "").unwrap();
+    let mut settings = Settings::default();
+    settings
+        .linked_runtimes
+        .insert("0.4.0-dev".to_string(), exe_path.clone());
+    settings
+        .linked_runtimes
+        .insert("local".to_string(), exe_path.clone());
+    assert_eq!(settings.linked_versions().len(), 1);
+    let linked = RuntimeVersion::without_builds(semver::Version::parse("0.4.0-dev").unwrap());
+    assert_eq!(
+        linked.installed(&settings).unwrap(),
+        Some((RuntimeStore::Linked, exe_path.clone()))
+    );
+    assert_eq!(
+        settings.linked_runtime("local").unwrap(),
+        Some(exe_path.as_path())
+    );
+
+    std::fs::remove_file(&exe_path).unwrap();
+    let err = linked.installed(&settings).unwrap_err().to_string();
+    assert!(
+        err.starts_with("Runtime `0.4.0-dev` is linked to"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_resolution_tier() {
+    let source = versions::FakeSource::new(&["0.3.0", "0.3.1", "0.4.0"]);
+    let parse = |v: &str| semver::Version::parse(v).unwrap();
+    let tier = |req: &str, default: &str, installed: &[&str]| {
+        let installed = installed.iter().map(|v| parse(v)).collect_vec();
+        resolve_version_req(
+            &VersionReq::parse(req).unwrap(),
+            &RequirementOrigin::Flag,
+            Some(&parse(default)),
+            &installed,
+            ReleaseTrain::Stable,
+            &source,
+            &[],
+        )
+        .unwrap()
+        .1
+    };
+    assert_eq!(tier("0.3", "0.3.0", &["0.3.1"]), ResolutionTier::Default);
+    assert_eq!(tier("0.3", "0.2.0", &["0.3.1"]), ResolutionTier::Installed);
+    assert_eq!(tier("0.4", "0.3.0", &["0.3.1"]), ResolutionTier::Remote);
+    assert_eq!(tier("*", "0.3.0", &["0.3.1"]), ResolutionTier::Installed);
+    assert_eq!(tier("*", "0.3.0", &[]), ResolutionTier::Remote);
+}
+
+#[test]
+fn test_get_latest_remote_version_for_train() {
+    let source = versions::FakeSource::new(&[
+        "0.3.0",
+        "0.3.1",
+        "0.4.0-rc.1",
+        "0.3.2-nightly-2023-10-05",
+        "0.4.0-internal-abc",
+    ]);
+    let latest = |train, fallback| {
+        get_latest_remote_version_for_train(&source, train, fallback, None, &[])
+            .map(|v| v.version.to_string())
+    };
+    assert_eq!(latest(ReleaseTrain::Stable, false).unwrap(), "0.3.1");
+    assert_eq!(latest(ReleaseTrain::Beta, false).unwrap(), "0.4.0-rc.1");
+    assert_eq!(
+        latest(ReleaseTrain::Nightly, false).unwrap(),
+        "0.3.2-nightly-2023-10-05"
+    );
+    assert_eq!(
+        latest(ReleaseTrain::Internal, false).unwrap(),
+        "0.4.0-internal-abc"
+    );
+    assert_eq!(
+        get_latest_remote_version_for_train(
+            &source,
+            ReleaseTrain::Stable,
+            false,
+            None,
+            &[semver::Version::parse("0.3.1").unwrap()]
+        )
+        .unwrap()
+        .version
+        .to_string(),
+        "0.3.0"
+    );
+    // Yanked versions are only picked when there's nothing else
+    let yanked = |train, yanked: &[&str]| {
+        let yanked = yanked
+            .iter()
+            .map(|v| semver::Version::parse(v).unwrap())
+            .collect_vec();
+        latest_remote_version_for_train(&source, train, false, None, &[], &yanked)
+            .map(|v| v.version.to_string())
+    };
+    assert_eq!(yanked(ReleaseTrain::Stable, &["0.3.1"]).unwrap(), "0.3.0");
+    assert_eq!(
+        yanked(ReleaseTrain::Beta, &["0.4.0-rc.1"]).unwrap(),
+        "0.4.0-rc.1"
+    );
+
+    let nightlies_only = versions::FakeSource::new(&["0.3.2-nightly-2023-10-05"]);
+    assert_eq!(
+        get_latest_remote_version_for_train(&nightlies_only, ReleaseTrain::Stable, true, None, &[])
+            .unwrap()
+            .version
+            .to_string(),
+        "0.3.2-nightly-2023-10-05"
+    );
+    let err = get_latest_remote_version_for_train(
+        &nightlies_only,
+        ReleaseTrain::Stable,
+        false,
+        None,
+        &[],
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "No versions found for the stable release train"
+    );
+}
+
+#[test]
+fn test_get_default_update() {
+    let source = versions::FakeSource::new(&["0.3.0", "0.3.1", "0.3.2-nightly-2023-10-05"]);
+    let settings = |default: &str| Settings {
+        default_runtime: Some(semver::Version::parse(default).unwrap()),
+        ..Default::default()
+    };
+    let update = |default, train| {
+        get_default_update(&source, &settings(default), train).map(|v| v.version.to_string())
+    };
+    for default in ["0.3.0", "0.3.1-nightly-2023-10-01", "0.4.0-internal-abc"] {
+        assert_eq!(
+            update(default, Some(ReleaseTrain::Stable)).unwrap(),
+            "0.3.1"
+        );
+        assert_eq!(
+            update(default, Some(ReleaseTrain::Nightly)).unwrap(),
+            "0.3.2-nightly-2023-10-05"
+        );
+    }
+    assert_eq!(update("0.3.0", None).unwrap(), "0.3.1");
+    assert_eq!(
+        update("0.3.1-nightly-2023-10-01", None).unwrap(),
+        "0.3.2-nightly-2023-10-05"
+    );
+    assert_eq!(
+        update("0.4.0-internal-abc", None).unwrap_err().to_string(),
+        "update-default looks for internal versions because the default runtime 0.4.0-internal-abc is on \
+         the internal release train. Pick a release train with `ambient runtime update-default --train stable` or `--train nightly`"
+    );
+    assert!(update("0.3.0", Some(ReleaseTrain::Internal)).is_err());
+}
+
+#[test]
+fn test_broken_install_reason() {
+    let dir = tempfile::tempdir().unwrap();
+    let bin = environment::Os::current().ambient_bin_name();
+    let install = |name: &str, binary: Option<&str>| {
+        let path = dir.path().join(name);
+        std::fs::create_dir_all(&path).unwrap();
+        if let Some(binary) = binary {
+            std::fs::write(path.join(bin), binary).unwrap();
+        }
+        path
+    };
+    assert_eq!(
+        broken_install_reason(&install("0.3.1", Some("binary"))),
+        None
+    );
+    assert_eq!(
+        broken_install_reason(&install("0.3.2", None)),
+        Some("no runtime binary")
+    );
+    assert_eq!(
+        broken_install_reason(&install("0.3.3", Some(""))),
+        Some("the runtime binary is empty")
+    );
+    assert_eq!(
+        broken_install_reason(&install("0.3.4.tmp", Some("binary"))),
+        Some("leftover temporary files")
+    );
+    let other_os = install("0.3.1@windows-latest", None);
+    std::fs::write(other_os.join("ambient.exe"), "binary").unwrap();
+    assert_eq!(broken_install_reason(&other_os), None);
+    assert_eq!(
+        broken_install_reason(&install("0.3.2@windows-latest", None)),
+        Some("no runtime binary")
+    );
+    // Anything else is left alone
+    assert_eq!(broken_install_reason(&install("backup", None)), None);
+    let file = dir.path().join("notes.txt");
+    std::fs::write(&file, "").unwrap();
+    assert_eq!(broken_install_reason(&file), None);
+}
+
+#[test]
+fn test_update_constraint() {
+    let source = versions::FakeSource::new(&["0.2.5", "0.3.0", "0.3.4", "1.0.0", "1.2.0"]);
+    let current = semver::Version::parse("0.3.0").unwrap();
+    let latest = |minor, patch| {
+        get_latest_remote_version_for_train(
+            &source,
+            ReleaseTrain::Stable,
+            false,
+            update_constraint(&current, minor, patch).as_ref(),
+            &[],
+        )
+        .map(|v| v.version.to_string())
+    };
+    assert_eq!(latest(false, false).unwrap(), "1.2.0");
+    assert_eq!(latest(true, false).unwrap(), "0.3.4");
+    assert_eq!(latest(false, true).unwrap(), "0.3.4");
+
+    let current = semver::Version::parse("0.4.0").unwrap();
+    let err = get_latest_remote_version_for_train(
+        &source,
+        ReleaseTrain::Stable,
+        false,
+        update_constraint(&current, false, true).as_ref(),
+        &[],
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "No compatible update found: no stable version matches =0.4"
+    );
+}
+
+fn latest_version_for_train(
+    versions: &[RuntimeVersion],
+    release_train: ReleaseTrain,
+    fallback_to_nightly: bool,
+) -> Option<RuntimeVersion> {
+    let latest_in = |train: ReleaseTrain| {
+        versions
+            .iter()
+            .filter(|v| ReleaseTrain::from_version(&v.version) == train)
+            .max_by(|a, b| train_order_key(&a.version).cmp(&train_order_key(&b.version)))
+            .cloned()
+    };
+    latest_in(release_train).or_else(|| {
+        if fallback_to_nightly {
+            latest_in(ReleaseTrain::Nightly)
+        } else {
+            None
+        }
+    })
+}
+
+#[test]
+fn test_latest_version_for_train() {
+    let versions = [
+        "0.3.0",
+        "0.3.1",
+        "0.4.0-rc.1",
+        "0.4.0-rc.2",
+        "0.3.1-nightly-2023-10-01",
+        "0.4.0-nightly-2023-10-05",
+        "0.4.0-internal-abc",
+    ]
+    .map(|v| RuntimeVersion::without_builds(semver::Version::parse(v).unwrap()));
+    let latest = |train, fallback| {
+        latest_version_for_train(&versions, train, fallback).map(|v| v.version.to_string())
+    };
+    assert_eq!(
+        latest(ReleaseTrain::Stable, false).as_deref(),
+        Some("0.3.1")
+    );
+    assert_eq!(
+        latest(ReleaseTrain::Beta, false).as_deref(),
+        Some("0.4.0-rc.2")
+    );
+    assert_eq!(
+        latest(ReleaseTrain::Nightly, false).as_deref(),
+        Some("0.4.0-nightly-2023-10-05")
+    );
+    assert_eq!(
+        latest(ReleaseTrain::Internal, false).as_deref(),
+        Some("0.4.0-internal-abc")
+    );
+
+    let only_prereleases = &versions[2..];
+    assert!(latest_version_for_train(only_prereleases, ReleaseTrain::Stable, false).is_none());
+    assert_eq!(
+        latest_version_for_train(only_prereleases, ReleaseTrain::Stable, true)
+            .map(|v| v.version.to_string())
+            .as_deref(),
+        Some("0.4.0-nightly-2023-10-05")
+    );
+}
+
+/// Orders versions within a release train. Nightlies are ordered by their build date first, since that's what
+/// makes a nightly newer than another one.
+fn train_order_key(version: &semver::Version) -> (&str, &semver::Version) {
+    let date = match ReleaseTrain::from_version(version) {
+        ReleaseTrain::Nightly => version.pre.as_str().trim_start_matches("nightly-"),
+        _ => "",
+    };
+    (date, version)
+}
+
+/// The latest version in the same release train as `version`, if it's newer than `version`
+pub fn newer_in_train<'a>(
+    version: &semver::Version,
+    versions: &'a [semver::Version],
+) -> Option<&'a semver::Version> {
+    let train = ReleaseTrain::from_version(version);
+    versions
+        .iter()
+        .filter(|v| ReleaseTrain::from_version(v) == train)
+        .max_by(|a, b| train_order_key(a).cmp(&train_order_key(b)))
+        .filter(|latest| is_newer(latest, version))
+}
+
+/// Whether `version` is newer than `than`: by build date for nightlies, like [`newer_in_train`], and by semver
+/// precedence otherwise
+pub fn is_newer(version: &semver::Version, than: &semver::Version) -> bool {
+    if ReleaseTrain::from_version(version) == ReleaseTrain::from_version(than) {
+        train_order_key(version) > train_order_key(than)
+    } else {
+        version > than
+    }
+}
+
+#[test]
+fn test_newer_in_train() {
+    let versions = [
+        "0.2.1",
+        "0.3.0",
+        "0.3.1-nightly-2023-09-30",
+        "0.3.0-nightly-2023-10-05",
+        "0.3.0-internal-abc",
+    ]
+    .map(|v| semver::Version::parse(v).unwrap());
+    let newer =
+        |v| newer_in_train(&semver::Version::parse(v).unwrap(), &versions).map(|v| v.to_string());
+    assert_eq!(newer("0.2.1").as_deref(), Some("0.3.0"));
+    assert_eq!(newer("0.3.0"), None);
+    assert_eq!(
+        newer("0.3.1-nightly-2023-09-30").as_deref(),
+        Some("0.3.0-nightly-2023-10-05")
+    );
+    assert_eq!(newer("0.3.0-nightly-2023-10-05"), None);
+}
+
+/// The runtime version to use for the package in `package_dir`: the one its ambient.toml asks for, or the default
+/// runtime outside of packages. The version may not be installed yet; see [`RuntimeVersion::install`].
+pub fn resolve_runtime(
+    package_dir: Option<&Path>,
+    settings: &Settings,
+) -> anyhow::Result<RuntimeVersion> {
+    Ok(resolve_runtime_with_tier(package_dir, settings)?.runtime)
+}
+
+/// The runtime selected with `ambient +name ...`: the latest installed version of a release train like `+nightly`
+/// (or the latest available one when none is installed), or a version like `+0.3.0-nightly-2023-10-01`
+pub fn resolve_runtime_override(settings: &Settings, name: &str) -> anyhow::Result<RuntimeVersion> {
+    let installed = list_installed_runtimes(settings)?
+        .into_iter()
+        .map(|runtime| runtime.version)
+        .chain(settings.linked_versions())
+        .map(RuntimeVersion::without_builds)
+        .collect_vec();
+    if let Ok(train) = ReleaseTrain::from_str(name) {
+        if let Some(version) = latest_version_for_train(&installed, train, false) {
+            return Ok(version);
+        }
+        return get_latest_remote_version_for_train(
+            &BucketSource::new(settings),
+            train,
+            false,
+            None,
+            &settings.ignored_versions,
+        );
+    }
+    let name = versions::strip_version_prefix(name);
+    if let Some(version) = installed
+        .into_iter()
+        .find(|runtime| runtime.version.to_string() == name)
+    {
+        return Ok(version);
+    }
+    versions::find_versions(&BucketSource::new(settings), name)?
+        .pop()
+        .with_context(|| format!("No runtime version matches +{}", name))
+}
+
+/// A resolved runtime version, with the requirement it satisfies and where it came from
+pub struct Resolution {
+    pub runtime: RuntimeVersion,
+    pub requirement: Option<VersionReq>,
+    pub tier: ResolutionTier,
+}
+
+/// Like [`resolve_runtime`], but also tells where the version came from
+pub fn resolve_runtime_with_tier(
+    package_dir: Option<&Path>,
+    settings: &Settings,
+) -> anyhow::Result<Resolution> {
+    if let Some(package_dir) = package_dir {
+        let ambient_toml = PackagePath(package_dir.to_path_buf())
+            .ambient_toml()
+            .get_content()?
+            .context("No ambient.toml found")?;
+        let requirement = ambient_toml.package.ambient_version;
+        if !settings.ignore_lock {
+            if let Some(lock) = RuntimeLock::load(package_dir)? {
+                lock.check_satisfies(requirement.as_ref(), package_dir)?;
+                return Ok(Resolution {
+                    runtime: RuntimeVersion::without_builds(lock.version),
+                    requirement,
+                    tier: ResolutionTier::Lock,
+                });
+            }
+        }
+        if let Some(version_req) = requirement {
+            let origin = RequirementOrigin::Manifest(
+                std::path::absolute(package_dir.join("ambient.toml"))
+                    .unwrap_or_else(|_| package_dir.join("ambient.toml")),
+            );
+            let (runtime, tier) = get_version_satisfying_req(
+                settings,
+                &BucketSource::new(settings),
+                &version_req,
+                &origin,
+            )?;
+            return Ok(Resolution {
+                runtime,
+                requirement: Some(version_req),
+                tier,
+            });
+        }
+    }
+    match &settings.default_runtime {
+        Some(version) => Ok(Resolution {
+            runtime: RuntimeVersion::without_builds(version.clone()),
+            requirement: None,
+            tier: ResolutionTier::Default,
+        }),
+        None => {
+            anyhow::bail!("No default runtime version set")
+        }
+    }
+}
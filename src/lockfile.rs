@@ -0,0 +1,191 @@
+use crate::{
+    environment::write_file_atomically,
+    matches_exact, resolve_runtime,
+    versions::{BucketSource, RuntimeVersion, VersionSource},
+    ReleaseTrain, Settings,
+};
+use anyhow::Context;
+use semver::VersionReq;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+pub const LOCKFILE_NAME: &str = "ambient.lock";
+
+const HEADER: &str =
+    "# Written by `ambient runtime lock`; commit it so that everyone runs the same runtime\n";
+
+/// The exact runtime a package resolved to, stored in `ambient.lock` next to its `ambient.toml`, so that its
+/// `ambient_version` requirement resolves the same way on every machine
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuntimeLock {
+    pub version: semver::Version,
+    pub release_train: ReleaseTrain,
+    /// Base64 encoded MD5 checksums of the builds, by OS
+    #[serde(default)]
+    pub checksums: BTreeMap<String, String>,
+}
+#[derive(Serialize, Deserialize)]
+struct Lockfile {
+    runtime: RuntimeLock,
+}
+impl RuntimeLock {
+    pub fn path(package_dir: &Path) -> PathBuf {
+        package_dir.join(LOCKFILE_NAME)
+    }
+    /// The package's lock, if it has one
+    pub fn load(package_dir: &Path) -> anyhow::Result<Option<Self>> {
+        let path = Self::path(package_dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content =
+            std::fs::read_to_string(&path).with_context(|| format!("Failed to read {:?}", path))?;
+        let lock = Self::from_str(&content).with_context(|| format!("Invalid {:?}", path))?;
+        Ok(Some(lock))
+    }
+    pub fn save(&self, package_dir: &Path) -> anyhow::Result<()> {
+        let content = toml::to_string(&Lockfile {
+            runtime: self.clone(),
+        })?;
+        write_file_atomically(&Self::path(package_dir), &format!("{}{}", HEADER, content))
+    }
+    fn for_version(runtime: &RuntimeVersion) -> Self {
+        Self {
+            version: runtime.version.clone(),
+            release_train: ReleaseTrain::from_version(&runtime.version),
+            checksums: runtime
+                .builds
+                .iter()
+                .filter_map(|build| Some((build.os.to_string(), build.md5.clone()?)))
+                .collect(),
+        }
+    }
+    /// Fails with a hint to relock when the locked version no longer satisfies the manifest's `requirement`
+    pub fn check_satisfies(
+        &self,
+        requirement: Option<&VersionReq>,
+        package_dir: &Path,
+    ) -> anyhow::Result<()> {
+        match requirement {
+            Some(requirement) if !matches_exact(requirement, &self.version) => anyhow::bail!(
+                "{} locks runtime {}, which doesn't satisfy ambient_version = \"{}\" in {}. \
+                 Run `ambient runtime lock` to update the lock, or pass --ignore-lock to resolve without it",
+                LOCKFILE_NAME,
+                self.version,
+                requirement,
+                package_dir.join("ambient.toml").display()
+            ),
+            _ => Ok(()),
+        }
+    }
+    /// The locked version with its builds from `source`, checked against and carrying the locked checksums, so
+    /// that installing it verifies the download against them
+    pub fn runtime(&self, source: &dyn VersionSource) -> anyhow::Result<RuntimeVersion> {
+        let mut builds = source.builds_for(&self.version)?;
+        if builds.is_empty() {
+            anyhow::bail!(
+                "Runtime {} from {} was not found on the server",
+                self.version,
+                LOCKFILE_NAME
+            );
+        }
+        for build in &mut builds {
+            let Some(locked) = self.checksums.get(&build.os.to_string()) else {
+                continue;
+            };
+            if build.md5.as_ref().is_some_and(|md5| md5 != locked) {
+                anyhow::bail!(
+                    "The {} build of runtime {} has changed since it was locked in {}. \
+                     Run `ambient runtime lock` if the new build is expected",
+                    build.os,
+                    self.version,
+                    LOCKFILE_NAME
+                );
+            }
+            build.md5 = Some(locked.clone());
+        }
+        Ok(RuntimeVersion {
+            version: self.version.clone(),
+            builds,
+        })
+    }
+}
+impl FromStr for RuntimeLock {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(toml::from_str::<Lockfile>(s)?.runtime)
+    }
+}
+
+/// Resolves the package's requirement without its current lock and writes the result to its `ambient.lock`
+pub fn lock_runtime(settings: &Settings, package_dir: &Path) -> anyhow::Result<RuntimeLock> {
+    let settings = Settings {
+        ignore_lock: true,
+        ..settings.clone()
+    };
+    let version = resolve_runtime(Some(package_dir), &settings)?.version;
+    let builds = BucketSource::new(&settings).builds_for(&version)?;
+    if builds.is_empty() {
+        anyhow::bail!("Runtime {} was not found on the server", version);
+    }
+    let lock = RuntimeLock::for_version(&RuntimeVersion { version, builds });
+    lock.save(package_dir)?;
+    Ok(lock)
+}
+
+#[cfg(test)]
+fn lock(version: &str, checksums: &[(&str, &str)]) -> RuntimeLock {
+    RuntimeLock {
+        version: semver::Version::parse(version).unwrap(),
+        release_train: ReleaseTrain::Stable,
+        checksums: checksums
+            .iter()
+            .map(|(os, md5)| (os.to_string(), md5.to_string()))
+            .collect(),
+    }
+}
+
+#[test]
+fn test_lockfile_roundtrip() {
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(RuntimeLock::load(dir.path()).unwrap(), None);
+    let locked = lock(
+        "0.3.1",
+        &[("ubuntu-22.04", "abc=="), ("windows-latest", "def==")],
+    );
+    locked.save(dir.path()).unwrap();
+    let content = std::fs::read_to_string(RuntimeLock::path(dir.path())).unwrap();
+    assert!(content.starts_with(HEADER), "{}", content);
+    assert!(
+        content.contains("release_train = \"stable\""),
+        "{}",
+        content
+    );
+    assert_eq!(RuntimeLock::load(dir.path()).unwrap(), Some(locked));
+}
+
+#[test]
+fn test_lock_check_satisfies() {
+    let locked = lock("0.3.1", &[]);
+    let req = |req| VersionReq::parse(req).unwrap();
+    assert!(locked.check_satisfies(None, Path::new("game")).is_ok());
+    assert!(locked
+        .check_satisfies(Some(&req("0.3")), Path::new("game"))
+        .is_ok());
+    let err = locked
+        .check_satisfies(Some(&req("0.4")), Path::new("game"))
+        .unwrap_err()
+        .to_string();
+    assert!(
+        err.starts_with(
+            "ambient.lock locks runtime 0.3.1, which doesn't satisfy ambient_version = \"^0.4\""
+        ),
+        "{}",
+        err
+    );
+}
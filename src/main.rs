@@ -1,215 +1,837 @@
-mod ambient_toml;
-mod environment;
-mod versions;
+mod doctor;
+mod manpages;
 
+use ambient::{
+    app_dir,
+    crash::Crash,
+    download_cache::DownloadCache,
+    environment::{
+        dir_size, download_cache_dir, logs_dir, settings_path, write_file_atomically,
+        AmbientApiSpec, Os, PackagePath, RuntimeStore, SetRuntimeOptions,
+    },
+    get_default_update, get_latest_remote_version_for_train,
+    history::{DefaultRuntimeChange, DefaultRuntimeHistory},
+    is_newer, list_installed_runtimes,
+    lockfile::{lock_runtime, RuntimeLock, LOCKFILE_NAME},
+    matches_exact, newer_in_train,
+    quarantine::{gatekeeper_help, is_quarantined},
+    resolve_runtime, resolve_runtime_override, resolve_runtime_with_tier,
+    runtime_logs::{tail, RuntimeLogs},
+    runtimes_dir, runtimes_to_prune, settings_dir,
+    shell_env::{env_script, Shell},
+    templates::{create_project, Template},
+    update_constraint, version_cache_path,
+    versions::{
+        find_versions, get_deprecations, parse_mirror_url, refresh_version_cache,
+        strip_version_prefix, version_cache_fetched_at, BucketSource, InstallProgress,
+        RuntimeVersion, VersionSource, VersionsFilter,
+    },
+    InstallOptions, InstalledRuntime, ReleaseTrain, Settings, PROFILE_KEYS,
+};
 use anyhow::Context;
 use clap::Parser;
 use colored::Colorize;
-use environment::{runtimes_dir, settings_dir, settings_path, Os, PackagePath};
-use semver::VersionReq;
-use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use versions::{get_version, get_versions, RuntimeVersion, VersionsFilter};
+use doctor::{fix_broken_runtime_dirs, run_check_project, run_doctor};
+use itertools::Itertools;
+use std::{
+    io::{IsTerminal, Read, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::RecvTimeoutError,
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// Set with `--quiet`: confirmations and install progress aren't printed, only warnings and errors
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Set from `settings.verbose`, i.e. `AMBIENT_VERBOSE=1`: the download URLs and sizes are printed too
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// Like `println!`, unless `--quiet` is set
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            println!($($arg)*);
+        }
+    };
+}
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     #[command(subcommand)]
     command: Commands,
+    /// Don't access the network; only use installed runtimes and the cached version list
+    #[arg(long, global = true)]
+    offline: bool,
+    /// Answer yes to confirmations, e.g. when switching the default runtime to another release train
+    #[arg(long, global = true)]
+    yes: bool,
+    /// Resolve the local package's runtime from its ambient.toml, without its ambient.lock
+    #[arg(long, global = true)]
+    ignore_lock: bool,
+    /// Don't run the post_install and pre_uninstall hooks from the settings
+    #[arg(long, global = true)]
+    no_hooks: bool,
+    /// Only print warnings and errors when installing a runtime or setting the default or local runtime, e.g. in
+    /// scripts
+    #[arg(long, short, global = true)]
+    quiet: bool,
+    /// Use the settings of this profile instead of the active one; also set with AMBIENT_PROFILE
+    // Applied in main(), before the arguments are parsed
+    #[arg(long, global = true)]
+    profile: Option<String>,
 }
 
 #[derive(Parser, Clone, Debug)]
 pub enum Commands {
+    /// Install and manage runtime versions
     #[command(subcommand)]
     Runtime(RuntimeCommands),
+    /// Create a new project that uses the default runtime version
+    New {
+        name: String,
+        /// The project layout: minimal, game or server
+        #[arg(long)]
+        template: Option<Template>,
+        /// Where to create the project; defaults to a directory named after it
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Write man pages for the CLI to a directory, for packaging
+    #[command(hide = true)]
+    GenerateManpages { dir: PathBuf },
 }
 
+/// How arguments reach the runtime, for the description of the top-level man page
+const EXEC_PASSTHROUGH_DESCRIPTION: &str = "Any other command, like `ambient run` or `ambient build`, is passed on to \
+the Ambient runtime the current package asks for in the ambient_version of its ambient.toml (or its ambient.lock), \
+or else to the default runtime. The runtime is installed first if it's missing. `ambient +<version> ...` runs a \
+specific version, release train or linked runtime instead.
+
+These flags are handled by the CLI and not passed on when they come before the runtime's command, like in \
+`ambient --exec run`: --yes, --exec, --env KEY=VALUE, --ignore-lock, --no-hooks, --profile NAME, --print-command and, \
+together with --print-command, --dry-run. With a +<version> override, --force is handled too.
+
+When the runtime fails, the CLI exits with the runtime's exit code, or with 1 if the runtime was killed by a signal.";
+
 #[derive(Parser, Clone, Debug)]
 pub enum RuntimeCommands {
     /// List all available runtime versions
-    ListAll,
+    ListAll {
+        /// Fetch the version list from the server instead of using the cache
+        #[arg(long)]
+        refresh_cache: bool,
+        /// Group the versions by major.minor, newest first
+        #[arg(long)]
+        grouped: bool,
+        /// With --grouped, list every version in each group instead of just the latest ones
+        #[arg(long, requires = "grouped")]
+        verbose: bool,
+        /// Only list versions that have a build for this OS (macos-latest, windows-latest or ubuntu-22.04)
+        #[arg(long)]
+        available_for: Option<Os>,
+        /// Print the versions and their download URLs as JSON
+        #[arg(long, conflicts_with = "grouped")]
+        json: bool,
+        /// Only list nightly versions. By default stable, beta and nightly versions are listed
+        #[arg(long, conflicts_with = "no_nightly")]
+        nightly_only: bool,
+        /// Leave out nightly versions
+        #[arg(long)]
+        no_nightly: bool,
+        /// Also list internal versions, which are left out by default
+        #[arg(long, conflicts_with = "no_internal")]
+        include_internal: bool,
+        /// Leave out internal versions (the default)
+        #[arg(long)]
+        no_internal: bool,
+        /// Mark the installed versions and the default, and list the installed versions first within each release
+        /// train
+        #[arg(long, conflicts_with = "grouped")]
+        with_installed_marker: bool,
+    },
     /// List locally installed runtime versions
-    ListInstalled,
+    ListInstalled {
+        /// Mark versions that have a newer version in the same release train; exits with 1 if any are outdated
+        #[arg(long)]
+        outdated: bool,
+        /// Print the installed versions and where they are as JSON
+        #[arg(long, conflicts_with = "outdated")]
+        json: bool,
+    },
+    /// Compare the default, local package and installed runtime versions with the latest in their release trains.
+    /// Exits with 2 if any are outdated
+    Outdated {
+        /// Only check the version the local package's ambient.toml asks for
+        #[arg(long)]
+        local: bool,
+        /// Print the comparison as JSON
+        #[arg(long)]
+        json: bool,
+    },
     /// Install a specific runtime version
-    Install { version: String },
+    Install {
+        version: String,
+        /// Don't run the installed binary to check that it works, e.g. where it can't be executed
+        #[arg(long, visible_alias = "skip-smoke-test")]
+        no_smoke_test: bool,
+        /// Install the build for another OS (macos-latest, windows-latest, ubuntu-22.04 or a target triple like
+        /// aarch64-apple-darwin), e.g. for packaging.
+        /// Such installs are never used to run the runtime locally
+        #[arg(long)]
+        os: Option<Os>,
+        /// Extract the runtime into this directory instead of installing it, e.g. into a package-local `.ambient/`
+        #[arg(long, visible_alias = "extract-to")]
+        dest: Option<PathBuf>,
+        /// Pick the version from a list when several versions match
+        #[arg(long)]
+        interactive: bool,
+        /// After installing, check the installed files against the SHA-256 checksums of the archive and run the
+        /// binary; prints PASS or FAIL, and exits with 1 on FAIL (the runtime stays installed)
+        #[arg(long, conflicts_with_all = ["os", "dest", "no_smoke_test"])]
+        check: bool,
+        /// Install the version even if it has been yanked
+        #[arg(long)]
+        allow_yanked: bool,
+    },
+    /// Download and install a runtime without changing the default or any package, e.g. to have it available
+    /// offline or to warm a CI cache
+    Prefetch {
+        /// A version, `latest` for the latest stable version, or a release train like `nightly`
+        #[arg(required_unless_present = "project")]
+        version: Option<String>,
+        /// Prefetch the runtime the local package resolves to
+        #[arg(long, conflicts_with = "version")]
+        project: bool,
+    },
+    /// Show information about a runtime version, including which OS builds are available
+    Info { version: String },
     /// Update the default runtime version to the latest available
-    UpdateDefault,
+    UpdateDefault {
+        /// Only report whether there is an update; exits with 1 if there is
+        #[arg(long)]
+        check: bool,
+        /// Update to the latest version of this release train (stable, beta, nightly or internal) instead of the
+        /// train of the current default, e.g. `--train stable` to move from nightly back to stable
+        #[arg(long)]
+        train: Option<ReleaseTrain>,
+    },
     /// Update the runtime version for the local package
-    UpdateLocal,
-    /// Set the global default version
-    SetDefault { version: String },
+    UpdateLocal {
+        /// Only update to versions with the same major version
+        #[arg(long)]
+        minor: bool,
+        /// Only update to versions with the same major and minor version
+        #[arg(long, conflicts_with = "minor")]
+        patch: bool,
+        /// Only report whether there is an update; exits with 1 if there is
+        #[arg(long)]
+        check: bool,
+        /// Don't update; set ambient_api in Cargo.toml to the runtime version the package resolves to, e.g. after
+        /// `runtime check-project` found that they differ
+        #[arg(long, conflicts_with_all = ["minor", "patch", "check"])]
+        fix: bool,
+        /// Update ambient_api in Cargo.toml even when it points to a local path
+        #[arg(long)]
+        force_remote: bool,
+        /// Also run `cargo update --package ambient_api` when the package has a Cargo.lock. Its output is printed
+        /// with AMBIENT_VERBOSE=1
+        #[arg(long, conflicts_with = "check")]
+        update_lock: bool,
+    },
+    /// Update the runtime version of the workspace the local package belongs to, in the workspace root's
+    /// ambient.toml, or of the local package if it isn't in a workspace
+    UpdateAllLocal,
+    /// Show, set or unset the global default version
+    #[command(subcommand)]
+    Default(DefaultCommands),
+    /// Show, set or remove the mirror runtimes are listed and downloaded from
+    #[command(subcommand)]
+    Mirror(MirrorCommands),
+    /// List, show, create or switch the settings profiles, e.g. for work and personal projects
+    #[command(subcommand)]
+    Profile(ProfileCommands),
+    /// Set the global default version; the same as `default set`
+    #[command(hide = true)]
+    SetDefault {
+        version: String,
+        /// Pick the version from a list when several versions match
+        #[arg(long)]
+        interactive: bool,
+        /// Change the default even if it's pinned
+        #[arg(long)]
+        force: bool,
+        /// Change the default to an older version or another release train without asking
+        #[arg(long)]
+        allow_downgrade: bool,
+        /// Only set the default if none is set yet, e.g. in CI setup scripts
+        #[arg(long)]
+        if_unset: bool,
+        /// Use the version even if it has been yanked
+        #[arg(long)]
+        allow_yanked: bool,
+    },
+    /// Keep the default runtime version from being changed, e.g. by `update-default`
+    Pin {
+        /// Shown when a change of the default is refused
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Allow the default runtime version to be changed again
+    Unpin,
+    /// Use a locally built runtime binary in place, e.g. `target/release/ambient`. Linked by a version like
+    /// `0.4.0-dev`, it can be the default or satisfy ambient_version like an installed version; linked by any other
+    /// name, it's selected with `ambient +<name> ...`
+    Link { name: String, path: PathBuf },
+    /// Remove a link made with `runtime link`; the binary itself is left alone
+    Unlink { name: String },
+    /// Never offer a version as an update, e.g. a broken nightly
+    Ignore { version: String },
+    /// Offer an ignored version as an update again
+    Unignore { version: String },
+    /// Run a runtime version once, e.g. `ambient runtime exec 0.3.0-nightly-2023-09-20 -- run --release`, without
+    /// changing the default or the local package. Exits with the runtime's exit code
+    Exec {
+        /// A version, version prefix, release train or linked runtime name, like with `ambient +<version>`
+        version: String,
+        /// The arguments to run the runtime with
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
     /// Set the local package ambient runtime version
-    SetLocal { version: String },
+    SetLocal {
+        version: String,
+        /// Pick the version from a list when several versions match
+        #[arg(long)]
+        interactive: bool,
+        /// Update ambient_api in Cargo.toml even when it points to a local path
+        #[arg(long)]
+        force_remote: bool,
+        /// Point ambient_api in Cargo.toml at this branch of the Ambient repository, e.g. `main`, instead of the
+        /// version
+        #[arg(long)]
+        branch: Option<String>,
+    },
+    /// Show the runtime version requirement in the local package's ambient.toml
+    ShowLocal,
+    /// Resolve the local package's runtime requirement and pin the exact version in ambient.lock
+    Lock,
     /// Show where the settings file is located
     ShowSettingsPath,
+    /// Show where the runtimes are installed
+    ShowDataDir,
+    /// Show the directory of the settings file
+    ShowConfigDir,
+    /// Show the base directory of the CLI's data, like the runtimes and the version cache
+    ShowAppDir,
+    /// Show all of the directories and files the CLI uses
+    ShowPaths,
+    /// Remove a specific installed runtime version
+    Uninstall {
+        version: String,
+        /// Allow removing the version from the shared runtimes dir
+        #[arg(long)]
+        shared: bool,
+    },
     /// Remove all installed runtime versions
-    UninstallAll,
+    UninstallAll {
+        /// Also remove the versions in the shared runtimes dir
+        #[arg(long)]
+        shared: bool,
+    },
+    /// Remove all but the newest installed runtime versions, keeping the default and the one before it
+    Prune {
+        /// How many of the newest versions to keep
+        #[arg(long, default_value_t = 2)]
+        keep: usize,
+        /// Only print what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Fetch the list of available runtime versions and update the local cache
+    RefreshCache,
+    /// Remove the cached downloads of runtime versions
+    CleanCache,
+    /// Show how much disk space the installed runtimes and the download cache use
+    DiskUsage,
+    /// List the runtime output logs captured with the `capture_logs` setting, oldest first
+    Logs {
+        /// Only show the log of the latest session
+        #[arg(long)]
+        last_run: bool,
+        /// Print the last lines of the latest session's log instead of its path
+        #[arg(long, requires = "last_run")]
+        tail: Option<usize>,
+    },
+    /// Revert the default runtime version to the previous one
+    Rollback,
+    /// Show the changes of the default runtime version of the profile in use
+    History,
+    /// Check for common problems, like network connectivity issues
+    Doctor {
+        /// Remove the leftovers of failed installs from the runtimes dirs before checking
+        #[arg(long)]
+        fix: bool,
+        /// Only list what --fix would remove
+        #[arg(long, requires = "fix")]
+        dry_run: bool,
+    },
+    /// Check that the local package's ambient.toml and Cargo.toml agree on the runtime version, and that it can be
+    /// resolved; exits with 1 if not
+    CheckProject,
+    /// Show an overview of the CLI, the default runtime and the local package, without downloading anything
+    Status {
+        /// Print the overview as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the path of the runtime binary that `ambient` runs here
+    Which,
+    /// Print the statements that export AMBIENT_RUNTIME_PATH and AMBIENT_RUNTIME_VERSION for the runtime that
+    /// `ambient` runs here, and put it on the PATH, e.g. `eval "$(ambient runtime env)"`
+    Env {
+        /// The format of the statements: bash, fish, powershell, or github to make the runtime available to the
+        /// following steps of a GitHub Actions job
+        #[arg(long, default_value_t)]
+        shell: Shell,
+        /// Install the runtime if it isn't installed yet
+        #[arg(long)]
+        install: bool,
+    },
+    /// Print the runtime version a package resolves to, without installing or running anything.
+    /// Exits with 3 if no version satisfies its requirement
+    Resolve {
+        /// The package's ambient.toml (or its directory); defaults to the local package
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+        /// Also print the requirement, where the version came from and whether it's installed, as JSON
+        #[arg(long)]
+        json: bool,
+    },
 }
 
-fn list_installed_runtimes() -> anyhow::Result<Vec<(semver::Version, PathBuf)>> {
-    let runtimes_dir = runtimes_dir()?;
-    if !runtimes_dir.exists() {
-        return Ok(Vec::new());
-    }
-    let mut runtimes = Vec::new();
-    for entry in std::fs::read_dir(runtimes_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir() {
-            let version = semver::Version::parse(entry.file_name().to_str().unwrap())?;
-            runtimes.push((version, path.join(Os::current().ambient_bin_name())));
-        }
-    }
-    Ok(runtimes)
+#[derive(Parser, Clone, Debug)]
+pub enum DefaultCommands {
+    /// Show the default version, whether it's installed and where
+    Show {
+        /// Print the default version as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Set the global default version
+    Set {
+        version: String,
+        /// Pick the version from a list when several versions match
+        #[arg(long)]
+        interactive: bool,
+        /// Change the default even if it's pinned
+        #[arg(long)]
+        force: bool,
+        /// Change the default to an older version or another release train without asking
+        #[arg(long)]
+        allow_downgrade: bool,
+        /// Only set the default if none is set yet, e.g. in CI setup scripts
+        #[arg(long)]
+        if_unset: bool,
+        /// Use the version even if it has been yanked
+        #[arg(long)]
+        allow_yanked: bool,
+    },
+    /// Remove the default version, so that the next run installs the latest stable version again
+    Unset,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum ReleaseTrain {
-    Stable,
-    Nightly,
-    Internal,
-}
-impl ReleaseTrain {
-    pub fn from_version(version: &semver::Version) -> Self {
-        if version.pre.is_empty() {
-            ReleaseTrain::Stable
-        } else if version.pre.contains("nightly") {
-            ReleaseTrain::Nightly
-        } else {
-            ReleaseTrain::Internal
+#[derive(Parser, Clone, Debug)]
+pub enum MirrorCommands {
+    /// Show the mirror URL, or (none) when runtimes come from Google Storage
+    Get,
+    /// List and download runtimes from a mirror of the runtime bucket, e.g. `https://mirror.example.com/ambient/`
+    Set { url: String },
+    /// Go back to listing and downloading runtimes from Google Storage
+    Remove,
+}
+
+#[derive(Parser, Clone, Debug)]
+pub enum ProfileCommands {
+    /// List the profiles, marking the one in use with `*`
+    List,
+    /// Show the settings of a profile, by default of the one in use
+    Show { name: Option<String> },
+    /// Create a profile. It uses the top-level settings until its own are set, e.g. with
+    /// `ambient runtime --profile <name> mirror set <url>`
+    Create { name: String },
+    /// Use a profile unless another one is selected with `--profile`; without a name, go back to the top-level
+    /// settings
+    Switch { name: Option<String> },
+}
+
+/// The versions in the order of `list-all --with-installed-marker`: by release train, the installed versions first
+/// within each train, and otherwise in the given order. Each comes with its marker, if it's installed
+fn with_installed_markers(
+    versions: Vec<semver::Version>,
+    installed: &[semver::Version],
+    default: Option<&semver::Version>,
+) -> Vec<(semver::Version, &'static str)> {
+    let train_order = |version: &semver::Version| match ReleaseTrain::from_version(version) {
+        ReleaseTrain::Stable => 0,
+        ReleaseTrain::Beta => 1,
+        ReleaseTrain::Nightly => 2,
+        ReleaseTrain::Internal => 3,
+    };
+    let mut marked = versions
+        .into_iter()
+        .map(|version| {
+            let marker = match (installed.contains(&version), default == Some(&version)) {
+                (true, true) => " [installed][default]",
+                (true, false) => " [installed]",
+                (false, _) => "",
+            };
+            (version, marker)
+        })
+        .collect_vec();
+    marked.sort_by_key(|(version, marker)| (train_order(version), marker.is_empty()));
+    marked
+}
+
+#[test]
+fn test_with_installed_markers() {
+    let version = |v| semver::Version::parse(v).unwrap();
+    let versions = [
+        "0.2.0",
+        "0.3.0-nightly-2023-09-01",
+        "0.3.0",
+        "0.3.1-nightly-2023-10-01",
+        "0.3.1",
+    ]
+    .map(version)
+    .to_vec();
+    let installed = [version("0.3.0"), version("0.3.1-nightly-2023-10-01")];
+    let lines = with_installed_markers(versions, &installed, Some(&version("0.3.0")))
+        .into_iter()
+        .map(|(version, marker)| format!("{}{}", version, marker))
+        .collect_vec();
+    assert_eq!(
+        lines,
+        [
+            "0.3.0 [installed][default]",
+            "0.2.0",
+            "0.3.1",
+            "0.3.1-nightly-2023-10-01 [installed]",
+            "0.3.0-nightly-2023-09-01",
+        ]
+    );
+}
+
+/// Formats versions grouped by major.minor, newest group first. Each group shows its latest stable and nightly
+/// versions, and the rest only when `verbose` is set.
+fn grouped_versions_lines(versions: &[semver::Version], verbose: bool) -> Vec<String> {
+    let groups = versions
+        .iter()
+        .sorted()
+        .rev()
+        .group_by(|v| (v.major, v.minor));
+    let mut lines = Vec::new();
+    for ((major, minor), group) in &groups {
+        let group = group.collect_vec();
+        lines.push(format!(
+            "=== {}.{}.x ({} versions) ===",
+            major,
+            minor,
+            group.len()
+        ));
+        let latest_stable = group
+            .iter()
+            .find(|v| ReleaseTrain::from_version(v) == ReleaseTrain::Stable);
+        let latest_nightly = group
+            .iter()
+            .find(|v| ReleaseTrain::from_version(v) == ReleaseTrain::Nightly);
+        if let Some(v) = latest_stable {
+            lines.push(format!("  latest stable:  {}", v));
         }
-    }
-    pub fn from_version_req(version_req: &semver::VersionReq) -> Self {
-        if let Some(comp) = version_req.comparators.first() {
-            if comp.pre.is_empty() {
-                ReleaseTrain::Stable
-            } else if comp.pre.contains("nightly") {
-                ReleaseTrain::Nightly
-            } else {
-                ReleaseTrain::Internal
-            }
-        } else {
-            ReleaseTrain::Stable
+        if let Some(v) = latest_nightly {
+            lines.push(format!("  latest nightly: {}", v));
+        }
+        let rest = group
+            .iter()
+            .filter(|v| Some(*v) != latest_stable && Some(*v) != latest_nightly)
+            .collect_vec();
+        if verbose {
+            lines.extend(rest.iter().map(|v| format!("  {}", v)));
+        } else if !rest.is_empty() {
+            lines.push(format!(
+                "  ... and {} more (use --verbose to list them)",
+                rest.len()
+            ));
         }
     }
+    lines
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-struct Settings {
-    default_runtime: Option<semver::Version>,
-}
-impl Settings {
-    fn load() -> anyhow::Result<Self> {
-        Ok(serde_json::from_str(
-            std::fs::read_to_string(settings_path()?)?.as_str(),
-        )?)
-    }
-    fn save(&self) -> anyhow::Result<()> {
-        std::fs::create_dir_all(settings_dir()?)?;
-        std::fs::write(settings_path()?, serde_json::to_string_pretty(self)?)?;
-        Ok(())
-    }
-    fn release_train(&self) -> ReleaseTrain {
-        self.default_runtime
-            .as_ref()
-            .map(ReleaseTrain::from_version)
-            .unwrap_or(ReleaseTrain::Stable)
-    }
+#[test]
+fn test_grouped_versions_lines() {
+    let versions = [
+        "0.2.1",
+        "0.3.0-nightly-2023-09-01",
+        "0.3.0",
+        "0.2.0",
+        "0.3.1-nightly-2023-10-01",
+        "0.3.1-nightly-2023-10-02",
+    ]
+    .map(|v| semver::Version::parse(v).unwrap());
+    assert_eq!(
+        grouped_versions_lines(&versions, false),
+        vec![
+            "=== 0.3.x (4 versions) ===",
+            "  latest stable:  0.3.0",
+            "  latest nightly: 0.3.1-nightly-2023-10-02",
+            "  ... and 2 more (use --verbose to list them)",
+            "=== 0.2.x (2 versions) ===",
+            "  latest stable:  0.2.1",
+            "  ... and 1 more (use --verbose to list them)",
+        ]
+    );
+    assert_eq!(
+        grouped_versions_lines(&versions, true)[3..5],
+        ["  0.3.1-nightly-2023-10-01", "  0.3.0-nightly-2023-09-01"]
+    );
 }
 
-/// If the version requirement contains a pre-release identifier, only versions with the same pre-release identifier will be considered.
-fn matches_exact(version_req: &VersionReq, version: &semver::Version) -> bool {
-    for comp in &version_req.comparators {
-        if !comp.pre.is_empty() || !version.pre.is_empty() {
-            return comp.matches(version) && comp.pre == version.pre;
+/// Prints the warnings and notes even with `--quiet`, since they tell about something not going as asked
+fn print_progress(progress: &InstallProgress) {
+    match progress {
+        InstallProgress::Downloading { .. } | InstallProgress::Downloaded { .. } => {
+            if VERBOSE.load(Ordering::Relaxed) {
+                eprintln!("{}", progress);
+            }
         }
+        InstallProgress::SharedDirNotWritable { .. }
+        | InstallProgress::Wsl
+        | InstallProgress::OptionalHookFailed { .. } => eprintln!("{}", progress),
+        // `Installing`, `Installed`, `RunningHook` and any steps added later
+        _ => status!("{}", progress),
     }
-    version_req.matches(version)
 }
 
-fn get_version_satisfying_req(
+/// Resolves a user-supplied version string. When several versions match, the newest is selected, or the user
+/// picks one when `interactive` is set.
+fn get_version(
     settings: &Settings,
-    version_req: &VersionReq,
+    version: &str,
+    interactive: bool,
 ) -> anyhow::Result<RuntimeVersion> {
-    log::info!("Looking for version satisfying {}", version_req);
-    if let Some(default_version) = &settings.default_runtime {
-        log::info!("Checking default version: {}", default_version);
-        if matches_exact(version_req, default_version) {
-            log::info!("Default version matches, returning.");
-            return Ok(RuntimeVersion::without_builds(default_version.clone()));
-        }
+    let mut matches = find_versions(&BucketSource::new(settings), version)?;
+    // A yanked version is only used when it's the only match, and then check_deprecation refuses it
+    let yanked = get_deprecations().yanked();
+    if matches.iter().any(|v| !yanked.contains(&v.version)) {
+        matches.retain(|v| !yanked.contains(&v.version));
     }
-    log::info!("Checking installed versions");
-    for (version, _) in list_installed_runtimes()? {
-        if matches_exact(version_req, &version) {
-            return Ok(RuntimeVersion::without_builds(version));
+    let version = strip_version_prefix(version);
+    let selected = if matches.len() > 1 && interactive {
+        let index = pick_version(&matches)?;
+        matches.remove(index)
+    } else {
+        let selected = matches.pop().context("No matching versions")?;
+        if !matches.is_empty() {
+            status!("Selected {} ({} other matches)", selected, matches.len());
+        } else if selected.version.to_string() != version {
+            status!("Resolved {} to {}", version, selected);
         }
+        selected
+    };
+    Ok(selected)
+}
+
+/// The runtime `runtime prefetch` installs: the latest stable version for `latest`, the latest version of a release
+/// train by its name, or a version like `install` takes
+fn prefetch_version(settings: &Settings, version: &str) -> anyhow::Result<RuntimeVersion> {
+    let train = match version {
+        "latest" => Some(ReleaseTrain::Stable),
+        _ => version.parse::<ReleaseTrain>().ok(),
+    };
+    match train {
+        Some(train) => get_latest_remote_version_for_train(
+            &BucketSource::new(settings),
+            train,
+            false,
+            None,
+            &settings.ignored_versions,
+        ),
+        None => get_version(settings, version, false),
     }
-    log::info!("Checking all versions");
-    for version in get_versions(VersionsFilter {
-        include_private: true,
-        include_nightly: true,
-    })? {
-        if matches_exact(version_req, &version.version) {
-            return Ok(version);
-        }
+}
+
+/// Warns about using a deprecated version, and refuses a yanked one unless `allow_yanked` is set
+fn check_deprecation(version: &RuntimeVersion, allow_yanked: bool) -> anyhow::Result<()> {
+    let deprecations = get_deprecations();
+    match deprecations.get(&version.version) {
+        Some(deprecation) if deprecation.yanked && !allow_yanked => anyhow::bail!(
+            "Runtime {} has been yanked: {}. Pass --allow-yanked to use it anyway",
+            version,
+            deprecation.reason
+        ),
+        Some(deprecation) => println!(
+            "{} runtime {} is {}",
+            "Warning:".yellow().bold(),
+            version,
+            deprecation
+        ),
+        None => {}
     }
-    anyhow::bail!("No version found satisfying {}", version_req);
+    Ok(())
 }
 
-fn get_latest_remote_version_for_train(
-    release_train: ReleaseTrain,
-    fallback_to_nightly: bool,
-) -> anyhow::Result<RuntimeVersion> {
-    let versions = get_versions(VersionsFilter {
-        include_private: release_train == ReleaseTrain::Internal,
-        include_nightly: release_train == ReleaseTrain::Nightly || fallback_to_nightly,
-    })?;
-    let latest_for_train = versions
-        .iter()
-        .filter(|v| release_train == ReleaseTrain::from_version(&v.version))
-        .last()
-        .cloned();
-    if let Some(latest_for_train) = latest_for_train {
-        return Ok(latest_for_train);
-    } else if fallback_to_nightly {
-        let latest_nightly = versions.iter().filter(|v| v.is_nightly()).last().cloned();
-        if let Some(latest_nightly) = latest_nightly {
-            return Ok(latest_nightly);
+/// Asks the user to pick one of `versions` on stdin, newest first; returns its index
+fn pick_version(versions: &[RuntimeVersion]) -> anyhow::Result<usize> {
+    println!("Multiple versions match:");
+    for (i, version) in versions.iter().rev().enumerate() {
+        println!("  {}) {}", i + 1, version);
+    }
+    loop {
+        print!("Pick a version [1-{}] (default 1): ", versions.len());
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line)? == 0 {
+            anyhow::bail!("No version picked");
+        }
+        let line = line.trim();
+        let choice = if line.is_empty() {
+            Some(1)
+        } else {
+            line.parse::<usize>().ok()
+        };
+        match choice {
+            Some(choice) if (1..=versions.len()).contains(&choice) => {
+                return Ok(versions.len() - choice)
+            }
+            _ => println!("Please enter a number between 1 and {}", versions.len()),
         }
     }
-    Err(anyhow::anyhow!("No versions found for {:?}", release_train))
 }
 
-fn get_current_runtime(
+const LOCAL_AMBIENT_API_WARNING: &str =
+    "Not updating ambient_api: it currently points to a local path. Use `--force-remote` to override.";
+
+fn print_cargo_update_output(output: &str, verbose: bool) {
+    if verbose {
+        print!("{}", output);
+    }
+    status!("ambient_api updated in Cargo.lock");
+}
+
+/// Also refreshes the package's ambient.lock, if it has one. `settings.verbose` prints the output of `cargo update`
+fn set_local_runtime(
     settings: &Settings,
-    package_path: &Option<PackagePath>,
-) -> anyhow::Result<RuntimeVersion> {
-    if let Some(package_path) = package_path {
-        let ambient_toml = package_path
-            .ambient_toml()
-            .get_content()?
-            .context("No ambient.toml found")?;
-        if let Some(version_req) = &ambient_toml.package.ambient_version {
-            return get_version_satisfying_req(settings, version_req);
-        }
+    package_path: &PackagePath,
+    version: &semver::Version,
+    options: &SetRuntimeOptions,
+) -> anyhow::Result<()> {
+    let update = package_path.set_runtime(version, options)?;
+    status!(
+        "Runtime version set to ambient_version=\"{}\" in ambient.toml",
+        version
+    );
+    if !update.cargo_toml_updated {
+        println!("{}", LOCAL_AMBIENT_API_WARNING);
+    } else if let Some(branch) = &options.ambient_api_branch {
+        status!("ambient_api set to the `{}` branch in Cargo.toml", branch);
+    } else {
+        status!(
+            "Runtime version set to ambient_version=\"{}\" in Cargo.toml",
+            version
+        );
     }
-    match &settings.default_runtime {
-        Some(version) => Ok(RuntimeVersion::without_builds(version.clone())),
-        None => {
-            anyhow::bail!("No default runtime version set")
-        }
+    if let Some(output) = update.cargo_update_output {
+        print_cargo_update_output(&output, settings.verbose);
     }
+    if RuntimeLock::load(&package_path.0)?.is_some() {
+        let lock = lock_runtime(settings, &package_path.0)?;
+        status!(
+            "Runtime version locked to {} in {}",
+            lock.version,
+            LOCKFILE_NAME
+        );
+    }
+    Ok(())
 }
 
-fn set_default_runtime(settings: &mut Settings, version: &RuntimeVersion) -> anyhow::Result<()> {
-    version.install()?;
-    settings.default_runtime = Some(version.version.clone());
-    settings.save()?;
-    println!(
-        "The default runtime version is now {}",
-        version.version
-    );
+/// `command` is recorded in the history of default runtime changes. Refuses to change a pinned default unless
+/// `force` is set.
+fn set_default_runtime(
+    settings: &mut Settings,
+    version: &RuntimeVersion,
+    command: &str,
+    force: bool,
+    allow_downgrade: bool,
+) -> anyhow::Result<()> {
+    if !force {
+        settings.check_pin(&version.version)?;
+    }
+    if let Some(old) = settings
+        .default_runtime
+        .as_ref()
+        .filter(|_| !allow_downgrade)
+    {
+        let old_train = ReleaseTrain::from_version(old);
+        let new_train = ReleaseTrain::from_version(&version.version);
+        let mut warnings = Vec::new();
+        if version.version < *old {
+            warnings.push(format!(
+                "This downgrades the default runtime from {} to {}.",
+                old, version.version
+            ));
+        }
+        if old_train != new_train {
+            warnings.push(format!(
+                "You are switching from {} ({}) to {} ({}).{}",
+                old_train,
+                old,
+                new_train,
+                version.version,
+                match new_train {
+                    ReleaseTrain::Stable => "",
+                    ReleaseTrain::Beta => " Beta builds may be unstable.",
+                    ReleaseTrain::Nightly => " Nightly builds may be unstable.",
+                    ReleaseTrain::Internal => " Internal builds may be unstable.",
+                }
+            ));
+        }
+        if !warnings.is_empty() {
+            for warning in &warnings {
+                println!("{} {}", "Warning:".yellow().bold(), warning);
+            }
+            if !confirm(settings, "Continue?", false)? {
+                anyhow::bail!(
+                    "The default runtime was not changed to {}; pass --allow-downgrade to change it anyway",
+                    version
+                );
+            }
+        }
+    }
+    version.install_with(settings, &InstallOptions::default(), &print_progress)?;
+    let old = settings.default_runtime.clone();
+    settings.modify(|settings| {
+        if settings.default_runtime.as_ref() != Some(&version.version) {
+            settings.previous_default_runtime = settings.default_runtime.take();
+        }
+        settings.default_runtime = Some(version.version.clone());
+    })?;
+    // Only recorded once the change is saved, so that the history never has changes that didn't happen
+    if old.as_ref() != Some(&version.version) {
+        let mut history = DefaultRuntimeHistory::load()?;
+        history.record(DefaultRuntimeChange {
+            at: chrono::Utc::now(),
+            old,
+            new: version.version.clone(),
+            command: command.to_string(),
+            profile: settings.profile.clone(),
+        });
+        history.save()?;
+    }
+    status!("The default runtime version is now {}", version);
     Ok(())
 }
 
@@ -218,41 +840,624 @@ fn version_manager_main(
     mut settings: Settings,
 ) -> anyhow::Result<()> {
     let args = Args::parse();
+    settings.offline |= args.offline;
+    settings.ignore_lock |= args.ignore_lock;
+    settings.assume_yes |= args.yes;
+    settings.no_hooks |= args.no_hooks;
+    QUIET.store(args.quiet, Ordering::Relaxed);
 
     match args.command {
-        Commands::Runtime(RuntimeCommands::ListAll) => {
-            for build in get_versions(VersionsFilter {
-                include_private: true,
-                include_nightly: true,
-            })? {
-                println!("{}", build.version);
+        Commands::Runtime(RuntimeCommands::ListAll {
+            refresh_cache,
+            grouped,
+            verbose,
+            available_for,
+            json,
+            nightly_only,
+            no_nightly,
+            include_internal,
+            no_internal: _,
+            with_installed_marker,
+        }) => {
+            if refresh_cache {
+                refresh_version_cache(&settings)?;
+            }
+            let filter = VersionsFilter {
+                include_private: include_internal,
+                include_nightly: !no_nightly,
+                include_beta: true,
+            };
+            let mut versions = BucketSource::new(&settings).list(filter)?;
+            let deprecations = get_deprecations();
+            if nightly_only {
+                versions.retain(|v| v.is_nightly());
+            }
+            if let Some(os) = available_for {
+                for version in &mut versions {
+                    version.builds.retain(|b| b.os == os);
+                }
+                versions.retain(|v| !v.builds.is_empty());
+            }
+            let installed = if with_installed_marker {
+                list_installed_runtimes(&settings)?
+                    .into_iter()
+                    .map(|runtime| runtime.version)
+                    .collect_vec()
+            } else {
+                Vec::new()
+            };
+            if json {
+                let versions = versions
+                    .iter()
+                    .map(|v| {
+                        let deprecation = deprecations.get(&v.version);
+                        let mut json = serde_json::json!({
+                            "version": v.version.to_string(),
+                            "builds": v.builds.iter().map(|b| serde_json::json!({
+                                "os": b.os.to_string(),
+                                "url": b.url,
+                            })).collect_vec(),
+                            "deprecated": deprecation.map(|d| &d.reason),
+                            "yanked": deprecation.is_some_and(|d| d.yanked),
+                        });
+                        if with_installed_marker {
+                            json["installed"] = installed.contains(&v.version).into();
+                            json["default"] =
+                                (settings.default_runtime.as_ref() == Some(&v.version)).into();
+                        }
+                        json
+                    })
+                    .collect_vec();
+                println!("{}", serde_json::to_string_pretty(&versions)?);
+            } else if grouped {
+                let versions = versions.into_iter().map(|v| v.version).collect_vec();
+                for line in grouped_versions_lines(&versions, verbose) {
+                    println!("{}", line);
+                }
+            } else {
+                let versions = versions.into_iter().map(|v| v.version).collect_vec();
+                let marked = if with_installed_marker {
+                    with_installed_markers(versions, &installed, settings.default_runtime.as_ref())
+                } else {
+                    versions.into_iter().map(|v| (v, "")).collect_vec()
+                };
+                for (version, marker) in marked {
+                    match deprecations.get(&version) {
+                        Some(deprecation) => println!("{}{} ({})", version, marker, deprecation),
+                        None => println!("{}{}", version, marker),
+                    }
+                }
+            }
+        }
+        Commands::Runtime(RuntimeCommands::ListInstalled { outdated, json }) => {
+            let runtimes = list_installed_runtimes(&settings)?;
+            let is_default = |version: &str| {
+                settings
+                    .default_runtime
+                    .as_ref()
+                    .is_some_and(|default| default.to_string() == version)
+            };
+            if json {
+                let runtimes = runtimes
+                    .iter()
+                    .map(|runtime| {
+                        serde_json::json!({
+                            "version": runtime.version.to_string(),
+                            "path": runtime.dir,
+                            "store": runtime.store.to_string(),
+                            "default": is_default(&runtime.version.to_string()),
+                        })
+                    })
+                    .chain(settings.linked_runtimes.iter().map(|(name, path)| {
+                        serde_json::json!({
+                            "version": name,
+                            "path": path,
+                            "store": RuntimeStore::Linked.to_string(),
+                            "default": is_default(name),
+                        })
+                    }))
+                    .collect_vec();
+                println!("{}", serde_json::to_string_pretty(&runtimes)?);
+            } else if runtimes.is_empty() && settings.linked_runtimes.is_empty() {
+                eprintln!(
+                    "No runtime versions are installed. Run `ambient runtime install <version>` or \
+                     `ambient runtime update-default` to install one."
+                );
+                warn_if_default_not_installed(&settings)?;
+                std::process::exit(1);
+            } else if list_installed(&settings, runtimes, outdated)? {
+                std::process::exit(1);
+            }
+        }
+        Commands::Runtime(RuntimeCommands::Outdated { local, json }) => {
+            let rows = outdated_rows(&settings, package_path.as_ref(), local)?;
+            if json {
+                let rows = rows
+                    .iter()
+                    .map(|row| {
+                        serde_json::json!({
+                            "name": row.name,
+                            "current": row.current.to_string(),
+                            "wanted": row.wanted.as_ref().map(|v| v.to_string()),
+                            "latest": row.latest.as_ref().map(|v| v.to_string()),
+                            "outdated": row.is_outdated(),
+                        })
+                    })
+                    .collect_vec();
+                println!("{}", serde_json::to_string_pretty(&rows)?);
+            } else {
+                for line in outdated_table(&rows) {
+                    println!("{}", line);
+                }
+            }
+            if rows.iter().any(|row| row.is_outdated()) {
+                std::process::exit(UPDATES_AVAILABLE_EXIT_CODE);
+            }
+        }
+        Commands::Runtime(RuntimeCommands::Install {
+            version,
+            no_smoke_test,
+            os,
+            dest,
+            interactive,
+            check,
+            allow_yanked,
+        }) => {
+            let runtime_version = get_version(&settings, &version, interactive)?;
+            check_deprecation(&runtime_version, allow_yanked)?;
+            if let (None, Some(dest)) = (os, &dest) {
+                let path = runtime_version.install_to(&settings, dest, &print_progress)?;
+                status!(
+                    "Extracted runtime {} to {}",
+                    runtime_version,
+                    path.join(Os::current().ambient_bin_name()).display()
+                );
+            } else if os.is_some() || dest.is_some() {
+                runtime_version.install_for_os(
+                    &settings,
+                    os.unwrap_or_else(Os::current),
+                    dest.as_deref(),
+                    &print_progress,
+                )?;
+            } else {
+                let mut options = InstallOptions::default();
+                options.smoke_test = !no_smoke_test;
+                runtime_version.install_with(&settings, &options, &print_progress)?;
+            }
+            if check {
+                match runtime_version.verify(&settings, &print_progress) {
+                    Ok(()) => status!("{} runtime {}", "PASS".green().bold(), runtime_version),
+                    Err(err) => {
+                        println!(
+                            "{} runtime {}: {:#}",
+                            "FAIL".red().bold(),
+                            runtime_version,
+                            err
+                        );
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+        Commands::Runtime(RuntimeCommands::Prefetch {
+            version,
+            project: _,
+        }) => {
+            let runtime_version = match version {
+                Some(version) => prefetch_version(&settings, &version)?,
+                // Without a version, --project is required
+                None => {
+                    let package_path = package_path.as_ref().context("No local package found")?;
+                    let runtime = resolve_runtime(Some(&package_path.0), &settings)?;
+                    // Prefetches the locked build, verified against the locked checksums
+                    match RuntimeLock::load(&package_path.0)?.filter(|_| !settings.ignore_lock) {
+                        Some(lock) => lock.runtime(&BucketSource::new(&settings))?,
+                        None => runtime,
+                    }
+                }
+            };
+            if runtime_version.is_installed(&settings)? {
+                println!(
+                    "Runtime {} is already installed at {}",
+                    runtime_version,
+                    runtime_version.exe_path(&settings)?.display()
+                );
+            } else {
+                runtime_version.install_with(
+                    &settings,
+                    &InstallOptions::default(),
+                    &print_progress,
+                )?;
+                println!(
+                    "Prefetched runtime {} to {}",
+                    runtime_version,
+                    runtime_version.exe_path(&settings)?.display()
+                );
+            }
+        }
+        Commands::Runtime(RuntimeCommands::Info { version }) => {
+            let runtime_version = get_version(&settings, &version, false)?;
+            println!("Version: {}", runtime_version.version);
+            println!(
+                "Release train: {}",
+                ReleaseTrain::from_version(&runtime_version.version)
+            );
+            println!("Installed: {}", runtime_version.is_installed(&settings)?);
+            println!("Builds:");
+            for build in &runtime_version.builds {
+                println!("  {}: {}", build.os, build.url);
+            }
+        }
+        Commands::Runtime(RuntimeCommands::Default(DefaultCommands::Show { json })) => {
+            let runtime = settings
+                .default_runtime
+                .clone()
+                .map(RuntimeVersion::without_builds);
+            let installed = match &runtime {
+                Some(runtime) => runtime.installed(&settings)?,
+                None => None,
+            };
+            if json {
+                let default = serde_json::json!({
+                    "version": runtime.as_ref().map(|r| r.version.to_string()),
+                    "installed": installed.is_some(),
+                    "path": installed.as_ref().map(|(_, path)| path),
+                    "pinned": settings.pinned,
+                });
+                println!("{}", serde_json::to_string_pretty(&default)?);
+            } else {
+                match (&runtime, &installed) {
+                    (None, _) => println!("No default runtime version set"),
+                    (Some(runtime), Some((_, path))) => {
+                        println!("{} [installed at {}]", runtime, path.display())
+                    }
+                    (Some(runtime), None) => println!("{} [not installed]", runtime),
+                }
+            }
+        }
+        Commands::Runtime(RuntimeCommands::Mirror(MirrorCommands::Get)) => {
+            println!("{}", settings.mirror_url.as_deref().unwrap_or("(none)"));
+        }
+        Commands::Runtime(RuntimeCommands::Mirror(MirrorCommands::Set { url })) => {
+            parse_mirror_url(&url)?;
+            if !url.ends_with('/') {
+                println!(
+                    "Warning: {} doesn't end with `/`; the bucket path is appended to it directly, giving {}storage/v1/...",
+                    url, url
+                );
+            }
+            settings.modify(|settings| settings.mirror_url = Some(url.clone()))?;
+            remove_version_cache()?;
+            println!("Runtimes are now listed and downloaded from {}", url);
+        }
+        Commands::Runtime(RuntimeCommands::Mirror(MirrorCommands::Remove)) => {
+            if settings.mirror_url.is_none() {
+                anyhow::bail!("No mirror is set");
+            }
+            settings.modify(|settings| settings.mirror_url = None)?;
+            remove_version_cache()?;
+            println!("The mirror was removed; runtimes are listed and downloaded from Google Storage again");
+        }
+        Commands::Runtime(RuntimeCommands::Profile(ProfileCommands::List)) => {
+            if settings.profiles.is_empty() {
+                println!("No profiles. Create one with `ambient runtime profile create <name>`");
+            }
+            for name in settings.profiles.keys() {
+                let marker = if settings.profile.as_ref() == Some(name) {
+                    "*"
+                } else {
+                    " "
+                };
+                let active = if settings.active_profile.as_ref() == Some(name) {
+                    " (active)"
+                } else {
+                    ""
+                };
+                println!("{} {}{}", marker, name, active);
+            }
+        }
+        Commands::Runtime(RuntimeCommands::Profile(ProfileCommands::Show { name })) => {
+            let Some(name) = name.or_else(|| settings.profile.clone()) else {
+                anyhow::bail!(
+                    "No profile is in use. Pass the name of one: {}",
+                    settings.profiles.keys().join(", ")
+                );
+            };
+            // The fallbacks are the top-level settings, not those of the profile in use
+            let profile = Settings::load_or_default()?.with_profile(&name)?;
+            let own = &settings.profiles[&name];
+            let values = serde_json::to_value(&profile)?;
+            println!("Profile `{}`:", name);
+            for key in PROFILE_KEYS {
+                let value = match &values[key] {
+                    serde_json::Value::Null => "(none)".to_string(),
+                    serde_json::Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                let fallback = if own.contains_key(*key) {
+                    ""
+                } else {
+                    " (top-level)"
+                };
+                println!("  {}: {}{}", key, value, fallback);
+            }
+        }
+        Commands::Runtime(RuntimeCommands::Profile(ProfileCommands::Create { name })) => {
+            if name.is_empty() {
+                anyhow::bail!("The profile name can't be empty");
+            }
+            if settings.profiles.contains_key(&name) {
+                anyhow::bail!("Profile `{}` already exists", name);
+            }
+            settings.modify(|settings| {
+                settings.profiles.entry(name.clone()).or_default();
+            })?;
+            println!(
+                "Created profile `{}`. Use it with `--profile {}`, or by default after `ambient runtime profile switch {}`",
+                name, name, name
+            );
+        }
+        Commands::Runtime(RuntimeCommands::Profile(ProfileCommands::Switch { name })) => {
+            if let Some(name) = &name {
+                settings.with_profile(name)?;
+            }
+            settings.modify(|settings| settings.active_profile = name.clone())?;
+            match name {
+                Some(name) => println!("Switched to profile `{}`", name),
+                None => println!("Switched to the top-level settings, without a profile"),
+            }
+        }
+        Commands::Runtime(RuntimeCommands::Default(DefaultCommands::Unset)) => {
+            if settings.pinned {
+                anyhow::bail!(
+                    "The default runtime is pinned, so it was not unset. Run `ambient runtime unpin` first"
+                );
+            }
+            settings.modify(|settings| {
+                if settings.default_runtime.is_some() {
+                    settings.previous_default_runtime = settings.default_runtime.take();
+                }
+            })?;
+            println!("The default runtime version is no longer set");
+        }
+        Commands::Runtime(
+            RuntimeCommands::SetDefault {
+                version,
+                interactive,
+                force,
+                allow_downgrade,
+                if_unset,
+                allow_yanked,
+            }
+            | RuntimeCommands::Default(DefaultCommands::Set {
+                version,
+                interactive,
+                force,
+                allow_downgrade,
+                if_unset,
+                allow_yanked,
+            }),
+        ) => match &settings.default_runtime {
+            Some(default) if if_unset => {
+                status!("Default runtime already set to {}, skipping.", default);
             }
+            _ => {
+                let runtime_version = match settings.linked_runtime(&version)? {
+                    Some(_) => RuntimeVersion::without_builds(
+                        semver::Version::parse(&version).ok().with_context(|| {
+                            format!(
+                                "Runtime `{}` is linked by name, so it can only be selected with `ambient +{} ...`. \
+                                 Link it by a version like 0.4.0-dev to make it the default",
+                                version, version
+                            )
+                        })?,
+                    ),
+                    None => get_version(&settings, &version, interactive)?,
+                };
+                check_deprecation(&runtime_version, allow_yanked)?;
+                set_default_runtime(
+                    &mut settings,
+                    &runtime_version,
+                    "set-default",
+                    force,
+                    allow_downgrade,
+                )?;
+            }
+        },
+        Commands::Runtime(RuntimeCommands::Pin { reason }) => {
+            let version = settings
+                .default_runtime
+                .clone()
+                .context("No default runtime version set; set one with `ambient runtime set-default <version>` first")?;
+            settings.modify(|settings| {
+                settings.pinned = true;
+                settings.pin_reason = reason.clone();
+            })?;
+            println!("The default runtime version is pinned to {}", version);
+        }
+        Commands::Runtime(RuntimeCommands::Unpin) => {
+            settings.modify(|settings| {
+                settings.pinned = false;
+                settings.pin_reason = None;
+            })?;
+            println!("The default runtime version is no longer pinned");
         }
-        Commands::Runtime(RuntimeCommands::ListInstalled) => {
-            for (version, _) in list_installed_runtimes()? {
-                println!("{}", version);
+        Commands::Runtime(RuntimeCommands::Link { name, path }) => {
+            if name.parse::<ReleaseTrain>().is_ok() {
+                anyhow::bail!(
+                    "`{}` is a release train, so `ambient +{}` wouldn't select the link; pick another name",
+                    name,
+                    name
+                );
             }
+            let name = match semver::Version::parse(strip_version_prefix(&name)) {
+                Ok(version) => version.to_string(),
+                Err(_) => name,
+            };
+            let path = std::path::absolute(&path)?;
+            if !path.is_file() {
+                anyhow::bail!("{} is not a runtime binary", path.display());
+            }
+            settings.modify(|settings| {
+                settings.linked_runtimes.insert(name.clone(), path.clone());
+            })?;
+            println!("Runtime `{}` is now linked to {}", name, path.display());
         }
-        Commands::Runtime(RuntimeCommands::Install { version }) => {
-            let runtime_version = get_version(&version)?;
-            runtime_version.install()?;
+        Commands::Runtime(RuntimeCommands::Unlink { name }) => {
+            if !settings.linked_runtimes.contains_key(&name) {
+                anyhow::bail!("No runtime is linked as `{}`", name);
+            }
+            settings.modify(|settings| {
+                settings.linked_runtimes.remove(&name);
+            })?;
+            println!("Runtime `{}` is no longer linked", name);
         }
-        Commands::Runtime(RuntimeCommands::SetDefault { version }) => {
-            let runtime_version = get_version(&version)?;
-            set_default_runtime(&mut settings, &runtime_version)?;
+        Commands::Runtime(RuntimeCommands::Ignore { version }) => {
+            let version = semver::Version::parse(strip_version_prefix(&version))?;
+            settings.modify(|settings| {
+                if !settings.ignored_versions.contains(&version) {
+                    settings.ignored_versions.push(version.clone());
+                }
+            })?;
+            println!(
+                "Version {} added to ignore list. It will not appear as an available update.",
+                version
+            );
+        }
+        Commands::Runtime(RuntimeCommands::Unignore { version }) => {
+            let version = semver::Version::parse(strip_version_prefix(&version))?;
+            if !settings.ignored_versions.contains(&version) {
+                anyhow::bail!("Version {} is not in the ignore list", version);
+            }
+            settings.modify(|settings| settings.ignored_versions.retain(|v| *v != version))?;
+            println!("Version {} removed from the ignore list", version);
+        }
+        Commands::Runtime(RuntimeCommands::Exec { version, args }) => {
+            let code = exec_runtime(&settings, package_path.as_ref(), &version, args)?;
+            std::process::exit(code);
+        }
+        Commands::Runtime(RuntimeCommands::SetLocal {
+            version,
+            interactive,
+            force_remote,
+            branch,
+        }) => {
+            let package_path = package_path.as_ref().context("No local package found")?;
+            let runtime_version = get_version(&settings, &version, interactive)?;
+            let options = SetRuntimeOptions {
+                force_remote,
+                ambient_api_branch: branch,
+                ..Default::default()
+            };
+            set_local_runtime(&settings, package_path, &runtime_version.version, &options)?;
+        }
+        Commands::Runtime(RuntimeCommands::Lock) => {
+            let package_path = package_path.as_ref().context("No local package found")?;
+            let lock = lock_runtime(&settings, &package_path.0)?;
+            println!(
+                "Locked runtime {} in {}",
+                lock.version,
+                RuntimeLock::path(&package_path.0).display()
+            );
         }
-        Commands::Runtime(RuntimeCommands::SetLocal { version }) => {
-            package_path
+        Commands::Runtime(RuntimeCommands::ShowLocal) => {
+            let package_path = package_path.as_ref().context("No local package found")?;
+            let ambient_toml = package_path
+                .ambient_toml()
+                .get_content()?
+                .context("No ambient.toml found")?;
+            match ambient_toml.package.ambient_version {
+                Some(version_req) => println!("{}", version_req),
+                None => println!("No ambient_version set in ambient.toml"),
+            }
+        }
+        Commands::Runtime(RuntimeCommands::UpdateDefault { check, train }) => {
+            let version = get_default_update(&BucketSource::new(&settings), &settings, train)?;
+            let version_train = ReleaseTrain::from_version(&version.version);
+            let required = package_path
                 .as_ref()
-                .context("No local package found")?
-                .set_runtime(&semver::Version::parse(&version)?)?;
+                .and_then(|p| p.ambient_toml().get_content().ok().flatten())
+                .and_then(|toml| toml.package.ambient_version);
+            if let Some(required) = required.filter(|req| {
+                ReleaseTrain::from_version_req(req).is_some_and(|train| train != version_train)
+            }) {
+                println!(
+                    "Note: the local package requires {}, which isn't on the {} release train the default is updated on",
+                    required, version_train
+                );
+            }
+            if check {
+                match default_kept_reason(&settings, &version.version, train) {
+                    Some(reason) => println!("{}, so the default would be kept", reason),
+                    None => {
+                        let current = settings.default_runtime.as_ref();
+                        if check_update("The default runtime", current, &version.version) {
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            } else if let Some(current) = settings
+                .default_runtime
+                .as_ref()
+                .filter(|current| train.is_none() && version.version < **current)
+            {
+                println!(
+                    "The latest {} runtime {} is older than the default runtime {}, so the default was kept",
+                    version_train, version, current
+                );
+            } else {
+                // Picking a train explicitly is how to move to another one, even if its latest version is older
+                set_default_runtime(
+                    &mut settings,
+                    &version,
+                    "update-default",
+                    false,
+                    train.is_some(),
+                )?;
+            }
         }
-        Commands::Runtime(RuntimeCommands::UpdateDefault) => {
-            let version = get_latest_remote_version_for_train(settings.release_train(), false)?;
-            set_default_runtime(&mut settings, &version)?;
+        Commands::Runtime(RuntimeCommands::UpdateLocal {
+            fix: true,
+            force_remote,
+            update_lock,
+            ..
+        }) => {
+            let package_path = package_path.as_ref().context("No local package found")?;
+            let version = resolve_runtime(Some(&package_path.0), &settings)?.version;
+            let cargo_toml = package_path.cargo_toml();
+            match cargo_toml
+                .with_ambient_api(&AmbientApiSpec::for_version(&version), force_remote)?
+            {
+                Some(content) => {
+                    write_file_atomically(&cargo_toml.0, &content)?;
+                    println!("ambient_api set to {} in Cargo.toml", version);
+                    if update_lock {
+                        if let Some(output) = package_path.update_cargo_lock()? {
+                            print_cargo_update_output(&output, settings.verbose);
+                        }
+                    }
+                }
+                None => println!("{}", LOCAL_AMBIENT_API_WARNING),
+            }
         }
-        Commands::Runtime(RuntimeCommands::UpdateLocal) => {
+        Commands::Runtime(RuntimeCommands::UpdateLocal {
+            minor,
+            patch,
+            check,
+            fix: false,
+            force_remote,
+            update_lock,
+        }) => {
             let package_path = package_path.as_ref().context("No local package found")?;
+            let current = if minor || patch || check {
+                Some(resolve_runtime(Some(&package_path.0), &settings)?.version)
+            } else {
+                None
+            };
+            let constraint = current
+                .as_ref()
+                .filter(|_| minor || patch)
+                .and_then(|current| update_constraint(current, minor, patch));
             let ambient_toml = package_path
                 .ambient_toml()
                 .get_content()?
@@ -260,56 +1465,1674 @@ fn version_manager_main(
             let release_train = ambient_toml
                 .package
                 .ambient_version
-                .map(|v| ReleaseTrain::from_version_req(&v))
-                .unwrap_or(ReleaseTrain::Stable);
-            let version = get_latest_remote_version_for_train(release_train, false)?;
-            package_path.set_runtime(&version.version)?;
+                .and_then(|v| ReleaseTrain::from_version_req(&v))
+                .unwrap_or_else(|| settings.release_train());
+            let version = get_latest_remote_version_for_train(
+                &BucketSource::new(&settings),
+                release_train,
+                false,
+                constraint.as_ref(),
+                &settings.ignored_versions,
+            )?;
+            if check {
+                if check_update(
+                    "The local package's runtime",
+                    current.as_ref(),
+                    &version.version,
+                ) {
+                    std::process::exit(1);
+                }
+            } else {
+                let options = SetRuntimeOptions {
+                    force_remote,
+                    update_cargo_lock: update_lock,
+                    ..Default::default()
+                };
+                set_local_runtime(&settings, package_path, &version.version, &options)?;
+            }
+        }
+        Commands::Runtime(RuntimeCommands::UpdateAllLocal) => {
+            let start = match package_path {
+                Some(package_path) => package_path.0.clone(),
+                None => std::env::current_dir()?,
+            };
+            let target = match PackagePath::find_workspace_root(&start) {
+                Some(root) => {
+                    println!("Updating the workspace at {:?}", root.0);
+                    root
+                }
+                None => {
+                    let package_path = package_path
+                        .as_ref()
+                        .context("No local package or workspace found")?;
+                    println!("Updating the standalone package at {:?}", package_path.0);
+                    PackagePath(package_path.0.clone())
+                }
+            };
+            let release_train = target
+                .ambient_toml()
+                .get_content()?
+                .and_then(|toml| toml.package.ambient_version)
+                .and_then(|v| ReleaseTrain::from_version_req(&v))
+                .unwrap_or_else(|| settings.release_train());
+            let version = get_latest_remote_version_for_train(
+                &BucketSource::new(&settings),
+                release_train,
+                false,
+                None,
+                &settings.ignored_versions,
+            )?;
+            set_local_runtime(
+                &settings,
+                &target,
+                &version.version,
+                &SetRuntimeOptions::default(),
+            )?;
         }
         Commands::Runtime(RuntimeCommands::ShowSettingsPath) => {
             println!("{}", settings_path()?.to_string_lossy());
         }
-        Commands::Runtime(RuntimeCommands::UninstallAll) => {
-            std::fs::remove_dir_all(runtimes_dir()?)?;
-            std::fs::create_dir_all(runtimes_dir()?)?;
+        Commands::Runtime(RuntimeCommands::ShowDataDir) => {
+            println!("{}", runtimes_dir()?.to_string_lossy());
+        }
+        Commands::Runtime(RuntimeCommands::ShowConfigDir) => {
+            println!("{}", settings_dir()?.to_string_lossy());
+        }
+        Commands::Runtime(RuntimeCommands::ShowAppDir) => {
+            println!("{}", app_dir()?.data_dir().to_string_lossy());
+        }
+        Commands::Runtime(RuntimeCommands::Logs {
+            last_run,
+            tail: lines,
+        }) => {
+            let logs = RuntimeLogs::new(&settings)?.list()?;
+            match logs.last() {
+                None => println!(
+                    "No runtime logs have been captured. Set \"capture_logs\": true in {} to capture them",
+                    settings_path()?.display()
+                ),
+                Some((path, _)) if last_run => match lines {
+                    Some(lines) => {
+                        let content = std::fs::read(path)
+                            .with_context(|| format!("Failed to read {:?}", path))?;
+                        for line in tail(&String::from_utf8_lossy(&content), lines) {
+                            println!("{}", line);
+                        }
+                    }
+                    None => println!("{}", path.display()),
+                },
+                Some(_) => {
+                    for (path, size) in &logs {
+                        println!("{} ({})", path.display(), megabytes(*size));
+                    }
+                }
+            }
+        }
+        Commands::Runtime(RuntimeCommands::ShowPaths) => {
+            println!("App dir:       {}", app_dir()?.data_dir().display());
+            println!("Runtimes:      {}", runtimes_dir()?.display());
+            if let Some(shared) = &settings.shared_runtimes_dir {
+                println!("Shared:        {}", shared.display());
+            }
+            println!("Config dir:    {}", settings_dir()?.display());
+            println!("Settings:      {}", settings_path()?.display());
+            println!("Version cache: {}", version_cache_path()?.display());
+            println!("Downloads:     {}", download_cache_dir()?.display());
+            println!("Logs:          {}", logs_dir()?.display());
+        }
+        Commands::Runtime(RuntimeCommands::Uninstall { version, shared }) => {
+            let version = semver::Version::parse(strip_version_prefix(&version))?;
+            if settings.linked_runtimes.contains_key(&version.to_string()) {
+                anyhow::bail!(
+                    "Runtime {} is linked; remove the link with `ambient runtime unlink {}`",
+                    version,
+                    version
+                );
+            }
+            let runtime = list_installed_runtimes(&settings)?
+                .into_iter()
+                .find(|r| r.version == version)
+                .with_context(|| format!("Version {} is not installed", version))?;
+            if runtime.store == RuntimeStore::Shared && !shared {
+                anyhow::bail!(
+                    "Version {} is installed in the shared runtimes dir {:?}; pass --shared to remove it",
+                    version,
+                    runtime.dir
+                );
+            }
+            runtime.uninstall(&settings, &print_progress)?;
+            println!("Uninstalled {} from {:?}", version, runtime.dir);
+        }
+        Commands::Runtime(RuntimeCommands::UninstallAll { shared }) => {
+            for (store, dir) in settings.runtime_stores()? {
+                if store == RuntimeStore::Shared && !shared {
+                    println!(
+                        "Keeping the runtimes in the shared runtimes dir {:?}; pass --shared to remove them",
+                        dir
+                    );
+                    continue;
+                }
+                for runtime in list_installed_runtimes(&settings)?
+                    .into_iter()
+                    .filter(|runtime| runtime.store == store)
+                {
+                    runtime.uninstall(&settings, &print_progress)?;
+                }
+                if dir.exists() {
+                    std::fs::remove_dir_all(&dir)?;
+                }
+                std::fs::create_dir_all(&dir)?;
+            }
+        }
+        Commands::Runtime(RuntimeCommands::Prune { keep, dry_run }) => {
+            let installed = list_installed_runtimes(&settings)?;
+            let mut total = 0;
+            for runtime in runtimes_to_prune(&settings, installed, keep) {
+                let size = dir_size(&runtime.dir)?;
+                total += size;
+                if dry_run {
+                    println!("Would remove {} ({})", runtime.version, megabytes(size));
+                } else {
+                    runtime.uninstall(&settings, &print_progress)?;
+                    println!("Removed {} ({})", runtime.version, megabytes(size));
+                }
+            }
+            if dry_run {
+                println!("Would reclaim {}", megabytes(total));
+            } else {
+                println!("Reclaimed {}", megabytes(total));
+            }
+        }
+        Commands::Runtime(RuntimeCommands::RefreshCache) => {
+            let cache = refresh_version_cache(&settings)?;
+            println!("Fetched {} runtime versions", cache.version_count);
+        }
+        Commands::Runtime(RuntimeCommands::CleanCache) => {
+            let freed = DownloadCache::new(&settings)?.clean()?;
+            println!("Removed {} of cached downloads", megabytes(freed));
+        }
+        Commands::Runtime(RuntimeCommands::DiskUsage) => {
+            let mut total = 0;
+            for runtime in list_installed_runtimes(&settings)? {
+                let size = dir_size(&runtime.dir)?;
+                total += size;
+                println!("{}: {}", runtime.version, megabytes(size));
+            }
+            println!("Installed runtimes: {}", megabytes(total));
+            let cache = DownloadCache::new(&settings)?;
+            println!(
+                "Download cache: {} ({})",
+                megabytes(cache.size()?),
+                cache.dir().display()
+            );
+        }
+        Commands::GenerateManpages { dir } => {
+            let paths = manpages::write_manpages(
+                <Args as clap::CommandFactory>::command().name("ambient"),
+                EXEC_PASSTHROUGH_DESCRIPTION,
+                &dir,
+            )?;
+            println!("Wrote {} man pages to {}", paths.len(), dir.display());
+        }
+        Commands::New {
+            name,
+            template,
+            path,
+        } => {
+            let version = settings.default_runtime.as_ref().context(
+                "No default runtime version set; set one with `ambient runtime set-default <version>`",
+            )?;
+            let path = path.unwrap_or_else(|| PathBuf::from(&name));
+            create_project(&path, &name, template.unwrap_or_default(), version)?;
+            println!(
+                "Created project at {}. Run `cd {} && ambient run` to start.",
+                path.display(),
+                path.display()
+            );
+        }
+        Commands::Runtime(RuntimeCommands::Doctor { fix, dry_run }) => {
+            if fix {
+                fix_broken_runtime_dirs(&settings, dry_run)?;
+                println!();
+            }
+            if !run_doctor(&settings, package_path.as_ref()) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Runtime(RuntimeCommands::CheckProject) => {
+            let package_path = package_path.as_ref().context("No local package found")?;
+            if !run_check_project(&settings, package_path) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Runtime(RuntimeCommands::Status { json }) => {
+            // Only looks at what's already on disk, so that it works (and is quick) when something is broken
+            let mut local_settings = settings.clone();
+            local_settings.offline = true;
+            let installed_label = |installed: bool| {
+                if installed {
+                    "installed"
+                } else {
+                    "not installed"
+                }
+            };
+            let default_installed = match &settings.default_runtime {
+                Some(version) => {
+                    RuntimeVersion::without_builds(version.clone()).is_installed(&settings)?
+                }
+                None => false,
+            };
+            let ambient_toml = match &package_path {
+                Some(package_path) => package_path.ambient_toml().get_content()?,
+                None => None,
+            };
+            let requirement = ambient_toml
+                .as_ref()
+                .and_then(|ambient_toml| ambient_toml.package.ambient_version.clone());
+            let runtime = resolve_runtime(
+                package_path.as_ref().map(|p| p.0.as_path()),
+                &local_settings,
+            );
+            let runtime_installed = match &runtime {
+                Ok(runtime) => runtime.is_installed(&settings)?,
+                Err(_) => false,
+            };
+            let installed = list_installed_runtimes(&settings)?;
+            let installed_size = installed
+                .iter()
+                .map(|runtime| dir_size(&runtime.dir))
+                .sum::<anyhow::Result<u64>>()?;
+            let fetched_at = version_cache_fetched_at();
+            if json {
+                let status = serde_json::json!({
+                    "cli_version": env!("CARGO_PKG_VERSION"),
+                    "settings_path": settings_path()?,
+                    "channel": settings.release_train().to_string(),
+                    "default_runtime": {
+                        "version": settings.default_runtime.as_ref().map(|v| v.to_string()),
+                        "installed": default_installed,
+                        "pinned": settings.pinned,
+                    },
+                    "package": ambient_toml.as_ref().map(|ambient_toml| serde_json::json!({
+                        "name": ambient_toml.package.name,
+                        "path": package_path.as_ref().map(|p| &p.0),
+                        "ambient_version": requirement.as_ref().map(|r| r.to_string()),
+                    })),
+                    "runtime": match &runtime {
+                        Ok(runtime) => serde_json::json!({
+                            "version": runtime.version.to_string(),
+                            "installed": runtime_installed,
+                        }),
+                        Err(err) => serde_json::json!({ "error": format!("{:#}", err) }),
+                    },
+                    "installed_runtimes": {
+                        "count": installed.len(),
+                        "size_bytes": installed_size,
+                    },
+                    "version_list_fetched_at": fetched_at,
+                });
+                println!("{}", serde_json::to_string_pretty(&status)?);
+            } else {
+                println!("CLI version: {}", env!("CARGO_PKG_VERSION"));
+                println!("Settings: {}", settings_path()?.display());
+                println!("Channel: {}", settings.release_train());
+                match (&settings.default_runtime, &settings.pin_reason) {
+                    (Some(version), Some(reason)) if settings.pinned => println!(
+                        "Default runtime: {} [{}] (pinned: {})",
+                        version,
+                        installed_label(default_installed),
+                        reason
+                    ),
+                    (Some(version), None) if settings.pinned => println!(
+                        "Default runtime: {} [{}] (pinned)",
+                        version,
+                        installed_label(default_installed)
+                    ),
+                    (Some(version), _) => println!(
+                        "Default runtime: {} [{}]",
+                        version,
+                        installed_label(default_installed)
+                    ),
+                    (None, _) => println!("Default runtime: not set"),
+                }
+                if let Some(ambient_toml) = &ambient_toml {
+                    println!("Package: {}", ambient_toml.package.summary());
+                    match &requirement {
+                        Some(requirement) => println!("Package requires: {}", requirement),
+                        None => println!("Package requires: any (uses the default runtime)"),
+                    }
+                }
+                match &runtime {
+                    Ok(runtime) => println!(
+                        "Runtime: {} [{}]",
+                        runtime,
+                        installed_label(runtime_installed)
+                    ),
+                    Err(_) if requirement.is_some() && fetched_at.is_none() => println!(
+                        "Runtime: unknown until the version list is fetched with `ambient runtime refresh-cache`"
+                    ),
+                    Err(err) => println!("Runtime: could not be resolved: {:#}", err),
+                }
+                println!(
+                    "Installed runtimes: {} ({})",
+                    installed.len(),
+                    megabytes(installed_size)
+                );
+                match fetched_at {
+                    Some(at) => println!(
+                        "Version list fetched: {}",
+                        at.format("%Y-%m-%d %H:%M:%S UTC")
+                    ),
+                    None => println!("Version list fetched: never"),
+                }
+            }
+        }
+        Commands::Runtime(RuntimeCommands::Resolve { manifest, json }) => {
+            let package_dir = match manifest {
+                Some(manifest) if manifest.is_dir() => Some(manifest),
+                Some(manifest) => {
+                    anyhow::ensure!(manifest.exists(), "{} doesn't exist", manifest.display());
+                    Some(
+                        manifest
+                            .parent()
+                            .map(|dir| dir.to_path_buf())
+                            .unwrap_or_default(),
+                    )
+                }
+                None => package_path.as_ref().map(|p| p.0.clone()),
+            };
+            let resolution = match resolve_runtime_with_tier(package_dir.as_deref(), &settings) {
+                Ok(resolution) => resolution,
+                Err(err) => {
+                    eprintln!("Error: {:#}", err);
+                    std::process::exit(VERSION_NOT_FOUND_EXIT_CODE);
+                }
+            };
+            if json {
+                let resolved = serde_json::json!({
+                    "version": resolution.runtime.version.to_string(),
+                    "requirement": resolution.requirement.map(|r| r.to_string()),
+                    "source": resolution.tier.to_string(),
+                    "installed": resolution.runtime.is_installed(&settings)?,
+                    "manifest": package_dir.map(|dir| dir.join("ambient.toml")),
+                });
+                println!("{}", serde_json::to_string_pretty(&resolved)?);
+            } else {
+                println!("{}", resolution.runtime.version);
+            }
+        }
+        Commands::Runtime(RuntimeCommands::Which) => {
+            let runtime = resolve_runtime(package_path.as_ref().map(|p| p.0.as_path()), &settings)?;
+            if let Some(name) = package_path.as_ref().and_then(|p| p.package_name()) {
+                println!("Package `{}` uses runtime {}", name, runtime);
+            }
+            if !runtime.is_installed(&settings)? {
+                anyhow::bail!("Runtime {} is not installed", runtime);
+            }
+            println!("{}", runtime.exe_path(&settings)?.display());
+        }
+        Commands::Runtime(RuntimeCommands::Env { shell, install }) => {
+            let runtime = resolve_runtime(package_path.as_ref().map(|p| p.0.as_path()), &settings)?;
+            if !runtime.is_installed(&settings)? {
+                if !install {
+                    anyhow::bail!(
+                        "Runtime {} is not installed. Pass --install to install it",
+                        runtime
+                    );
+                }
+                // The progress goes to stderr, so that stdout can be evaluated as is
+                runtime.install_with(&settings, &InstallOptions::default(), &|progress| {
+                    eprintln!("{}", progress)
+                })?;
+            }
+            print!(
+                "{}",
+                env_script(shell, &runtime.exe_path(&settings)?, &runtime.version)
+            );
+        }
+        Commands::Runtime(RuntimeCommands::Rollback) => {
+            let history = DefaultRuntimeHistory::load()?;
+            let previous = history
+                .previous(settings.profile.as_deref())
+                .or(settings.previous_default_runtime.as_ref())
+                .cloned();
+            if let Some(previous) = previous {
+                let current = settings.default_runtime.clone();
+                // Reinstalls the previous version if it has been uninstalled since
+                set_default_runtime(
+                    &mut settings,
+                    &RuntimeVersion::without_builds(previous.clone()),
+                    "rollback",
+                    false,
+                    true,
+                )?;
+                match current {
+                    Some(current) => println!("Rolled back from {} to {}", current, previous),
+                    None => println!("Rolled back to {}", previous),
+                }
+            } else {
+                println!("No previous version to roll back to.");
+            }
+        }
+        Commands::Runtime(RuntimeCommands::History) => {
+            let history = DefaultRuntimeHistory::load()?;
+            let changes = history
+                .changes_for(settings.profile.as_deref())
+                .collect_vec();
+            if changes.is_empty() {
+                println!("The default runtime version hasn't been changed yet.");
+            }
+            for change in changes {
+                println!("{}", change);
+            }
         }
     }
     Ok(())
 }
 
+/// Asks whether to go ahead on a terminal, where an empty answer means `default`. Goes ahead with `--yes` /
+/// `assume_yes`, and otherwise answers `default` when not on a terminal
+fn confirm(settings: &Settings, question: &str, default: bool) -> anyhow::Result<bool> {
+    if settings.assume_yes {
+        return Ok(true);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Ok(default);
+    }
+    print!("{} {} ", question, if default { "[Y/n]" } else { "[y/N]" });
+    std::io::stdout().flush()?;
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    Ok(match line.trim().to_lowercase().as_str() {
+        "" => default,
+        answer => matches!(answer, "y" | "yes"),
+    })
+}
+
+/// Prints the installed runtimes, marking the outdated ones if `outdated` is set. Returns whether any are outdated
+fn list_installed(
+    settings: &Settings,
+    runtimes: Vec<InstalledRuntime>,
+    outdated: bool,
+) -> anyhow::Result<bool> {
+    let remote_versions = if outdated {
+        BucketSource::new(settings)
+            .list(VersionsFilter::all())?
+            .into_iter()
+            .map(|v| v.version)
+            .collect_vec()
+    } else {
+        Vec::new()
+    };
+    let mut any_outdated = false;
+    for runtime in runtimes {
+        let mut line = runtime.version.to_string();
+        if settings.shared_runtimes_dir.is_some() {
+            line += &format!(" ({})", runtime.store);
+        }
+        if settings.default_runtime.as_ref() == Some(&runtime.version) {
+            line += " (default)";
+        }
+        if outdated {
+            match newer_in_train(&runtime.version, &remote_versions) {
+                Some(latest) => {
+                    any_outdated = true;
+                    line += &format!(" [outdated: latest is {}]", latest);
+                }
+                None => line += " [current]",
+            }
+        }
+        println!("{}", line);
+    }
+    for (name, path) in &settings.linked_runtimes {
+        let default = match &settings.default_runtime {
+            Some(default) if default.to_string() == *name => " (default)",
+            _ => "",
+        };
+        let missing = if path.is_file() { "" } else { " [missing]" };
+        println!(
+            "{} (linked){} -> {}{}",
+            name,
+            default,
+            path.display(),
+            missing
+        );
+    }
+    warn_if_default_not_installed(settings)?;
+    Ok(any_outdated)
+}
+
+fn warn_if_default_not_installed(settings: &Settings) -> anyhow::Result<()> {
+    if let Some(default) = &settings.default_runtime {
+        if !RuntimeVersion::without_builds(default.clone()).is_installed(settings)? {
+            println!(
+                "Default version {} is not installed. Run `ambient runtime install {}` to install it.",
+                default, default
+            );
+        }
+    }
+    Ok(())
+}
+
+/// `runtime outdated` exits with this when updates are available, to tell it apart from failing
+const UPDATES_AVAILABLE_EXIT_CODE: i32 = 2;
+/// Exit code of `runtime resolve` when no runtime version satisfies the requirement
+const VERSION_NOT_FOUND_EXIT_CODE: i32 = 3;
+
+/// A row of `runtime outdated`
+struct OutdatedRow {
+    name: String,
+    current: semver::Version,
+    /// The newest version the package's ambient_version allows
+    wanted: Option<semver::Version>,
+    /// The newest version in the release train of `current`, `None` if the train has no versions to offer
+    latest: Option<semver::Version>,
+}
+impl OutdatedRow {
+    fn is_outdated(&self) -> bool {
+        self.latest
+            .as_ref()
+            .is_some_and(|latest| is_newer(latest, &self.current))
+    }
+}
+
+/// Checks the default runtime, the local package's runtime and the installed runtimes, or only the package's with `local`
+fn outdated_rows(
+    settings: &Settings,
+    package_path: Option<&PackagePath>,
+    local: bool,
+) -> anyhow::Result<Vec<OutdatedRow>> {
+    let source = BucketSource::new(settings);
+    // Fetched up front, so that failing to fetch the listing isn't mistaken for a train without versions below
+    let remote_versions = source
+        .list(VersionsFilter::all())?
+        .into_iter()
+        .map(|v| v.version)
+        .collect_vec();
+    let latest_in_train = |train| {
+        get_latest_remote_version_for_train(&source, train, false, None, &settings.ignored_versions)
+            .ok()
+            .map(|v| v.version)
+    };
+    let mut rows = Vec::new();
+    if !local {
+        if let Some(default) = &settings.default_runtime {
+            rows.push(OutdatedRow {
+                name: "default".to_string(),
+                current: default.clone(),
+                wanted: None,
+                latest: latest_in_train(ReleaseTrain::from_version(default)),
+            });
+        }
+    }
+    let version_req = match package_path {
+        Some(package_path) => package_path
+            .ambient_toml()
+            .get_content()?
+            .and_then(|toml| toml.package.ambient_version),
+        None if local => anyhow::bail!("No local package found"),
+        None => None,
+    };
+    if let (Some(package_path), Some(version_req)) = (package_path, version_req) {
+        let current = resolve_runtime(Some(&package_path.0), settings)?;
+        let train = ReleaseTrain::from_version_req(&version_req)
+            .unwrap_or_else(|| settings.release_train());
+        rows.push(OutdatedRow {
+            name: match package_path.package_name() {
+                Some(name) => format!("package `{}`", name),
+                None => "package".to_string(),
+            },
+            current: current.version,
+            wanted: get_latest_remote_version_for_train(
+                &source,
+                train,
+                false,
+                Some(&version_req),
+                &settings.ignored_versions,
+            )
+            .ok()
+            .map(|v| v.version),
+            latest: latest_in_train(train),
+        });
+    } else if local {
+        anyhow::bail!("The local package's ambient.toml has no ambient_version");
+    }
+    if !local {
+        for runtime in list_installed_runtimes(settings)? {
+            if settings.default_runtime.as_ref() == Some(&runtime.version) {
+                continue;
+            }
+            if let Some(latest) = newer_in_train(&runtime.version, &remote_versions) {
+                rows.push(OutdatedRow {
+                    name: "installed".to_string(),
+                    current: runtime.version,
+                    wanted: None,
+                    latest: Some(latest.clone()),
+                });
+            }
+        }
+    }
+    Ok(rows)
+}
+
+/// Formats the rows of `runtime outdated` as aligned columns under a header
+fn outdated_table(rows: &[OutdatedRow]) -> Vec<String> {
+    let cells = std::iter::once([
+        "".to_string(),
+        "Current".into(),
+        "Wanted".into(),
+        "Latest".into(),
+    ])
+    .chain(rows.iter().map(|row| {
+        [
+            row.name.clone(),
+            row.current.to_string(),
+            row.wanted
+                .as_ref()
+                .map_or("-".to_string(), |v| v.to_string()),
+            row.latest
+                .as_ref()
+                .map_or("unknown".to_string(), |v| v.to_string()),
+        ]
+    }))
+    .collect_vec();
+    let widths = (0..4)
+        .map(|i| cells.iter().map(|row| row[i].len()).max().unwrap_or(0))
+        .collect_vec();
+    cells
+        .iter()
+        .map(|row| {
+            row.iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect()
+}
+
+#[test]
+fn test_take_runtime_override() {
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    let mut with_override = args(&["+nightly", "--yes", "run", "game"]);
+    assert_eq!(
+        take_runtime_override(&mut with_override).as_deref(),
+        Some("nightly")
+    );
+    assert_eq!(with_override, args(&["--yes", "run", "game"]));
+    assert!(take_flag(&mut with_override, "--yes"));
+    assert_eq!(with_override, args(&["run", "game"]));
+
+    let mut without = args(&["run", "+nightly"]);
+    assert_eq!(take_runtime_override(&mut without), None);
+    assert_eq!(without, args(&["run", "+nightly"]));
+}
+
+#[test]
+fn test_take_env_flags() {
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    let mut with_env = args(&["--env", "A=1", "--env=B=x=y", "run", "game", "--env", "C=1"]);
+    assert_eq!(
+        take_env_flags(&mut with_env).unwrap(),
+        [
+            ("A".to_string(), "1".to_string()),
+            ("B".to_string(), "x=y".to_string())
+        ]
+    );
+    // The runtime's own --env is passed on
+    assert_eq!(with_env, args(&["run", "game", "--env", "C=1"]));
+    assert!(take_env_flags(&mut args(&["--env", "A", "run"])).is_err());
+    assert!(take_env_flags(&mut args(&["--env"])).is_err());
+    assert!(take_env_flags(&mut args(&["--env", "--", "A=1"])).is_err());
+}
+
+#[test]
+fn test_take_flag() {
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    let mut before = args(&["--exec", "run", "game"]);
+    assert!(take_flag(&mut before, "--exec"));
+    assert_eq!(before, args(&["run", "game"]));
+    // Flags after the runtime's subcommand or `--` are the runtime's
+    for runtime_args in [
+        &["run", "--force"][..],
+        &["run", "--", "--force"],
+        &["--", "--force"],
+    ] {
+        let mut after = args(runtime_args);
+        assert!(!take_flag(&mut after, "--force"));
+        assert_eq!(after, args(runtime_args));
+    }
+
+    // The version manager's commands take their flags anywhere before `--`
+    let mut manager = args(&["runtime", "list", "--profile", "ci", "--", "--profile", "x"]);
+    assert_eq!(
+        take_profile_flag(&mut manager).unwrap().as_deref(),
+        Some("ci")
+    );
+    assert_eq!(manager, args(&["runtime", "list", "--", "--profile", "x"]));
+    let mut runtime = args(&["--profile=ci", "run", "--profile", "x"]);
+    assert_eq!(
+        take_profile_flag(&mut runtime).unwrap().as_deref(),
+        Some("ci")
+    );
+    assert_eq!(runtime, args(&["run", "--profile", "x"]));
+    assert!(take_profile_flag(&mut args(&["--profile", "--", "run"])).is_err());
+
+    // Leading global flags don't hide the version manager's commands
+    for flags in [&["--quiet"][..], &["--offline", "--profile", "ci"], &[]] {
+        let mut manager = args(flags);
+        manager.extend(args(&["runtime", "list-installed"]));
+        assert!(is_version_manager_command(&manager));
+        assert_eq!(
+            &manager[command_index(&manager)..],
+            args(&["runtime", "list-installed"])
+        );
+    }
+    assert!(!is_version_manager_command(&args(&[
+        "--quiet", "run", "runtime"
+    ])));
+    assert!(!is_version_manager_command(&args(&["--", "runtime"])));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_runtime_command_env() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("ambient");
+    std::fs::write(
+        &script,
+        "#!/bin/sh\necho \"$AMBIENT_RUNTIME_VERSION $AMBIENT_PACKAGE_PATH $TEST_FROM_SETTINGS $TEST_FROM_FLAG $TEST_INHERITED\"\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    // Stands in for the environment the runtime inherits, without changing the test process's
+    let run = |runtime: &RuntimeCommand| {
+        let mut command = std::process::Command::new(&script);
+        command
+            .args(&runtime.args)
+            .env("TEST_INHERITED", "inherited")
+            .env("TEST_FROM_SETTINGS", "inherited");
+        runtime.apply_env(&mut command);
+        String::from_utf8_lossy(&command.output().unwrap().stdout).to_string()
+    };
+    let mut settings = Settings::default();
+    for (key, value) in [
+        ("TEST_FROM_SETTINGS", "settings"),
+        ("TEST_FROM_FLAG", "settings"),
+        ("AMBIENT_RUNTIME_VERSION", "overridden"),
+    ] {
+        settings.env.insert(key.to_string(), value.to_string());
+    }
+    let mut args = ["--env", "TEST_FROM_FLAG=flag", "run"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect_vec();
+    settings.env.extend(take_env_flags(&mut args).unwrap());
+    assert_eq!(args, ["run"]);
+    let package = PackagePath(PathBuf::from("/games/demo"));
+    let runtime = RuntimeCommand::new(
+        &settings,
+        script.clone(),
+        args,
+        Some(&semver::Version::new(0, 3, 0)),
+        Some(&package),
+    );
+    assert_eq!(
+        run(&runtime),
+        "overridden /games/demo settings flag inherited\n"
+    );
+
+    let runtime = RuntimeCommand::new(
+        &Settings::default(),
+        script.clone(),
+        Vec::new(),
+        Some(&semver::Version::new(0, 3, 0)),
+        None,
+    );
+    assert_eq!(run(&runtime), "0.3.0  inherited  inherited\n");
+}
+
+#[test]
+fn test_package_path_arg() {
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        package_path_arg(&args(&["run", "./my-project", "--release"])),
+        Some("./my-project")
+    );
+    assert_eq!(package_path_arg(&args(&["build", "game"])), Some("game"));
+    assert_eq!(
+        package_path_arg(&args(&["--env", "A=1", "--exec", "run", "game"])),
+        Some("game")
+    );
+    assert_eq!(package_path_arg(&args(&["run"])), None);
+    assert_eq!(package_path_arg(&args(&["runtime", "game"])), None);
+    assert_eq!(package_path_arg(&args(&["new", "game"])), None);
+}
+
+#[test]
+fn test_outdated_table() {
+    let version = |v: &str| semver::Version::parse(v).unwrap();
+    let rows = [
+        OutdatedRow {
+            name: "default".to_string(),
+            current: version("0.3.0"),
+            wanted: None,
+            latest: Some(version("0.3.1")),
+        },
+        OutdatedRow {
+            name: "package `my_game`".to_string(),
+            current: version("0.3.1"),
+            wanted: Some(version("0.3.1")),
+            latest: Some(version("0.3.1")),
+        },
+        OutdatedRow {
+            name: "installed".to_string(),
+            current: version("0.4.0-rc.1"),
+            wanted: None,
+            latest: None,
+        },
+    ];
+    assert!(rows[0].is_outdated());
+    assert!(!rows[1].is_outdated());
+    assert!(!rows[2].is_outdated());
+    assert_eq!(
+        outdated_table(&rows),
+        vec![
+            "                   Current     Wanted  Latest",
+            "default            0.3.0       -       0.3.1",
+            "package `my_game`  0.3.1       0.3.1   0.3.1",
+            "installed          0.4.0-rc.1  -       unknown",
+        ]
+    );
+
+    // Nightlies are ordered by build date, like the installed runtimes are checked
+    let nightly = OutdatedRow {
+        name: "default".to_string(),
+        current: version("0.3.2-nightly-2023-10-01"),
+        wanted: None,
+        latest: Some(version("0.3.1-nightly-2023-10-05")),
+    };
+    assert!(nightly.is_outdated());
+}
+
+/// Why `update-default` keeps the default runtime rather than moving it to `latest`: `latest` is older and no
+/// `train` was picked explicitly, or the default is pinned
+fn default_kept_reason(
+    settings: &Settings,
+    latest: &semver::Version,
+    train: Option<ReleaseTrain>,
+) -> Option<String> {
+    let current = settings.default_runtime.as_ref()?;
+    if train.is_none() && latest < current {
+        Some(format!(
+            "The latest {} runtime {} is older than the default runtime {}",
+            ReleaseTrain::from_version(latest),
+            latest,
+            current
+        ))
+    } else if settings.pinned && current != latest {
+        Some(format!("The default runtime is pinned to {}", current))
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_default_kept_reason() {
+    let version = |v| semver::Version::parse(v).unwrap();
+    let mut settings = Settings::default();
+    settings.default_runtime = Some(version("0.3.1"));
+    assert_eq!(
+        default_kept_reason(&settings, &version("0.3.0"), None).unwrap(),
+        "The latest stable runtime 0.3.0 is older than the default runtime 0.3.1"
+    );
+    // Picking the train explicitly moves to it even when that's a downgrade
+    assert_eq!(
+        default_kept_reason(&settings, &version("0.3.0"), Some(ReleaseTrain::Stable)),
+        None
+    );
+    assert_eq!(
+        default_kept_reason(&settings, &version("0.3.2"), None),
+        None
+    );
+    assert_eq!(
+        default_kept_reason(&settings, &version("0.3.1"), None),
+        None
+    );
+
+    let mut pinned = settings;
+    pinned.pinned = true;
+    assert_eq!(
+        default_kept_reason(&pinned, &version("0.3.2"), None).unwrap(),
+        "The default runtime is pinned to 0.3.1"
+    );
+    assert_eq!(default_kept_reason(&pinned, &version("0.3.1"), None), None);
+    assert_eq!(
+        default_kept_reason(&Settings::default(), &version("0.3.0"), None),
+        None
+    );
+}
+
+/// For `--check`: reports whether an update would change `current` to `latest`, and returns whether it would
+fn check_update(what: &str, current: Option<&semver::Version>, latest: &semver::Version) -> bool {
+    match current {
+        Some(current) if current == latest => {
+            println!("{} is up to date ({})", what, current);
+            false
+        }
+        Some(current) => {
+            println!("{}: would update {} → {}", what, current, latest);
+            true
+        }
+        None => {
+            println!("{}: would update to {}", what, latest);
+            true
+        }
+    }
+}
+
+/// The cached version list came from the previous source, so it's fetched again from the new one
+fn remove_version_cache() -> anyhow::Result<()> {
+    let path = version_cache_path()?;
+    match std::fs::remove_file(&path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(anyhow::Error::new(err).context(format!("Failed to remove {:?}", path)))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn megabytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024. * 1024.))
+}
+
+/// Runtime subcommands that take the package path as their first argument, like `ambient run ./my-project`
+const SUBCOMMANDS_WITH_PATH_ARG: &[&str] = &["run", "build", "serve", "deploy"];
+
+/// The argument after a subcommand that takes a package path; it's only a path if it has an ambient.toml
+fn package_path_arg(args: &[String]) -> Option<&str> {
+    match &args[cli_flags_end(args)..] {
+        [command, path, ..] if SUBCOMMANDS_WITH_PATH_ARG.contains(&command.as_str()) => {
+            Some(path.as_str())
+        }
+        _ => None,
+    }
+}
+
+/// Removes a leading `+name` from `args`, rustup style, and returns the name
+fn take_runtime_override(args: &mut Vec<String>) -> Option<String> {
+    let name = args.first()?.strip_prefix('+')?.to_string();
+    args.remove(0);
+    Some(name)
+}
+
+/// Where the CLI's own flags end in `args`. For a command passed on to the runtime, that's at the runtime's
+/// subcommand, like `run` in `ambient --exec run game`, so that the runtime's arguments are left alone. The
+/// version manager's commands are parsed by clap, so their flags may come anywhere. Either way, they end at `--`
+fn cli_flags_end(args: &[String]) -> usize {
+    if is_version_manager_command(args) {
+        args.iter()
+            .position(|arg| arg == "--")
+            .unwrap_or(args.len())
+    } else {
+        command_index(args)
+    }
+}
+
+/// The index of the command in `args`, after the leading flags like `--quiet` in `ambient --quiet runtime list`
+fn command_index(args: &[String]) -> usize {
+    let separator = args
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len());
+    let mut index = 0;
+    while let Some(arg) = args[..separator].get(index) {
+        match arg.as_str() {
+            "--env" | "--profile" => index += 2,
+            _ if arg.starts_with('-') || arg.starts_with('+') => index += 1,
+            _ => break,
+        }
+    }
+    index.min(separator)
+}
+
+/// Whether `args` are for the version manager, like `ambient --offline runtime list`, rather than for a runtime
+fn is_version_manager_command(args: &[String]) -> bool {
+    matches!(
+        args.get(command_index(args)).map(|arg| arg.as_str()),
+        Some("runtime" | "new" | "generate-manpages")
+    )
+}
+
+/// Removes the CLI's `--env KEY=VALUE` and `--env=KEY=VALUE` flags from `args`, returning the variables in order
+fn take_env_flags(args: &mut Vec<String>) -> anyhow::Result<Vec<(String, String)>> {
+    let mut env = Vec::new();
+    let mut index = 0;
+    while index < cli_flags_end(args) {
+        let assignment = match args[index].strip_prefix("--env") {
+            Some("") => {
+                anyhow::ensure!(
+                    index + 1 < cli_flags_end(args),
+                    "--env needs a KEY=VALUE argument"
+                );
+                args.remove(index);
+                args.remove(index)
+            }
+            Some(value) if value.starts_with('=') => {
+                let assignment = value[1..].to_string();
+                args.remove(index);
+                assignment
+            }
+            _ => {
+                index += 1;
+                continue;
+            }
+        };
+        let (key, value) = assignment
+            .split_once('=')
+            .with_context(|| format!("Invalid --env {:?}, expected KEY=VALUE", assignment))?;
+        env.push((key.to_string(), value.to_string()));
+    }
+    Ok(env)
+}
+
+/// Removes the CLI's `--profile NAME` or `--profile=NAME` flag from `args`, returning the name
+fn take_profile_flag(args: &mut Vec<String>) -> anyhow::Result<Option<String>> {
+    let end = cli_flags_end(args);
+    let Some(index) = args[..end]
+        .iter()
+        .position(|arg| arg == "--profile" || arg.starts_with("--profile="))
+    else {
+        return Ok(None);
+    };
+    let arg = args.remove(index);
+    match arg.strip_prefix("--profile=") {
+        Some(name) => Ok(Some(name.to_string())),
+        None if index + 1 < end => Ok(Some(args.remove(index))),
+        None => anyhow::bail!("--profile needs a profile name"),
+    }
+}
+
+/// Removes the CLI's `flag` from `args`; returns whether it was there
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args[..cli_flags_end(args)]
+        .iter()
+        .position(|arg| arg == flag)
+    {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+/// How `runtime_exec` ends: running the runtime, or printing how it would be run
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ExecMode {
+    Run,
+    /// `--print-command`
+    Print,
+    /// `--print-command --dry-run`, which doesn't install the runtime either
+    DryRun,
+}
+
+/// `--yes`, `--exec`, `--env`, `--ignore-lock`, `--no-hooks`, `--print-command` and, with a `+name` override,
+/// `--force` are handled here rather than passed on to the runtime, when they come before the runtime's subcommand
+/// like in `ambient --exec run`. So is `--dry-run`, but only along with `--print-command`
 fn runtime_exec(
     mut settings: Settings,
     package_path: &Option<PackagePath>,
-    args: Vec<String>,
+    mut args: Vec<String>,
+    runtime_override: Option<String>,
 ) -> anyhow::Result<()> {
+    let mode = if take_flag(&mut args, "--print-command") {
+        // The runtime may have a --dry-run of its own
+        if take_flag(&mut args, "--dry-run") {
+            ExecMode::DryRun
+        } else {
+            ExecMode::Print
+        }
+    } else {
+        ExecMode::Run
+    };
+    settings.assume_yes |= take_flag(&mut args, "--yes");
+    settings.exec_runtime |= take_flag(&mut args, "--exec");
+    settings.ignore_lock |= take_flag(&mut args, "--ignore-lock");
+    settings.no_hooks |= take_flag(&mut args, "--no-hooks");
+    // Variables passed with --env override those in the settings
+    settings.env.extend(take_env_flags(&mut args)?);
+    let package_path = package_path.as_ref();
+    if let Some(name) = runtime_override {
+        let force = take_flag(&mut args, "--force");
+        // Links named by a version are resolved like installed versions below
+        if semver::Version::parse(&name).is_err() {
+            if let Some(exe_path) = settings.linked_runtime(&name)? {
+                let runtime = RuntimeCommand::new(
+                    &settings,
+                    exe_path.to_path_buf(),
+                    args,
+                    None,
+                    package_path,
+                );
+                return run_or_print(settings, &runtime, mode);
+            }
+        }
+        let version = resolve_runtime_override(&settings, &name)?;
+        let lock = match package_path.filter(|_| !settings.ignore_lock) {
+            Some(package_path) => RuntimeLock::load(&package_path.0)?,
+            None => None,
+        };
+        if let Some(lock) = lock.filter(|lock| lock.version != version.version) {
+            if !force {
+                anyhow::bail!(
+                    "+{} selects runtime {}, but {} locks this package to {}. Run `ambient +{} --force ...` to use {} \
+                     anyway",
+                    name,
+                    version,
+                    LOCKFILE_NAME,
+                    lock.version,
+                    name,
+                    version
+                );
+            }
+        }
+        if !version.is_installed(&settings)? {
+            confirm_install(
+                &settings,
+                &format!("+{} selects runtime {}", name, version),
+                &version,
+                mode,
+            )?;
+        }
+        return run_runtime(settings, version, args, package_path, mode);
+    }
     if settings.default_runtime.is_none() {
-        println!("No default runtime version set, installing latest stable version");
-        let version = get_latest_remote_version_for_train(ReleaseTrain::Stable, true)?;
-        set_default_runtime(&mut settings, &version)?;
-    }
-    let version = get_current_runtime(&settings, package_path)?;
-    version.install()?;
-    let mut process = std::process::Command::new(version.exe_path()?)
-        .args(args)
-        .spawn()?;
-    process.wait()?;
+        let version = get_latest_remote_version_for_train(
+            &BucketSource::new(&settings),
+            ReleaseTrain::Stable,
+            true,
+            None,
+            &settings.ignored_versions,
+        )?;
+        if mode == ExecMode::DryRun {
+            println!(
+                "No default runtime version set; would install the latest stable version {} and make it the default",
+                version
+            );
+            settings.default_runtime = Some(version.version);
+        } else {
+            println!("No default runtime version set, installing latest stable version");
+            set_default_runtime(&mut settings, &version, "run", false, false)?;
+        }
+    }
+    let package = package_path
+        .and_then(|p| p.ambient_toml().get_content().ok().flatten())
+        .map(|toml| toml.package);
+    let version_req = package.as_ref().and_then(|p| p.ambient_version.as_ref());
+    let mut version = resolve_runtime(package_path.map(|p| p.0.as_path()), &settings)?;
+    if !version.is_installed(&settings)? {
+        // Installs the locked build, verified against the locked checksums
+        let lock = match package_path.filter(|_| !settings.ignore_lock) {
+            Some(package_path) => RuntimeLock::load(&package_path.0)?,
+            None => None,
+        };
+        if let Some(lock) = lock {
+            version = lock.runtime(&BucketSource::new(&settings))?;
+        }
+        let name = package.as_ref().and_then(|p| p.name.as_ref());
+        let reason = match (version_req, name) {
+            (Some(version_req), Some(name)) => {
+                format!("Project `{}` requires {}", name, version_req)
+            }
+            (Some(version_req), None) => format!("ambient.toml requires {}", version_req),
+            (None, _) => "The default runtime is not installed".to_string(),
+        };
+        confirm_install(&settings, &reason, &version, mode)?;
+    }
+    run_runtime(settings, version, args, package_path, mode)
+}
+
+/// Asks before installing the missing runtime `version`, which `reason` explains. A dry run only tells that it
+/// would be installed
+fn confirm_install(
+    settings: &Settings,
+    reason: &str,
+    version: &RuntimeVersion,
+    mode: ExecMode,
+) -> anyhow::Result<()> {
+    if mode == ExecMode::DryRun {
+        println!("{}; would install {}", reason, version);
+        return Ok(());
+    }
+    println!("{}; installing {}", reason, version);
+    if !confirm(settings, "Download and install it?", true)? {
+        anyhow::bail!("Runtime {} is required but was not installed", version);
+    }
     Ok(())
 }
 
-fn main() -> anyhow::Result<()> {
-    env_logger::init();
+/// Installs `version` if needed, unless this is a dry run, and runs it with `args`
+fn run_runtime(
+    settings: Settings,
+    version: RuntimeVersion,
+    args: Vec<String>,
+    package_path: Option<&PackagePath>,
+    mode: ExecMode,
+) -> anyhow::Result<()> {
+    if mode != ExecMode::DryRun {
+        version.install_with(&settings, &InstallOptions::default(), &print_progress)?;
+    }
+    let exe_path = version.exe_path(&settings)?;
+    let runtime = RuntimeCommand::new(
+        &settings,
+        exe_path,
+        args,
+        Some(&version.version),
+        package_path,
+    );
+    run_or_print(settings, &runtime, mode)
+}
 
-    let settings = if settings_path()?.exists() {
-        Settings::load()?
+fn run_or_print(
+    settings: Settings,
+    runtime: &RuntimeCommand,
+    mode: ExecMode,
+) -> anyhow::Result<()> {
+    match mode {
+        ExecMode::Run => run_exe(settings, runtime),
+        ExecMode::Print | ExecMode::DryRun => print_runtime_command(&settings, runtime),
+    }
+}
+
+/// Prints how `runtime` would be run, for `--print-command`: the environment variables are the ones set on top of
+/// the inherited environment
+fn print_runtime_command(settings: &Settings, runtime: &RuntimeCommand) -> anyhow::Result<()> {
+    let launch = if settings.capture_logs {
+        "as a child process, with its output captured in a log"
+    } else if settings.exec_runtime {
+        "in place of the CLI process"
     } else {
-        Settings::default()
+        "as a child process"
     };
+    println!("Executable:  {}", runtime.exe_path.display());
+    println!("Arguments:   {:?}", runtime.args);
+    println!("Directory:   {}", std::env::current_dir()?.display());
+    println!("Launched:    {}", launch);
+    println!("Environment:");
+    for (key, value) in &runtime.env {
+        println!("  {}={}", key, value);
+    }
+    Ok(())
+}
 
-    let args: Vec<String> = std::env::args().skip(1).collect();
-    let package_path = PackagePath::get(&args);
-    if args.get(0) == Some(&"runtime".to_string()) {
+/// A runtime binary to run, with its arguments and the environment variables to set for it
+struct RuntimeCommand {
+    exe_path: PathBuf,
+    args: Vec<String>,
+    /// `None` for runtimes linked by name
+    version: Option<semver::Version>,
+    /// Applied in order on top of the inherited environment, so later ones win
+    env: Vec<(String, String)>,
+}
+impl RuntimeCommand {
+    /// Tells the runtime how it was launched with `AMBIENT_*` variables, which the `env` settings can override
+    fn new(
+        settings: &Settings,
+        exe_path: PathBuf,
+        args: Vec<String>,
+        version: Option<&semver::Version>,
+        package_path: Option<&PackagePath>,
+    ) -> Self {
+        let mut env = vec![
+            (
+                "AMBIENT_CLI_VERSION".to_string(),
+                env!("CARGO_PKG_VERSION").to_string(),
+            ),
+            (
+                "AMBIENT_RUNTIME_PATH".to_string(),
+                exe_path.display().to_string(),
+            ),
+        ];
+        if let Some(version) = version {
+            env.push(("AMBIENT_RUNTIME_VERSION".to_string(), version.to_string()));
+        }
+        if let Some(package_path) = package_path {
+            env.push((
+                "AMBIENT_PACKAGE_PATH".to_string(),
+                package_path.0.display().to_string(),
+            ));
+        }
+        env.extend(settings.env.clone());
+        Self {
+            exe_path,
+            args,
+            version: version.cloned(),
+            env,
+        }
+    }
+    fn command(&self) -> std::process::Command {
+        let mut command = std::process::Command::new(&self.exe_path);
+        command.args(&self.args);
+        self.apply_env(&mut command);
+        command
+    }
+    /// Sets the variables on `command`, over the ones it inherits or already has
+    fn apply_env(&self, command: &mut std::process::Command) {
+        command.envs(self.env.iter().cloned());
+    }
+    fn run_error(&self, err: std::io::Error) -> anyhow::Error {
+        let err = anyhow::Error::new(err).context(format!("Failed to run {:?}", self.exe_path));
+        if cfg!(target_os = "macos") {
+            err.context(gatekeeper_help(&self.exe_path))
+        } else if Os::is_wsl() {
+            err.context(Os::wsl_help(self.version.as_ref()))
+        } else {
+            err
+        }
+    }
+}
+
+/// Runs the runtime selected by `name` once with `args`, installing it if needed, and returns its exit code. Unlike
+/// `ambient +<name>`, this ignores the package's lock and leaves the settings alone
+fn exec_runtime(
+    settings: &Settings,
+    package_path: Option<&PackagePath>,
+    name: &str,
+    args: Vec<String>,
+) -> anyhow::Result<i32> {
+    let linked = match semver::Version::parse(name) {
+        Ok(_) => None,
+        Err(_) => settings.linked_runtime(name)?,
+    };
+    let (exe_path, version) = match linked {
+        Some(exe_path) => (exe_path.to_path_buf(), None),
+        None => {
+            let version = resolve_runtime_override(settings, name)?;
+            let requirement = package_path
+                .and_then(|p| p.ambient_toml().get_content().ok().flatten())
+                .and_then(|toml| toml.package.ambient_version);
+            if let Some(requirement) =
+                requirement.filter(|req| !matches_exact(req, &version.version))
+            {
+                println!(
+                    "Warning: ambient.toml requires {}, but running {} as requested",
+                    requirement, version
+                );
+            }
+            if !version.is_installed(settings)? {
+                if settings.offline {
+                    anyhow::bail!(
+                        "Runtime {} is not installed, and can't be downloaded while offline",
+                        version
+                    );
+                }
+                version.install_with(settings, &InstallOptions::default(), &print_progress)?;
+            }
+            (version.exe_path(settings)?, Some(version.version))
+        }
+    };
+    let runtime = RuntimeCommand::new(settings, exe_path, args, version.as_ref(), package_path);
+    let status = spawn_exe(&runtime, None)?;
+    print_crash_triage(settings, &runtime, &status, None);
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Runs the runtime and waits for it, explaining how to get past Gatekeeper when macOS blocks it. With a `log`, its
+/// output also goes there as it's streamed to the terminal
+fn spawn_exe(
+    runtime: &RuntimeCommand,
+    log: Option<std::fs::File>,
+) -> anyhow::Result<std::process::ExitStatus> {
+    let mut command = runtime.command();
+    if log.is_some() {
+        command
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+        // The runtime leaves out colors when its output isn't a terminal
+        if std::io::stdout().is_terminal() {
+            command.env("CLICOLOR_FORCE", "1");
+        }
+    }
+    let mut process = command.spawn().map_err(|err| runtime.run_error(err))?;
+    let copies = match log {
+        Some(log) => {
+            let log = Arc::new(Mutex::new(log));
+            let stdout = process.stdout.take().expect("stdout is piped");
+            let stderr = process.stderr.take().expect("stderr is piped");
+            vec![
+                tee(stdout, std::io::stdout(), log.clone()),
+                tee(stderr, std::io::stderr(), log),
+            ]
+        }
+        None => Vec::new(),
+    };
+    let status = process.wait()?;
+    for copy in copies {
+        match copy.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => log::warn!("Failed to capture the runtime output: {}", err),
+            Err(_) => log::warn!("Failed to capture the runtime output"),
+        }
+    }
+    if !status.success() && is_quarantined(&runtime.exe_path).unwrap_or(false) {
+        eprintln!("{}", gatekeeper_help(&runtime.exe_path));
+    }
+    Ok(status)
+}
+
+/// Prints what's useful for reporting the crash when the runtime was killed by a signal or an exception, and
+/// returns whether it was
+fn print_crash_triage(
+    settings: &Settings,
+    runtime: &RuntimeCommand,
+    status: &std::process::ExitStatus,
+    log_path: Option<&std::path::Path>,
+) -> bool {
+    let Some(crash) = Crash::from_status(status) else {
+        return false;
+    };
+    eprintln!();
+    eprintln!("{} The runtime crashed: {}", "Error:".red(), crash);
+    let version = match &runtime.version {
+        Some(version) => version.to_string(),
+        None => "linked".to_string(),
+    };
+    eprintln!("  Runtime: {}", version);
+    eprintln!("  Path:    {}", runtime.exe_path.display());
+    eprintln!(
+        "  System:  {} {}{}",
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        if Os::is_wsl() { " (WSL)" } else { "" }
+    );
+    if let Some(log_path) = log_path {
+        eprintln!("  Log:     {}", log_path.display());
+    }
+    if Os::is_wsl() {
+        eprintln!("{}", Os::wsl_help(runtime.version.as_ref()));
+    }
+    let installed = runtime
+        .version
+        .as_ref()
+        .filter(|version| !settings.linked_runtimes.contains_key(&version.to_string()));
+    if let Some(version) = installed {
+        eprintln!(
+            "Run `ambient runtime install {} --check` to check that it's installed correctly, or try another version with \
+             `ambient +<version> ...`",
+            version
+        );
+    }
+    true
+}
+
+/// Copies everything from `from` to both `to` and `log` as it arrives, until `from` is closed
+fn tee(
+    mut from: impl Read + Send + 'static,
+    mut to: impl Write + Send + 'static,
+    log: Arc<Mutex<std::fs::File>>,
+) -> std::thread::JoinHandle<std::io::Result<()>> {
+    std::thread::spawn(move || {
+        let mut buf = [0; 8192];
+        loop {
+            let read = from.read(&mut buf)?;
+            if read == 0 {
+                return Ok(());
+            }
+            to.write_all(&buf[..read])?;
+            to.flush()?;
+            log.lock()
+                .expect("no tee panics while holding the log")
+                .write_all(&buf[..read])?;
+        }
+    })
+}
+
+/// Replaces this process with the runtime, so that signals and the exit code go straight to it. Only returns if
+/// that fails
+#[cfg(unix)]
+fn replace_process(runtime: &RuntimeCommand) -> anyhow::Result<()> {
+    use std::os::unix::process::CommandExt;
+    Err(runtime.run_error(runtime.command().exec()))
+}
+/// Windows can't replace a process, so this waits for the runtime and exits with its exit code
+#[cfg(not(unix))]
+fn replace_process(runtime: &RuntimeCommand) -> anyhow::Result<()> {
+    let status = spawn_exe(runtime, None)?;
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+/// How long to wait for the update check once the runtime is done; it's usually done by then
+const UPDATE_CHECK_WAIT: Duration = Duration::from_secs(2);
+
+/// Runs the runtime, checking for a newer stable runtime in the background, and exits with the runtime's exit code
+/// when it fails. With `exec_runtime` the runtime replaces this process instead, which skips the update check
+fn run_exe(mut settings: Settings, runtime: &RuntimeCommand) -> anyhow::Result<()> {
+    if settings.exec_runtime && !settings.capture_logs {
+        return replace_process(runtime);
+    }
+    let log = if settings.capture_logs {
+        let (path, mut file) = RuntimeLogs::new(&settings)?.create(chrono::Local::now())?;
+        writeln!(
+            file,
+            "$ {} {}",
+            runtime.exe_path.display(),
+            runtime.args.join(" ")
+        )?;
+        Some((path, file))
+    } else {
+        None
+    };
+    // A pinned default won't be updated anyway
+    let update_check_due = !settings.pinned && settings.update_check_due(chrono::Utc::now());
+    let update_check = update_check_due.then(|| {
+        let settings = settings.clone();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        // Not joined, so that a slow check can't hold up exiting once the runtime is done
+        std::thread::spawn(move || {
+            let _ = sender.send(get_latest_remote_version_for_train(
+                &BucketSource::new(&settings),
+                ReleaseTrain::Stable,
+                false,
+                None,
+                &settings.ignored_versions,
+            ));
+        });
+        receiver
+    });
+    let (log_path, log) = log.unzip();
+    let status = spawn_exe(runtime, log)?;
+    if !print_crash_triage(&settings, runtime, &status, log_path.as_deref()) {
+        if let Some(log_path) = log_path.filter(|_| !status.success()) {
+            eprintln!("The runtime output was saved to {}", log_path.display());
+        }
+    }
+    if let Some(update_check) = update_check {
+        match update_check.recv_timeout(UPDATE_CHECK_WAIT) {
+            Ok(Ok(latest)) => {
+                if settings
+                    .default_runtime
+                    .as_ref()
+                    .is_some_and(|default| latest.version > *default)
+                {
+                    println!(
+                        "A new runtime version {} is available. Run `ambient runtime update-default` to install it.",
+                        latest
+                    );
+                }
+                let now = chrono::Utc::now();
+                if let Err(err) = settings.modify(|settings| settings.last_update_check = Some(now))
+                {
+                    log::warn!("Failed to save the time of the update check: {:#}", err);
+                }
+            }
+            Ok(Err(err)) => log::warn!("Failed to check for a new runtime version: {:#}", err),
+            Err(RecvTimeoutError::Timeout) => {
+                log::info!("Gave up waiting for the check for a new runtime version")
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                log::warn!("Failed to check for a new runtime version")
+            }
+        }
+    }
+    if !status.success() {
+        std::process::exit(status.code().unwrap_or(1));
+    }
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let mut settings = Settings::load_or_default()?;
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    // The profile is applied first, so that everything else sees its settings
+    let profile = take_profile_flag(&mut args)?;
+    let command = args[command_index(&args)..].to_vec();
+    if let Err(err) = settings.select_profile(profile) {
+        // `runtime profile` is how a missing profile is created or switched away from
+        if !command.starts_with(&["runtime".into(), "profile".into()]) {
+            return Err(err);
+        }
+        eprintln!("{} {:#}", "Error:".red().bold(), err);
+    }
+    settings.apply_env();
+    VERBOSE.store(settings.verbose, Ordering::Relaxed);
+
+    // `runtime doctor` reports the problems itself, and `runtime mirror` is how an invalid mirror is fixed.
+    // Running a runtime only checks the mirror, since the other checks read the version listing and the stores
+    if !is_version_manager_command(&args) {
+        settings.validate_mirror_url()?;
+    } else if !command.starts_with(&["runtime".into(), "doctor".into()]) {
+        match settings.validate() {
+            Ok(warnings) => {
+                for warning in warnings {
+                    eprintln!("{} {}", "Warning:".yellow().bold(), warning);
+                }
+            }
+            Err(err) if command.starts_with(&["runtime".into(), "mirror".into()]) => {
+                eprintln!("{} {:#}", "Error:".red().bold(), err);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    let runtime_override = take_runtime_override(&mut args);
+    // With the override removed, a package path argument is at the same position as without one
+    let package_path = PackagePath::get(package_path_arg(&args));
+    if is_version_manager_command(&args) {
+        if let Some(name) = runtime_override {
+            anyhow::bail!(
+                "+{} only applies when running a runtime, not to `ambient {}`",
+                name,
+                command[0]
+            );
+        }
         version_manager_main(&package_path, settings)?;
-    } else if args.get(0) == Some(&"--help".to_string()) {
-        runtime_exec(settings, &package_path, args)?;
+    } else if args.first() == Some(&"--help".to_string()) {
+        runtime_exec(settings, &package_path, args, runtime_override)?;
         println!();
         println!(
             "{}",
@@ -323,14 +3146,17 @@ fn main() -> anyhow::Result<()> {
             "runtime".white().bold()
         );
     } else {
-        if args.get(0) == Some(&"--version".to_string()) {
+        if args.first() == Some(&"--version".to_string()) {
             if let Some(package) = &package_path {
-                println!("Using package at {:?}", package.0);
+                match package.package_name() {
+                    Some(name) => println!("Using package `{}` at {:?}", name, package.0),
+                    None => println!("Using package at {:?}", package.0),
+                }
             } else {
                 println!("Using global runtime version");
             }
         }
-        runtime_exec(settings, &package_path, args)?;
+        runtime_exec(settings, &package_path, args, runtime_override)?;
     }
 
     Ok(())
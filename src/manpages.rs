@@ -0,0 +1,114 @@
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+
+/// Writes a man page for `cmd` and for each of its subcommands, recursively, to `dir`: `ambient.1`,
+/// `ambient-runtime.1`, `ambient-runtime-install.1` and so on. Hidden commands and arguments are left out.
+/// `extra_description` is added to the top-level page in its own section. Returns the paths written, which only
+/// depend on `cmd`, so that packages built from the same version get the same pages
+pub fn write_manpages(
+    cmd: clap::Command,
+    extra_description: &str,
+    dir: &Path,
+) -> anyhow::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    let mut cmd = cmd.disable_help_subcommand(true);
+    if !extra_description.is_empty() {
+        cmd = cmd.after_long_help(extra_description.to_string());
+    }
+    // Propagates the global arguments, and names the subcommands after their parents, like `ambient-runtime` and
+    // `ambient runtime`
+    let name = cmd.get_name().to_string();
+    cmd = cmd.bin_name(&name);
+    cmd.build();
+    // Like `ambient 0.2.8`, for the pages of the subcommands too
+    let source = format!("{} {}", name, cmd.get_version().unwrap_or_default());
+    let mut paths = Vec::new();
+    write_pages(cmd, &source, dir, &mut paths)?;
+    Ok(paths)
+}
+
+fn write_pages(
+    cmd: clap::Command,
+    source: &str,
+    dir: &Path,
+    paths: &mut Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    let name = cmd
+        .get_display_name()
+        .unwrap_or_else(|| cmd.get_name())
+        .to_string();
+    let subcommands = cmd
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set())
+        .cloned()
+        .collect::<Vec<_>>();
+    // The page shows how the command is invoked, like `ambient runtime install` rather than `install`
+    let invocation = cmd
+        .get_bin_name()
+        .unwrap_or_else(|| cmd.get_name())
+        .to_string();
+    let mut page = Vec::new();
+    clap_mangen::Man::new(cmd.name(invocation))
+        .title(&name)
+        .source(source)
+        .render(&mut page)?;
+    let path = dir.join(format!("{}.1", name));
+    std::fs::write(&path, page).with_context(|| format!("Failed to write {:?}", path))?;
+    paths.push(path);
+    for sub in subcommands {
+        write_pages(sub, source, dir, paths)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_write_manpages() {
+    let cmd = || {
+        clap::Command::new("tool")
+            .version("1.2.3")
+            .about("Does things")
+            .arg(
+                clap::Arg::new("quiet")
+                    .long("quiet")
+                    .short('q')
+                    .global(true)
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Print less"),
+            )
+            .subcommand(
+                clap::Command::new("run")
+                    .about("Runs a thing")
+                    .arg(clap::Arg::new("version").required(true)),
+            )
+            .subcommand(clap::Command::new("secret").hide(true))
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let paths = write_manpages(cmd(), "tool +<version> runs it", dir.path()).unwrap();
+    let names = paths
+        .iter()
+        .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["tool.1", "tool-run.1"]);
+
+    let top = std::fs::read_to_string(&paths[0]).unwrap();
+    assert!(top.contains(".TH tool 1  \"tool 1.2.3\""));
+    assert!(top.contains("tool +<version> runs it"));
+    assert!(top.contains("tool\\-run(1)"));
+    assert!(!top.contains("secret"));
+    let run = std::fs::read_to_string(&paths[1]).unwrap();
+    assert!(run.contains(".TH tool-run 1  \"tool 1.2.3\""));
+    assert!(run.contains("\\fBtool run\\fR"));
+    assert!(!run.contains("runs it"));
+    // The global flag is documented on the subcommand's page too
+    assert!(run.contains("\\-\\-quiet"));
+
+    // The same command always gives the same pages
+    let again = tempfile::tempdir().unwrap();
+    write_manpages(cmd(), "tool +<version> runs it", again.path()).unwrap();
+    for name in names {
+        assert_eq!(
+            std::fs::read(dir.path().join(&name)).unwrap(),
+            std::fs::read(again.path().join(&name)).unwrap()
+        );
+    }
+}
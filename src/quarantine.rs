@@ -0,0 +1,45 @@
+use std::path::Path;
+
+/// Set by macOS on downloaded files; Gatekeeper then refuses to run unsigned binaries carrying it
+pub const QUARANTINE_ATTRIBUTE: &str = "com.apple.quarantine";
+
+/// Removes the quarantine attribute from `path`, if it has one. Does nothing on other platforms
+#[cfg(target_os = "macos")]
+pub fn remove_quarantine(path: &Path) -> anyhow::Result<()> {
+    if is_quarantined(path)? {
+        xattr::remove(path, QUARANTINE_ATTRIBUTE).map_err(|err| {
+            anyhow::anyhow!(
+                "Failed to remove the quarantine attribute from {:?}: {}",
+                path,
+                err
+            )
+        })?;
+    }
+    Ok(())
+}
+#[cfg(not(target_os = "macos"))]
+pub fn remove_quarantine(_path: &Path) -> anyhow::Result<()> {
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+pub fn is_quarantined(path: &Path) -> anyhow::Result<bool> {
+    Ok(xattr::get(path, QUARANTINE_ATTRIBUTE)
+        .map_err(|err| anyhow::anyhow!("Failed to read the attributes of {:?}: {}", path, err))?
+        .is_some())
+}
+#[cfg(not(target_os = "macos"))]
+pub fn is_quarantined(_path: &Path) -> anyhow::Result<bool> {
+    Ok(false)
+}
+
+/// How to let a runtime that Gatekeeper blocks run anyway
+pub fn gatekeeper_help(exe_path: &Path) -> String {
+    format!(
+        "macOS Gatekeeper may be blocking {path}. To allow it, run\n  \
+         xattr -d {attribute} {path}\n\
+         or open System Settings > Privacy & Security and click \"Open Anyway\" next to the message about it",
+        path = exe_path.display(),
+        attribute = QUARANTINE_ATTRIBUTE
+    )
+}
@@ -0,0 +1,126 @@
+use crate::{environment::logs_dir, Settings};
+use anyhow::Context;
+use itertools::Itertools;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_LOGS_MAX_MB: u64 = 100;
+
+/// The output of runtime sessions, captured with the `capture_logs` setting. Every session gets its own file,
+/// named after when it started, and the oldest ones are removed when the logs grow beyond their size cap.
+pub struct RuntimeLogs {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+impl RuntimeLogs {
+    pub fn new(settings: &Settings) -> anyhow::Result<Self> {
+        Ok(Self::in_dir(
+            logs_dir()?,
+            settings.logs_max_mb.unwrap_or(DEFAULT_LOGS_MAX_MB),
+        ))
+    }
+    fn in_dir(dir: PathBuf, max_mb: u64) -> Self {
+        Self {
+            dir,
+            max_bytes: max_mb.saturating_mul(1024 * 1024),
+        }
+    }
+    /// Creates the log file of a session starting at `now`, after making room for it
+    pub fn create(
+        &self,
+        now: chrono::DateTime<chrono::Local>,
+    ) -> anyhow::Result<(PathBuf, std::fs::File)> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {:?}", self.dir))?;
+        self.prune()?;
+        let name = now.format("runtime-%Y-%m-%d_%H-%M-%S%.3f.log").to_string();
+        let path = self.dir.join(name);
+        let file =
+            std::fs::File::create(&path).with_context(|| format!("Failed to create {:?}", path))?;
+        Ok((path, file))
+    }
+    /// Removes the oldest logs until the rest fit the size cap
+    fn prune(&self) -> anyhow::Result<()> {
+        let logs = self.list()?;
+        let mut total = logs.iter().map(|(_, size)| size).sum::<u64>();
+        for (path, size) in logs {
+            if total <= self.max_bytes {
+                break;
+            }
+            std::fs::remove_file(&path).with_context(|| format!("Failed to remove {:?}", path))?;
+            total -= size;
+        }
+        Ok(())
+    }
+    /// The logs with their sizes, oldest first
+    pub fn list(&self) -> anyhow::Result<Vec<(PathBuf, u64)>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut logs = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let is_log = entry.file_name().to_string_lossy().starts_with("runtime-");
+            if metadata.is_file() && is_log {
+                logs.push((entry.path(), metadata.len()));
+            }
+        }
+        // The names start with the time, so they sort chronologically
+        logs.sort();
+        Ok(logs)
+    }
+    /// The log of the latest session, if there is one
+    pub fn latest(&self) -> anyhow::Result<Option<PathBuf>> {
+        Ok(self.list()?.pop().map(|(path, _)| path))
+    }
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+/// The last `count` lines of `content`
+pub fn tail(content: &str, count: usize) -> Vec<&str> {
+    let lines = content.lines().collect_vec();
+    lines[lines.len().saturating_sub(count)..].to_vec()
+}
+
+#[test]
+fn test_runtime_logs() {
+    use chrono::TimeZone;
+    use std::io::Write;
+
+    let dir = tempfile::tempdir().unwrap();
+    let logs = RuntimeLogs::in_dir(dir.path().join("logs"), 1);
+    assert_eq!(logs.latest().unwrap(), None);
+    let at = |minute| {
+        chrono::Local
+            .with_ymd_and_hms(2023, 10, 5, 12, minute, 0)
+            .unwrap()
+    };
+    let (first, mut file) = logs.create(at(0)).unwrap();
+    file.write_all(&vec![b'a'; 700 * 1024]).unwrap();
+    let (second, mut file) = logs.create(at(1)).unwrap();
+    file.write_all(&vec![b'b'; 700 * 1024]).unwrap();
+    assert_eq!(
+        logs.list().unwrap(),
+        [(first.clone(), 700 * 1024), (second.clone(), 700 * 1024)]
+    );
+    assert_eq!(logs.latest().unwrap(), Some(second.clone()));
+
+    // Together the first two are over the 1 MB cap, so the oldest one goes
+    let (third, _) = logs.create(at(2)).unwrap();
+    assert!(!first.exists());
+    assert_eq!(
+        logs.list().unwrap(),
+        [(second, 700 * 1024), (third.clone(), 0)]
+    );
+    assert_eq!(logs.latest().unwrap(), Some(third));
+}
+
+#[test]
+fn test_tail() {
+    let content = "one\ntwo\nthree\n";
+    assert_eq!(tail(content, 2), ["two", "three"]);
+    assert_eq!(tail(content, 10), ["one", "two", "three"]);
+    assert!(tail(content, 0).is_empty());
+}
@@ -0,0 +1,113 @@
+use std::{path::Path, str::FromStr};
+
+/// The formats `ambient runtime env` prints its exports in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Shell {
+    #[default]
+    Bash,
+    Fish,
+    Powershell,
+    /// Shell commands for a GitHub Actions step, which append to `$GITHUB_ENV` and `$GITHUB_PATH` so that the
+    /// following steps have the runtime
+    Github,
+}
+impl std::fmt::Display for Shell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Shell::Bash => write!(f, "bash"),
+            Shell::Fish => write!(f, "fish"),
+            Shell::Powershell => write!(f, "powershell"),
+            Shell::Github => write!(f, "github"),
+        }
+    }
+}
+impl FromStr for Shell {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" | "sh" | "zsh" => Ok(Shell::Bash),
+            "fish" => Ok(Shell::Fish),
+            "powershell" | "pwsh" => Ok(Shell::Powershell),
+            "github" => Ok(Shell::Github),
+            _ => Err(anyhow::anyhow!(
+                "Invalid shell {:?}, expected one of bash, fish, powershell, github",
+                s
+            )),
+        }
+    }
+}
+
+/// The statements that export `AMBIENT_RUNTIME_PATH` and `AMBIENT_RUNTIME_VERSION` for the runtime at `exe_path`,
+/// and put its directory first on the `PATH`
+pub fn env_script(shell: Shell, exe_path: &Path, version: &semver::Version) -> String {
+    let dir = exe_path.parent().unwrap_or(Path::new(""));
+    let exe_path = exe_path.to_string_lossy();
+    let dir = dir.to_string_lossy();
+    let vars = [
+        ("AMBIENT_RUNTIME_PATH", exe_path.to_string()),
+        ("AMBIENT_RUNTIME_VERSION", version.to_string()),
+    ];
+    let mut lines = Vec::new();
+    for (name, value) in vars {
+        lines.push(match shell {
+            Shell::Bash => format!("export {}={}", name, posix_quote(&value)),
+            Shell::Fish => format!("set -gx {} {}", name, fish_quote(&value)),
+            Shell::Powershell => format!("$env:{} = {}", name, powershell_quote(&value)),
+            Shell::Github => format!(
+                "echo {} >> \"$GITHUB_ENV\"",
+                posix_quote(&format!("{}={}", name, value))
+            ),
+        });
+    }
+    lines.push(match shell {
+        Shell::Bash => format!("export PATH={}:\"$PATH\"", posix_quote(&dir)),
+        Shell::Fish => format!("fish_add_path --global --move --path {}", fish_quote(&dir)),
+        Shell::Powershell => format!(
+            "$env:PATH = {} + [IO.Path]::PathSeparator + $env:PATH",
+            powershell_quote(&dir)
+        ),
+        Shell::Github => format!("echo {} >> \"$GITHUB_PATH\"", posix_quote(&dir)),
+    });
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+fn posix_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+fn fish_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+fn powershell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[test]
+fn test_env_script() {
+    let exe_path = Path::new("/home/me/runtimes/0.3.1/ambient");
+    let version = semver::Version::parse("0.3.1").unwrap();
+    assert_eq!(
+        env_script(Shell::Bash, exe_path, &version),
+        "export AMBIENT_RUNTIME_PATH='/home/me/runtimes/0.3.1/ambient'\n\
+         export AMBIENT_RUNTIME_VERSION='0.3.1'\n\
+         export PATH='/home/me/runtimes/0.3.1':\"$PATH\"\n"
+    );
+    assert_eq!(
+        env_script(Shell::Github, exe_path, &version),
+        "echo 'AMBIENT_RUNTIME_PATH=/home/me/runtimes/0.3.1/ambient' >> \"$GITHUB_ENV\"\n\
+         echo 'AMBIENT_RUNTIME_VERSION=0.3.1' >> \"$GITHUB_ENV\"\n\
+         echo '/home/me/runtimes/0.3.1' >> \"$GITHUB_PATH\"\n"
+    );
+    assert_eq!(
+        env_script(Shell::Powershell, Path::new("/it's/ambient"), &version),
+        "$env:AMBIENT_RUNTIME_PATH = '/it''s/ambient'\n\
+         $env:AMBIENT_RUNTIME_VERSION = '0.3.1'\n\
+         $env:PATH = '/it''s' + [IO.Path]::PathSeparator + $env:PATH\n"
+    );
+    assert_eq!(
+        env_script(Shell::Fish, Path::new("/it's/ambient"), &version),
+        "set -gx AMBIENT_RUNTIME_PATH '/it\\'s/ambient'\n\
+         set -gx AMBIENT_RUNTIME_VERSION '0.3.1'\n\
+         fish_add_path --global --move --path '/it\\'s'\n"
+    );
+}
@@ -0,0 +1,246 @@
+use crate::environment::{set_cargo_toml_ambient_api, AmbientApiSpec};
+use anyhow::Context;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use toml_edit::Document;
+
+/// The project layouts `ambient new` can generate
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Template {
+    /// Empty client and server modules
+    #[default]
+    Minimal,
+    /// A camera and a ground plane to build a game on
+    Game,
+    /// Server-side code only
+    Server,
+}
+impl Template {
+    /// The files of a new project, as paths relative to the project dir and their contents
+    pub fn files(
+        &self,
+        id: &str,
+        name: &str,
+        ambient_version: &semver::Version,
+    ) -> anyhow::Result<Vec<(PathBuf, String)>> {
+        let mut files = vec![
+            (
+                PathBuf::from("ambient.toml"),
+                ambient_toml(id, name, ambient_version),
+            ),
+            (
+                PathBuf::from("Cargo.toml"),
+                self.cargo_toml(id, ambient_version)?,
+            ),
+            (PathBuf::from("src/server.rs"), self.server_rs().to_string()),
+        ];
+        if *self != Template::Server {
+            files.push((PathBuf::from("src/client.rs"), CLIENT_RS.to_string()));
+        }
+        Ok(files)
+    }
+    fn cargo_toml(&self, id: &str, ambient_version: &semver::Version) -> anyhow::Result<String> {
+        let mut toml = format!(
+            r#"[package]
+name = "{id}"
+edition = "2021"
+publish = false
+version = "0.0.1"
+
+[dependencies]
+ambient_api = "{ambient_version}"
+"#
+        );
+        if *self != Template::Server {
+            toml += &format!(
+                r#"
+[[bin]]
+name = "{id}_client"
+path = "src/client.rs"
+required-features = ["client"]
+"#
+            );
+        }
+        toml += &format!(
+            r#"
+[[bin]]
+name = "{id}_server"
+path = "src/server.rs"
+required-features = ["server"]
+
+[features]
+client = ["ambient_api/client"]
+server = ["ambient_api/server"]
+"#
+        );
+        let mut doc = toml
+            .parse::<Document>()
+            .context("Invalid Cargo.toml template")?;
+        // Pre-releases aren't on crates.io, so they need a git dependency
+        set_cargo_toml_ambient_api(
+            &mut doc,
+            &AmbientApiSpec::for_version(ambient_version),
+            false,
+        )?;
+        Ok(doc.to_string())
+    }
+    fn server_rs(&self) -> &'static str {
+        match self {
+            Template::Minimal | Template::Server => SERVER_RS,
+            Template::Game => GAME_SERVER_RS,
+        }
+    }
+}
+impl std::fmt::Display for Template {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Template::Minimal => write!(f, "minimal"),
+            Template::Game => write!(f, "game"),
+            Template::Server => write!(f, "server"),
+        }
+    }
+}
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "minimal" => Ok(Template::Minimal),
+            "game" => Ok(Template::Game),
+            "server" => Ok(Template::Server),
+            _ => Err(anyhow::anyhow!(
+                "Invalid template {:?}, expected one of minimal, game, server",
+                s
+            )),
+        }
+    }
+}
+
+/// Package ids are snake_case, e.g. `My Game` becomes `my_game`
+pub fn package_id(name: &str) -> anyhow::Result<String> {
+    let id = name
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    if !id.starts_with(|c: char| c.is_ascii_lowercase()) {
+        anyhow::bail!("Project name {:?} must start with a letter", name);
+    }
+    Ok(id)
+}
+
+/// Writes the files of `template` into `dir`, which must not exist yet or be empty
+pub fn create_project(
+    dir: &Path,
+    name: &str,
+    template: Template,
+    ambient_version: &semver::Version,
+) -> anyhow::Result<()> {
+    if dir
+        .read_dir()
+        .is_ok_and(|mut entries| entries.next().is_some())
+    {
+        anyhow::bail!("{:?} already exists and is not empty", dir);
+    }
+    let id = package_id(name)?;
+    for (path, contents) in template.files(&id, name, ambient_version)? {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {:?}", parent))?;
+        }
+        std::fs::write(&path, contents).with_context(|| format!("Failed to write {:?}", path))?;
+    }
+    Ok(())
+}
+
+fn ambient_toml(id: &str, name: &str, ambient_version: &semver::Version) -> String {
+    format!(
+        r#"[package]
+id = "{id}"
+name = {name:?}
+version = "0.0.1"
+content = {{ type = "Playable" }}
+ambient_version = "{ambient_version}"
+"#
+    )
+}
+
+const CLIENT_RS: &str = r#"use ambient_api::prelude::*;
+
+#[main]
+pub fn main() {}
+"#;
+
+const SERVER_RS: &str = r#"use ambient_api::prelude::*;
+
+#[main]
+pub fn main() {}
+"#;
+
+const GAME_SERVER_RS: &str = r#"use ambient_api::{
+    core::{
+        camera::concepts::{
+            PerspectiveInfiniteReverseCamera, PerspectiveInfiniteReverseCameraOptional,
+        },
+        primitives::components::quad,
+        transform::components::lookat_target,
+    },
+    prelude::*,
+};
+
+#[main]
+pub fn main() {
+    PerspectiveInfiniteReverseCamera {
+        optional: PerspectiveInfiniteReverseCameraOptional {
+            translation: Some(Vec3::ONE * 5.),
+            main_scene: Some(()),
+            ..default()
+        },
+        ..PerspectiveInfiniteReverseCamera::suggested()
+    }
+    .make()
+    .with(lookat_target(), vec3(0., 0., 0.))
+    .spawn();
+
+    Entity::new().with(quad(), ()).spawn();
+}
+"#;
+
+#[test]
+fn test_package_id() {
+    assert_eq!(package_id("My Game").unwrap(), "my_game");
+    assert_eq!(package_id("space-race2").unwrap(), "space_race2");
+    assert!(package_id("2fast").is_err());
+}
+
+#[test]
+fn test_template_files() {
+    let version = semver::Version::parse("0.3.0").unwrap();
+    let files = Template::Server
+        .files("my_game", "My Game", &version)
+        .unwrap();
+    let paths = files
+        .iter()
+        .map(|(path, _)| path.to_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, ["ambient.toml", "Cargo.toml", "src/server.rs"]);
+    assert!(files[0].1.contains(r#"ambient_version = "0.3.0""#));
+    assert!(files[1].1.contains(r#"ambient_api = "0.3.0""#));
+    assert!(!files[1].1.contains("my_game_client"));
+
+    let nightly = semver::Version::parse("0.3.0-nightly-2023-10-02").unwrap();
+    let files = Template::Game
+        .files("my_game", "My Game", &nightly)
+        .unwrap();
+    assert_eq!(files.len(), 4);
+    assert!(files[1].1.contains(r#"tag = "v0.3.0-nightly-2023-10-02""#));
+}
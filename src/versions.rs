@@ -1,22 +1,48 @@
-use crate::{environment::runtimes_dir, Os, ReleaseTrain};
+use crate::{
+    archive::{extract_zip, set_executable, verify_extracted},
+    deprecations::{Deprecations, DEPRECATIONS_PATH},
+    download_cache::{matches_md5, DownloadCache},
+    environment::{
+        is_dir_writable, runtimes_dir, version_cache_path, write_file_atomically, Os, RuntimeStore,
+    },
+    glibc::ensure_glibc_compatible,
+    hooks::{expand_hook, run_hook, Hook},
+    http,
+    quarantine::remove_quarantine,
+    ReleaseTrain, Settings,
+};
 use anyhow::Context;
 use itertools::Itertools;
-use serde::Deserialize;
-use std::{path::PathBuf, str::FromStr};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 #[derive(Debug, Deserialize)]
 struct BucketList {
     #[serde(default)]
     items: Vec<BucketItem>,
+    /// Set when there are more items than fit in one response
+    #[serde(rename = "nextPageToken")]
+    next_page_token: Option<String>,
 }
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct BucketItem {
     name: String,
     #[serde(rename = "mediaLink")]
     media_link: String,
+    /// Size in bytes; Google Storage encodes it as a string
+    #[serde(default)]
+    size: Option<String>,
+    /// Base64 encoded MD5 checksum of the contents
+    #[serde(rename = "md5Hash", default)]
+    md5_hash: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RuntimeVersion {
     pub version: semver::Version,
     pub builds: Vec<Build>,
@@ -34,54 +60,485 @@ impl RuntimeVersion {
     pub fn is_point_release(&self) -> bool {
         ReleaseTrain::from_version(&self.version) == ReleaseTrain::Stable
     }
+    pub fn is_beta(&self) -> bool {
+        ReleaseTrain::from_version(&self.version) == ReleaseTrain::Beta
+    }
     pub fn is_public(&self) -> bool {
-        self.is_point_release() || self.is_nightly()
+        self.is_point_release() || self.is_beta() || self.is_nightly()
+    }
+    /// The store and executable path of this version, if it's installed in any store
+    pub fn installed(
+        &self,
+        settings: &Settings,
+    ) -> anyhow::Result<Option<(RuntimeStore, PathBuf)>> {
+        if let Some(path) = settings.linked_runtime(&self.version.to_string())? {
+            return Ok(Some((RuntimeStore::Linked, path.to_path_buf())));
+        }
+        for (store, dir) in settings.runtime_stores()? {
+            let exe_path = dir
+                .join(self.version.to_string())
+                .join(Os::current().ambient_bin_name());
+            if exe_path.exists() {
+                return Ok(Some((store, exe_path)));
+            }
+        }
+        Ok(None)
     }
-    pub fn dir_path(&self) -> anyhow::Result<PathBuf> {
-        Ok(runtimes_dir()?.join(self.version.to_string()))
+    pub fn exe_path(&self, settings: &Settings) -> anyhow::Result<PathBuf> {
+        match self.installed(settings)? {
+            Some((_, exe_path)) => Ok(exe_path),
+            None => Ok(self
+                .install_dir(settings, &|_| {})?
+                .join(Os::current().ambient_bin_name())),
+        }
+    }
+    pub fn is_installed(&self, settings: &Settings) -> anyhow::Result<bool> {
+        Ok(self.installed(settings)?.is_some())
     }
-    pub fn exe_path(&self) -> anyhow::Result<PathBuf> {
-        Ok(self.dir_path()?.join(Os::current().ambient_bin_name()))
+    /// New installs go to the shared store when it's configured and writable, otherwise to the per-user store
+    fn install_dir(
+        &self,
+        settings: &Settings,
+        on_progress: &dyn Fn(&InstallProgress),
+    ) -> anyhow::Result<PathBuf> {
+        let mut stores = settings.runtime_stores()?;
+        let (_, user_dir) = stores.pop().context("No runtime stores")?;
+        if let Some((_, shared_dir)) = stores.pop() {
+            if is_dir_writable(&shared_dir) {
+                return Ok(shared_dir.join(self.version.to_string()));
+            }
+            on_progress(&InstallProgress::SharedDirNotWritable {
+                shared_dir,
+                user_dir: user_dir.clone(),
+            });
+        }
+        Ok(user_dir.join(self.version.to_string()))
     }
-    pub fn is_installed(&self) -> anyhow::Result<bool> {
-        Ok(self.exe_path()?.exists())
+    /// Versions resolved from installed or configured versions have no builds, so look them up in the bucket
+    fn fetch_builds(&self, settings: &Settings) -> anyhow::Result<Vec<Build>> {
+        if !self.builds.is_empty() {
+            return Ok(self.builds.clone());
+        }
+        log::info!("Fetching builds for {}", self.version);
+        BucketSource::new(settings).builds_for(&self.version)
     }
-    fn download(&self) -> anyhow::Result<Vec<u8>> {
-        let os = Os::current();
+    fn download(
+        &self,
+        settings: &Settings,
+        os: Os,
+        on_progress: &dyn Fn(&InstallProgress),
+    ) -> anyhow::Result<Vec<u8>> {
+        let builds = self.fetch_builds(settings)?;
+        if builds.is_empty() {
+            anyhow::bail!(
+                "Runtime version {} was not found on the server",
+                self.version
+            );
+        }
+        let Some(build) = builds.iter().find(|b| b.os == os) else {
+            let mut message = format!(
+                "Runtime version {} has no build for {}. Builds exist for: {}.",
+                self.version,
+                os,
+                builds.iter().map(|b| b.os.to_string()).join(", ")
+            );
+            if let Some(nearest) = self.nearest_version_with_build_for(settings, os)? {
+                message += &format!(
+                    " The nearest version with a {} build is {}; install it with `ambient runtime install {}`",
+                    os, nearest, nearest
+                );
+            }
+            anyhow::bail!(message);
+        };
+
+        let cache = DownloadCache::new(settings)?;
+        if let Some(md5) = &build.md5 {
+            if let Some(data) = cache.get(&self.version, os, md5) {
+                log::info!("Using the cached download of {} for {}", self.version, os);
+                return Ok(data);
+            }
+        }
 
-        let reponse = ureq::get(
-            &self
-                .builds
-                .iter()
-                .find(|b| b.os == os)
-                .context("No build for this OS")?
-                .url,
-        )
-        .call()?;
+        let operation = format!("Downloading runtime {}", self.version);
+        http::ensure_online(settings, &operation)?;
+        log::debug!("Downloading from: {}", build.url);
+        on_progress(&InstallProgress::Downloading {
+            url: build.url.clone(),
+        });
+        let reponse = http::agent(settings)?
+            .get(&build.url)
+            .call()
+            .map_err(|err| http::network_error(settings, &operation, err))?;
 
         let mut bytes: Vec<u8> = Vec::new();
-        reponse.into_reader().read_to_end(&mut bytes)?;
+        reponse
+            .into_reader()
+            .read_to_end(&mut bytes)
+            .map_err(|err| http::network_error(settings, &operation, err))?;
+        log::debug!("Downloaded {} bytes", bytes.len());
+        on_progress(&InstallProgress::Downloaded { bytes: bytes.len() });
+        if let Some(md5) = &build.md5 {
+            if !matches_md5(&bytes, md5) {
+                anyhow::bail!(
+                    "The download of runtime {} is corrupted: its checksum doesn't match",
+                    self.version
+                );
+            }
+            if let Err(err) = cache.put(&self.version, os, md5, &bytes) {
+                log::warn!(
+                    "Failed to cache the download of {}: {:#}",
+                    self.version,
+                    err
+                );
+            }
+        }
         Ok(bytes)
     }
-    pub fn install(&self) -> anyhow::Result<()> {
-        if self.is_installed()? {
+    /// Size of the download for `os`, if known
+    fn download_size(&self, settings: &Settings, os: Os) -> Option<u64> {
+        self.fetch_builds(settings)
+            .ok()?
+            .into_iter()
+            .find(|b| b.os == os)?
+            .size
+    }
+    /// Fails early if `dir` is on a partition without room for the download and its extracted contents
+    fn check_disk_space(&self, settings: &Settings, os: Os, dir: &Path) -> anyhow::Result<()> {
+        let Some(download_size) = self.download_size(settings, os) else {
+            return Ok(());
+        };
+        // The install dir usually doesn't exist yet, so check the partition of its nearest existing ancestor
+        let Some(partition) = dir.ancestors().find(|dir| dir.exists()) else {
+            return Ok(());
+        };
+        match fs2::available_space(partition) {
+            Ok(available) => ensure_disk_space(download_size, available, partition),
+            Err(err) => {
+                log::info!("Could not check free space on {:?}: {}", partition, err);
+                Ok(())
+            }
+        }
+    }
+    /// Prefers the newest older version in the same release train, then the oldest newer one
+    fn nearest_version_with_build_for(
+        &self,
+        settings: &Settings,
+        os: Os,
+    ) -> anyhow::Result<Option<semver::Version>> {
+        let train = ReleaseTrain::from_version(&self.version);
+        let candidates = get_versions(settings, VersionsFilter::all())?
+            .into_iter()
+            .filter(|v| ReleaseTrain::from_version(&v.version) == train)
+            .filter(|v| v.builds.iter().any(|b| b.os == os))
+            .map(|v| v.version)
+            .collect_vec();
+        Ok(candidates
+            .iter()
+            .filter(|v| **v < self.version)
+            .max()
+            .or_else(|| candidates.iter().filter(|v| **v > self.version).min())
+            .cloned())
+    }
+    /// Downloads, extracts and smoke tests this version, unless it's already installed
+    pub fn install(&self, settings: &Settings) -> anyhow::Result<()> {
+        self.install_with(settings, &InstallOptions::default(), &|_| {})
+    }
+    /// Like [`Self::install`], reporting what it does to `on_progress`
+    pub fn install_with(
+        &self,
+        settings: &Settings,
+        options: &InstallOptions,
+        on_progress: &dyn Fn(&InstallProgress),
+    ) -> anyhow::Result<()> {
+        if self.is_installed(settings)? {
             return Ok(());
         }
-        println!("Installing runtime version: {}", self.version);
-        let data = self.download()?;
-        let mut arch = zip::ZipArchive::new(std::io::Cursor::new(data))?;
-        let path = runtimes_dir()?.join(self.version.to_string());
-        std::fs::create_dir_all(&path)?;
-        arch.extract(&path)?;
+        on_progress(&InstallProgress::Installing {
+            version: self.version.clone(),
+            os: Os::current(),
+        });
+        if Os::is_wsl() {
+            on_progress(&InstallProgress::Wsl);
+        }
+        let path = self.install_dir(settings, on_progress)?;
+        self.check_disk_space(settings, Os::current(), &path)?;
+        let data = self.download(settings, Os::current(), on_progress)?;
+        extract_zip(&data, &path)?;
+        let exe_path = path.join(Os::current().ambient_bin_name());
+        if let Err(err) = set_executable(&exe_path) {
+            std::fs::remove_dir_all(&path)?;
+            return Err(err);
+        }
+        if let Err(err) = remove_quarantine(&exe_path) {
+            log::warn!("{:#}", err);
+        }
+        if let Err(err) = ensure_glibc_compatible(&exe_path) {
+            std::fs::remove_dir_all(&path)?;
+            return Err(err.context(format!(
+                "Runtime {} can't run on this system, so it has been removed again",
+                self.version
+            )));
+        }
 
-        println!("Installed at: {:?}", path);
+        if options.smoke_test {
+            if let Err(mut err) = self.smoke_test(&exe_path) {
+                std::fs::remove_dir_all(&path)?;
+                if Os::is_wsl() {
+                    err = err.context(Os::wsl_help(Some(&self.version)));
+                }
+                return Err(err.context(format!(
+                    "Runtime {} was installed but failed to start, so it has been removed again. \
+                     Pass --no-smoke-test to skip this check",
+                    self.version
+                )));
+            }
+        }
+
+        if let Some(template) = settings.hook(Hook::PostInstall) {
+            let command = expand_hook(template, &self.version, &exe_path);
+            on_progress(&InstallProgress::RunningHook {
+                hook: Hook::PostInstall,
+                command: command.clone(),
+            });
+            if let Err(err) = run_hook(Hook::PostInstall, &command) {
+                if !settings.hooks.post_install_optional {
+                    std::fs::remove_dir_all(&path)?;
+                    return Err(err.context(format!(
+                        "Runtime {} was installed but its post_install hook failed, so it has been removed again",
+                        self.version
+                    )));
+                }
+                on_progress(&InstallProgress::OptionalHookFailed {
+                    hook: Hook::PostInstall,
+                    error: format!("{:#}", err),
+                });
+            }
+        }
+
+        on_progress(&InstallProgress::Installed { path });
+        Ok(())
+    }
+    /// Downloads and extracts the build for this OS into `dir`, e.g. a package-local `.ambient/` directory. This is a
+    /// one-shot export: the runtime isn't installed by it, and `exe_path` keeps pointing into the runtime stores
+    pub fn install_to(
+        &self,
+        settings: &Settings,
+        dir: &Path,
+        on_progress: &dyn Fn(&InstallProgress),
+    ) -> anyhow::Result<PathBuf> {
+        self.install_for_os(settings, Os::current(), Some(dir), on_progress)
+    }
+    /// Downloads and extracts the build for another OS, e.g. for bundling it into an installer.
+    /// These are kept apart from the regular installs so that they're never picked up for local execution.
+    pub fn install_for_os(
+        &self,
+        settings: &Settings,
+        os: Os,
+        dest: Option<&Path>,
+        on_progress: &dyn Fn(&InstallProgress),
+    ) -> anyhow::Result<PathBuf> {
+        let path = match dest {
+            Some(dest) => dest.to_path_buf(),
+            None => runtimes_dir()?.join(format!("{}@{}", self.version, os)),
+        };
+        // Fails before downloading anything
+        if !is_dir_writable(&path) {
+            anyhow::bail!("Can't extract the runtime to {:?}: it isn't writable", path);
+        }
+        on_progress(&InstallProgress::Installing {
+            version: self.version.clone(),
+            os,
+        });
+        self.check_disk_space(settings, os, &path)?;
+        let data = self.download(settings, os, on_progress)?;
+        extract_zip(&data, &path)?;
+        if os != Os::Windows {
+            set_executable(&path.join(os.ambient_bin_name()))?;
+        }
+        on_progress(&InstallProgress::Installed { path: path.clone() });
+        Ok(path)
+    }
+    /// Checks an installed runtime more thoroughly than the smoke test: its files must have the same SHA-256
+    /// checksums as in the (MD5 verified) archive, and the binary must report this version
+    pub fn verify(
+        &self,
+        settings: &Settings,
+        on_progress: &dyn Fn(&InstallProgress),
+    ) -> anyhow::Result<()> {
+        let (_, exe_path) = self
+            .installed(settings)?
+            .with_context(|| format!("Runtime {} is not installed", self.version))?;
+        let dir = exe_path.parent().context("Invalid runtime path")?;
+        let data = self.download(settings, Os::current(), on_progress)?;
+        verify_extracted(&data, dir)?;
+        self.smoke_test(&exe_path)
+    }
+    /// Runs `<exe> --version` and checks that it reports this version.
+    /// Only major.minor.patch is compared, since pre-release builds don't always report their full tag.
+    fn smoke_test(&self, exe_path: &Path) -> anyhow::Result<()> {
+        let output = std::process::Command::new(exe_path)
+            .arg("--version")
+            .output()
+            .with_context(|| {
+                format!(
+                    "Installed binary failed smoke test: failed to run {:?}",
+                    exe_path
+                )
+            })?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() {
+            anyhow::bail!(
+                "Installed binary failed smoke test: `{} --version` exited with {}:\n{}",
+                exe_path.display(),
+                output.status,
+                stderr.trim()
+            );
+        }
+        let expected = format!(
+            "{}.{}.{}",
+            self.version.major, self.version.minor, self.version.patch
+        );
+        if !stdout.contains(&expected) {
+            anyhow::bail!(
+                "Installed binary failed smoke test: `{} --version` reported {:?}, expected version {}",
+                exe_path.display(),
+                stdout.trim(),
+                self.version
+            );
+        }
         Ok(())
     }
 }
+
+/// The zip and its extracted contents both need to fit, so require twice the download size
+fn ensure_disk_space(download_size: u64, available: u64, partition: &Path) -> anyhow::Result<()> {
+    const MB: u64 = 1024 * 1024;
+    let needed = download_size.saturating_mul(2);
+    if available < needed {
+        anyhow::bail!(
+            "Insufficient disk space: need {} MB, have {} MB on {}",
+            needed.div_ceil(MB),
+            available / MB,
+            partition.display()
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_ensure_disk_space() {
+    const MB: u64 = 1024 * 1024;
+    let partition = Path::new("/data");
+    assert!(ensure_disk_space(100 * MB, 200 * MB, partition).is_ok());
+    let err = ensure_disk_space(100 * MB, 150 * MB, partition).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Insufficient disk space: need 200 MB, have 150 MB on /data"
+    );
+}
+
+/// The steps of an install, so that callers can present them however they like
 #[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum InstallProgress {
+    Installing {
+        version: semver::Version,
+        os: Os,
+    },
+    /// The shared runtimes dir is configured but not writable, so the runtime goes into `user_dir`
+    SharedDirNotWritable {
+        shared_dir: PathBuf,
+        user_dir: PathBuf,
+    },
+    Installed {
+        path: PathBuf,
+    },
+    /// Running in WSL, where the Linux build is installed
+    Wsl,
+    /// The download is starting. This and `Downloaded` are meant for `verbose`, e.g. to retry a download with curl
+    Downloading {
+        url: String,
+    },
+    Downloaded {
+        bytes: usize,
+    },
+    RunningHook {
+        hook: Hook,
+        command: String,
+    },
+    /// A hook failed that is configured as optional, so the operation went on
+    OptionalHookFailed {
+        hook: Hook,
+        error: String,
+    },
+}
+impl std::fmt::Display for InstallProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InstallProgress::Installing { version, os } if *os == Os::current() => {
+                write!(f, "Installing runtime version: {}", version)
+            }
+            InstallProgress::Installing { version, os } => {
+                write!(f, "Installing runtime version {} for {}", version, os)
+            }
+            InstallProgress::SharedDirNotWritable {
+                shared_dir,
+                user_dir,
+            } => write!(
+                f,
+                "Note: the shared runtimes dir {:?} is not writable, installing to {:?} instead",
+                shared_dir, user_dir
+            ),
+            InstallProgress::Installed { path } => write!(f, "Installed at: {:?}", path),
+            InstallProgress::Wsl => write!(
+                f,
+                "Note: running in WSL, so the Linux build is installed. The Windows build can be installed with \
+                 --os windows-latest"
+            ),
+            InstallProgress::Downloading { url } => write!(f, "Downloading from: {}", url),
+            InstallProgress::Downloaded { bytes } => write!(f, "Downloaded {} bytes", bytes),
+            InstallProgress::RunningHook { hook, command } => {
+                write!(f, "Running the {} hook: {}", hook, command)
+            }
+            InstallProgress::OptionalHookFailed { hook, error } => write!(
+                f,
+                "Warning: the {} hook failed, continuing since it's optional: {}",
+                hook, error
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct InstallOptions {
+    /// Run the installed binary with `--version` to check that it works
+    pub smoke_test: bool,
+}
+impl Default for InstallOptions {
+    fn default() -> Self {
+        Self { smoke_test: true }
+    }
+}
+/// The version, followed by the release train unless it's stable, e.g. `0.3.0-nightly-2023-09-16 (nightly)`
+impl std::fmt::Display for RuntimeVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match ReleaseTrain::from_version(&self.version) {
+            ReleaseTrain::Stable => write!(f, "{}", self.version),
+            train => write!(f, "{} ({})", self.version, train),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Build {
     pub os: Os,
     pub url: String,
+    /// Size of the zip in bytes, if the server reported it
+    pub size: Option<u64>,
+    /// Base64 encoded MD5 checksum of the zip, if the server reported it
+    pub md5: Option<String>,
 }
 
 fn version_from_path(path: &str) -> anyhow::Result<semver::Version> {
@@ -93,23 +550,400 @@ fn version_from_path(path: &str) -> anyhow::Result<semver::Version> {
 pub struct VersionsFilter {
     pub include_private: bool,
     pub include_nightly: bool,
+    pub include_beta: bool,
+}
+impl VersionsFilter {
+    pub fn all() -> Self {
+        Self {
+            include_private: true,
+            include_nightly: true,
+            include_beta: true,
+        }
+    }
+    pub fn stable_only() -> Self {
+        Self {
+            include_private: false,
+            include_nightly: false,
+            include_beta: false,
+        }
+    }
+    /// The narrowest filter that still includes the versions of `train`
+    pub fn for_train(train: ReleaseTrain) -> Self {
+        match train {
+            ReleaseTrain::Stable => Self::stable_only(),
+            ReleaseTrain::Beta => Self {
+                include_beta: true,
+                ..Self::stable_only()
+            },
+            ReleaseTrain::Nightly => Self {
+                include_nightly: true,
+                ..Self::stable_only()
+            },
+            ReleaseTrain::Internal => Self::all(),
+        }
+    }
+    pub fn allows(&self, version: &RuntimeVersion) -> bool {
+        (self.include_private || version.is_public())
+            && (self.include_beta || !version.is_beta())
+            && (self.include_nightly || !version.is_nightly())
+    }
+}
+
+/// Where the available runtime versions come from
+pub trait VersionSource {
+    /// The versions allowed by `filter`, with their builds
+    fn list(&self, filter: VersionsFilter) -> anyhow::Result<Vec<RuntimeVersion>>;
+    /// The versions whose version string starts with `prefix`
+    fn list_with_prefix(&self, prefix: &str) -> anyhow::Result<Vec<RuntimeVersion>> {
+        let mut versions = self.list(VersionsFilter::all())?;
+        versions.retain(|v| v.version.to_string().starts_with(prefix));
+        Ok(versions)
+    }
+    fn builds_for(&self, version: &semver::Version) -> anyhow::Result<Vec<Build>> {
+        Ok(self
+            .list_with_prefix(&version.to_string())?
+            .into_iter()
+            .find(|v| v.version == *version)
+            .map(|v| v.builds)
+            .unwrap_or_default())
+    }
+    /// The deprecated and yanked versions, as of the same listing as [`VersionSource::list`]
+    fn deprecations(&self) -> anyhow::Result<Deprecations> {
+        Ok(Deprecations::default())
+    }
+}
+
+/// The versions published to the Ambient bucket, read through the version cache
+pub struct BucketSource<'a> {
+    settings: &'a Settings,
+}
+impl<'a> BucketSource<'a> {
+    pub fn new(settings: &'a Settings) -> Self {
+        Self { settings }
+    }
+}
+impl VersionSource for BucketSource<'_> {
+    fn list(&self, filter: VersionsFilter) -> anyhow::Result<Vec<RuntimeVersion>> {
+        get_versions_with_prefix(self.settings, "", filter)
+    }
+    // A prefixed lookup refetches when the cache doesn't have the version yet
+    fn list_with_prefix(&self, prefix: &str) -> anyhow::Result<Vec<RuntimeVersion>> {
+        get_versions_with_prefix(self.settings, prefix, VersionsFilter::all())
+    }
+    // Fetches the listing first, so that the deprecations aren't missing or older than the versions
+    fn deprecations(&self) -> anyhow::Result<Deprecations> {
+        get_bucket_items(self.settings, "")?;
+        Ok(get_deprecations())
+    }
+}
+
+/// A fixed set of versions, for testing resolution without the network
+#[cfg(test)]
+pub struct FakeSource(pub Vec<RuntimeVersion>);
+#[cfg(test)]
+impl FakeSource {
+    pub fn new(versions: &[&str]) -> Self {
+        Self(runtime_versions(versions))
+    }
+}
+#[cfg(test)]
+impl VersionSource for FakeSource {
+    fn list(&self, filter: VersionsFilter) -> anyhow::Result<Vec<RuntimeVersion>> {
+        let mut versions = self.0.clone();
+        versions.retain(|v| filter.allows(v));
+        versions.sort_by(|a, b| a.version.cmp(&b.version));
+        Ok(versions)
+    }
+}
+
+/// The full bucket listing, stored on disk so that we don't hit the network on every invocation
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VersionCache {
+    /// Unix timestamp (seconds) of when the listing was fetched
+    pub fetched_at: u64,
+    pub version_count: usize,
+    items: Vec<BucketItem>,
+    /// The published `deprecations.json`, empty if there is none
+    #[serde(default)]
+    deprecations: Deprecations,
+    /// The mirror the listing was fetched from, `None` for Google Storage. Profiles can use different mirrors
+    #[serde(default)]
+    mirror_url: Option<String>,
+}
+impl VersionCache {
+    fn load() -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(
+            std::fs::read_to_string(version_cache_path()?)?.as_str(),
+        )?)
+    }
+    fn save(&self) -> anyhow::Result<()> {
+        let path = version_cache_path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        write_file_atomically(&path, &serde_json::to_string(self)?)
+    }
+    fn is_fresh(&self, settings: &Settings) -> bool {
+        self.mirror_url == settings.mirror_url
+            && unix_now().saturating_sub(self.fetched_at) < settings.cache_ttl_seconds()
+    }
+}
+
+/// The full bucket listing for this process, so that every resolution path shares a single load of it
+struct VersionIndex {
+    items: Vec<BucketItem>,
+    deprecations: Deprecations,
+    /// Fetched from the bucket during this run, rather than loaded from the version cache
+    live: bool,
+}
+impl VersionIndex {
+    fn remember(items: Vec<BucketItem>, deprecations: Deprecations, live: bool) -> Arc<Self> {
+        let index = Arc::new(Self {
+            items,
+            deprecations,
+            live,
+        });
+        *VERSION_INDEX.lock().unwrap() = Some(index.clone());
+        index
+    }
+    fn current() -> Option<Arc<Self>> {
+        VERSION_INDEX.lock().unwrap().clone()
+    }
+    /// `None` if nothing matches `prefix` and the listing may be missing newer versions
+    fn items_with_prefix(&self, prefix: &str) -> Option<Vec<BucketItem>> {
+        let full_prefix = format!("ambient-builds/{prefix}");
+        let items = self
+            .items
+            .iter()
+            .filter(|b| b.name.starts_with(&full_prefix))
+            .cloned()
+            .collect_vec();
+        (!items.is_empty() || prefix.is_empty() || self.live).then_some(items)
+    }
 }
+static VERSION_INDEX: Mutex<Option<Arc<VersionIndex>>> = Mutex::new(None);
 
-pub fn get_versions(filter: VersionsFilter) -> anyhow::Result<Vec<RuntimeVersion>> {
-    get_versions_with_prefix("", filter)
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub const BUCKET_URL: &str = "https://storage.googleapis.com/";
+const BUCKET_LIST_PATH: &str = "storage/v1/b/ambient-artifacts/o";
+
+/// Where the runtime versions are listed: in the mirror if one is set, else in the Google Storage bucket
+pub fn bucket_list_url(settings: &Settings) -> String {
+    format!(
+        "{}{}",
+        settings.mirror_url.as_deref().unwrap_or(BUCKET_URL),
+        BUCKET_LIST_PATH
+    )
+}
+
+/// Checks that `url` can be used as a mirror of the runtime bucket
+pub fn parse_mirror_url(url: &str) -> anyhow::Result<url::Url> {
+    let parsed = url::Url::parse(url).with_context(|| format!("Invalid mirror URL {:?}", url))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        anyhow::bail!(
+            "Invalid mirror URL {:?}: only http and https URLs are supported",
+            url
+        );
+    }
+    Ok(parsed)
+}
+
+fn fetch_bucket_items(settings: &Settings, prefix: &str) -> anyhow::Result<Vec<BucketItem>> {
+    fetch_bucket_items_from(settings, &bucket_list_url(settings), prefix)
+}
+/// Lists all items under `prefix`, following `nextPageToken` since each response holds at most 1000 items
+fn fetch_bucket_items_from(
+    settings: &Settings,
+    url: &str,
+    prefix: &str,
+) -> anyhow::Result<Vec<BucketItem>> {
+    const OPERATION: &str = "Listing runtime versions";
+    http::ensure_online(settings, OPERATION)?;
+    let agent = http::agent(settings)?;
+    let mut items = Vec::new();
+    let mut page_token: Option<String> = None;
+    loop {
+        let mut request = agent
+            .get(url)
+            .query("prefix", &format!("ambient-builds/{prefix}"))
+            .query("alt", "json");
+        if let Some(page_token) = &page_token {
+            request = request.query("pageToken", page_token);
+        }
+        let response = match request.call() {
+            Ok(response) => response,
+            // Error statuses still carry a JSON body describing the problem
+            Err(ureq::Error::Status(_, response)) => response,
+            Err(err) => return Err(http::network_error(settings, OPERATION, err)),
+        };
+        let body = response
+            .into_string()
+            .map_err(|err| http::network_error(settings, OPERATION, err))?;
+        let page = parse_bucket_list(&body)?;
+        items.extend(page.items);
+        match page.next_page_token {
+            Some(next_page_token) => page_token = Some(next_page_token),
+            None => return Ok(items),
+        }
+    }
+}
+
+/// Parses a bucket listing response, surfacing the error object Google Storage returns instead of a listing
+fn parse_bucket_list(body: &str) -> anyhow::Result<BucketList> {
+    let value: serde_json::Value = serde_json::from_str(body)
+        .with_context(|| format!("Invalid response from Google Storage: {}", snippet(body)))?;
+    if let Some(error) = value.get("error") {
+        anyhow::bail!(
+            "Google Storage returned {}: {}",
+            error
+                .get("code")
+                .map(|c| c.to_string())
+                .unwrap_or_else(|| "an error".to_string()),
+            error
+                .get("message")
+                .and_then(|m| m.as_str())
+                .unwrap_or("no message")
+        );
+    }
+    serde_json::from_value(value)
+        .with_context(|| format!("Unexpected response from Google Storage: {}", snippet(body)))
+}
+
+fn snippet(body: &str) -> String {
+    const MAX_LEN: usize = 200;
+    match body.char_indices().nth(MAX_LEN) {
+        Some((i, _)) => format!("{}...", &body[..i]),
+        None => body.to_string(),
+    }
+}
+
+/// Downloads the `deprecations.json` listed in `items`. The versions are still usable without it, so failing to
+/// download or parse it only counts as there being no deprecations
+fn fetch_deprecations(settings: &Settings, items: &[BucketItem]) -> Deprecations {
+    let Some(item) = items.iter().find(|item| item.name == DEPRECATIONS_PATH) else {
+        return Deprecations::default();
+    };
+    let deprecations = http::agent(settings).and_then(|agent| {
+        let body = agent.get(&item.media_link).call()?.into_string()?;
+        Ok(serde_json::from_str(&body)?)
+    });
+    match deprecations {
+        Ok(deprecations) => deprecations,
+        Err(err) => {
+            log::warn!(
+                "Ignoring the deprecated versions: failed to get them: {:#}",
+                err
+            );
+            Deprecations::default()
+        }
+    }
+}
+
+/// The deprecated and yanked versions, as of the last fetch of the version listing. Doesn't access the network
+pub fn get_deprecations() -> Deprecations {
+    match VersionIndex::current() {
+        Some(index) => index.deprecations.clone(),
+        None => VersionCache::load()
+            .map(|cache| cache.deprecations)
+            .unwrap_or_default(),
+    }
+}
+
+/// The versions in the listing last fetched from the bucket, if there is one. Doesn't access the network
+pub fn cached_versions() -> Option<Vec<semver::Version>> {
+    let items = match VersionIndex::current() {
+        Some(index) => index.items.clone(),
+        None => VersionCache::load().ok()?.items,
+    };
+    Some(
+        items
+            .iter()
+            .filter_map(|item| version_from_path(&item.name).ok())
+            .unique()
+            .collect(),
+    )
+}
+
+/// When the version listing in the version cache was fetched, if there is one
+pub fn version_cache_fetched_at() -> Option<chrono::DateTime<chrono::Utc>> {
+    let cache = VersionCache::load().ok()?;
+    chrono::DateTime::from_timestamp(cache.fetched_at as i64, 0)
+}
+
+/// Fetches the full version listing from the bucket and writes it to the version cache
+pub fn refresh_version_cache(settings: &Settings) -> anyhow::Result<VersionCache> {
+    let items = fetch_bucket_items(settings, "")?;
+    let deprecations = fetch_deprecations(settings, &items);
+    let cache = VersionCache {
+        fetched_at: unix_now(),
+        version_count: items
+            .iter()
+            .filter_map(|b| version_from_path(&b.name).ok())
+            .unique()
+            .count(),
+        items,
+        deprecations,
+        mirror_url: settings.mirror_url.clone(),
+    };
+    cache.save()?;
+    VersionIndex::remember(cache.items.clone(), cache.deprecations.clone(), true);
+    Ok(cache)
+}
+
+/// Uses the listing already loaded by this process, then the version cache when it's fresh (or when offline),
+/// and otherwise fetches the full listing from the bucket, so the network is hit at most once per run.
+/// Prefixed lookups that miss in the cache refetch, since the version may be newer than the cache.
+fn get_bucket_items(settings: &Settings, prefix: &str) -> anyhow::Result<Vec<BucketItem>> {
+    match VersionIndex::current() {
+        Some(index) => {
+            if let Some(items) = index.items_with_prefix(prefix) {
+                return Ok(items);
+            }
+        }
+        None => match VersionCache::load() {
+            Ok(cache) if cache.is_fresh(settings) || settings.offline => {
+                log::info!("Using cached version list");
+                if let Some(items) = VersionIndex::remember(cache.items, cache.deprecations, false)
+                    .items_with_prefix(prefix)
+                {
+                    return Ok(items);
+                }
+            }
+            Ok(_) => log::info!("Version cache is stale"),
+            Err(err) => log::info!("No usable version cache: {err}"),
+        },
+    }
+    refresh_version_cache(settings)?;
+    Ok(VersionIndex::current()
+        .and_then(|index| index.items_with_prefix(prefix))
+        .unwrap_or_default())
+}
+
+fn get_versions(
+    settings: &Settings,
+    filter: VersionsFilter,
+) -> anyhow::Result<Vec<RuntimeVersion>> {
+    get_versions_with_prefix(settings, "", filter)
 }
 fn get_versions_with_prefix(
+    settings: &Settings,
     prefix: &str,
     filter: VersionsFilter,
 ) -> anyhow::Result<Vec<RuntimeVersion>> {
-    let builds = ureq::get("https://storage.googleapis.com/storage/v1/b/ambient-artifacts/o")
-        .query("prefix", &format!("ambient-builds/{prefix}"))
-        .query("alt", "json")
-        .call()?
-        .into_json::<BucketList>()?;
-
+    versions_from_items(get_bucket_items(settings, prefix)?, filter)
+}
+/// Groups the bucket items by version, oldest version first by semver precedence
+fn versions_from_items(
+    builds: Vec<BucketItem>,
+    filter: VersionsFilter,
+) -> anyhow::Result<Vec<RuntimeVersion>> {
     let builds = builds
-        .items
         .into_iter()
         .filter_map(|b| Some((version_from_path(&b.name).ok()?, b)))
         .collect_vec();
@@ -122,29 +956,412 @@ fn get_versions_with_prefix(
                     Ok(Build {
                         os: Os::from_str(build.name.split('/').nth(2).context("Invalid build")?)?,
                         url: build.media_link,
+                        size: build.size.and_then(|size| size.parse().ok()),
+                        md5: build.md5_hash,
                     })
                 })
                 .collect::<anyhow::Result<Vec<_>>>()?,
         });
     }
-    if !filter.include_private {
-        versions.retain(|v| v.is_public());
+    versions.retain(|v| filter.allows(v));
+    versions.sort_by(|a, b| a.version.cmp(&b.version));
+    Ok(versions)
+}
+/// Strips a leading `v`/`V` so that `v0.3.0` is treated the same as `0.3.0`
+pub fn strip_version_prefix(version: &str) -> &str {
+    version
+        .strip_prefix('v')
+        .or_else(|| version.strip_prefix('V'))
+        .unwrap_or(version)
+}
+/// The versions a user-supplied version string like `v0.3` or `0.3.0-nightly` could mean, oldest first
+pub fn find_versions(
+    source: &dyn VersionSource,
+    version: &str,
+) -> anyhow::Result<Vec<RuntimeVersion>> {
+    let version = strip_version_prefix(version);
+    matching_versions(version, get_versions_matching_prefix(source, version)?)
+}
+/// All versions whose version string starts with `prefix`, oldest first by semver precedence (so `0.10.0` comes
+/// after `0.9.0`), for callers that want every match rather than the one `find_versions` would settle on
+pub fn get_versions_matching_prefix(
+    source: &dyn VersionSource,
+    prefix: &str,
+) -> anyhow::Result<Vec<RuntimeVersion>> {
+    let mut versions = source.list_with_prefix(strip_version_prefix(prefix))?;
+    versions.sort_by(|a, b| a.version.cmp(&b.version));
+    Ok(versions)
+}
+
+/// Finds the versions the user could mean with `input`, oldest first:
+/// - a full release version (`0.3.0`) must match exactly
+/// - a partial version (`0.3`) is treated as `~0.3`, and matches stable releases if there are any
+/// - anything else (`0.3.0-nightly`) matches the versions it's a prefix of, unless it's an exact match
+fn matching_versions(
+    input: &str,
+    candidates: Vec<RuntimeVersion>,
+) -> anyhow::Result<Vec<RuntimeVersion>> {
+    let mut candidates = candidates
+        .into_iter()
+        .filter(|v| v.version.to_string().starts_with(input))
+        .collect_vec();
+    candidates.sort_by(|a, b| a.version.cmp(&b.version));
+
+    if let Ok(version) = semver::Version::parse(input) {
+        if let Some(index) = candidates.iter().position(|v| v.version == version) {
+            return Ok(vec![candidates.swap_remove(index)]);
+        }
+        if version.pre.is_empty() {
+            let mut message = format!("Version {} not found", input);
+            if !candidates.is_empty() {
+                message += &format!(
+                    ". Did you mean one of:\n{}",
+                    candidates
+                        .iter()
+                        .rev()
+                        .map(|v| format!("  {}", v.version))
+                        .join("\n")
+                );
+            }
+            anyhow::bail!(message);
+        }
+    } else if let Some(req) = partial_version_req(input) {
+        candidates.retain(|v| req.matches(&v.version));
+        if candidates.iter().any(|v| v.is_point_release()) {
+            candidates.retain(|v| v.is_point_release());
+        }
+    }
+    if candidates.is_empty() {
+        anyhow::bail!("Version {} not found", input);
     }
-    if !filter.include_nightly {
-        versions.retain(|v| !v.is_nightly());
+    Ok(candidates)
+}
+
+/// `0` or `0.3` as `~0` or `~0.3`
+fn partial_version_req(input: &str) -> Option<semver::VersionReq> {
+    let parts = input.split('.').collect_vec();
+    if parts.len() > 2 || parts.iter().any(|p| p.parse::<u64>().is_err()) {
+        return None;
     }
-    versions.sort_by_key(|v| v.version.to_string());
-    Ok(versions)
+    semver::VersionReq::parse(&format!("~{}", input)).ok()
 }
-pub fn get_version(version: &str) -> anyhow::Result<RuntimeVersion> {
-    get_versions_with_prefix(
-        version,
-        VersionsFilter {
-            include_private: true,
-            include_nightly: true,
-        },
-    )?
-    .into_iter()
-    .next()
-    .context("Version not found")
+
+#[test]
+fn test_version_cache_freshness() {
+    let settings = Settings {
+        cache_ttl_seconds: Some(60),
+        ..Default::default()
+    };
+    let cache = |fetched_at| VersionCache {
+        fetched_at,
+        version_count: 0,
+        items: Vec::new(),
+        deprecations: Deprecations::default(),
+        mirror_url: None,
+    };
+    assert!(cache(unix_now() - 10).is_fresh(&settings));
+    assert!(!cache(unix_now() - 120).is_fresh(&settings));
+    // A listing from another mirror, e.g. of another profile, is never fresh
+    let settings = Settings {
+        mirror_url: Some("https://mirror.example.com/".into()),
+        ..settings
+    };
+    assert!(!cache(unix_now() - 10).is_fresh(&settings));
+}
+
+#[test]
+fn test_strip_version_prefix() {
+    assert_eq!(strip_version_prefix("v0.3.0"), "0.3.0");
+    assert_eq!(strip_version_prefix("V0.3.0"), "0.3.0");
+    assert_eq!(
+        strip_version_prefix("v0.3.0-nightly-2023-10-02"),
+        "0.3.0-nightly-2023-10-02"
+    );
+    assert_eq!(strip_version_prefix("0.3.0"), "0.3.0");
+}
+
+#[test]
+fn test_parse_bucket_list_error() {
+    let body = r#"{
+  "error": {
+    "code": 429,
+    "message": "rate limit exceeded",
+    "errors": [{ "message": "rate limit exceeded", "domain": "usageLimits", "reason": "rateLimitExceeded" }]
+  }
+}"#;
+    assert_eq!(
+        parse_bucket_list(body).unwrap_err().to_string(),
+        "Google Storage returned 429: rate limit exceeded"
+    );
+}
+
+#[test]
+fn test_parse_bucket_list_empty() {
+    let body = r#"{ "kind": "storage#objects" }"#;
+    assert!(parse_bucket_list(body).unwrap().items.is_empty());
+}
+
+#[test]
+fn test_parse_bucket_list_items() {
+    let body = r#"{
+  "kind": "storage#objects",
+  "items": [{
+    "name": "ambient-builds/0.3.0/ubuntu-22.04/ambient.zip",
+    "mediaLink": "https://example.com/ambient.zip"
+  }]
+}"#;
+    let items = parse_bucket_list(body).unwrap().items;
+    assert_eq!(items.len(), 1);
+    assert_eq!(
+        items[0].name,
+        "ambient-builds/0.3.0/ubuntu-22.04/ambient.zip"
+    );
+}
+
+#[test]
+fn test_parse_bucket_list_malformed() {
+    let err = parse_bucket_list(r#"{ "items": [{ "name": 1 }] }"#).unwrap_err();
+    assert!(err.to_string().contains(r#"{ "items": [{ "name": 1 }] }"#));
+    assert!(parse_bucket_list("<html>Bad Gateway</html>").is_err());
+}
+
+#[cfg(test)]
+fn runtime_versions(versions: &[&str]) -> Vec<RuntimeVersion> {
+    versions
+        .iter()
+        .map(|v| RuntimeVersion::without_builds(semver::Version::parse(v).unwrap()))
+        .collect()
+}
+
+#[test]
+fn test_find_versions() {
+    let source = FakeSource::new(&["0.3.0", "0.3.1", "0.3.2-nightly-2023-10-05"]);
+    let find = |version| {
+        find_versions(&source, version).map(|versions| {
+            versions
+                .into_iter()
+                .map(|v| v.version.to_string())
+                .collect_vec()
+        })
+    };
+    assert_eq!(find("v0.3.0").unwrap(), ["0.3.0"]);
+    assert_eq!(find("0.3").unwrap(), ["0.3.0", "0.3.1"]);
+    assert_eq!(find("0.3.2-nightly").unwrap(), ["0.3.2-nightly-2023-10-05"]);
+    assert_eq!(
+        find("0.4").unwrap_err().to_string(),
+        "Version 0.4 not found"
+    );
+}
+
+#[test]
+fn test_get_versions_matching_prefix() {
+    let source = FakeSource::new(&["0.9.0", "0.10.0", "0.1.0", "0.10.1-nightly-2023-10-05"]);
+    let matching = |prefix| {
+        get_versions_matching_prefix(&source, prefix)
+            .unwrap()
+            .into_iter()
+            .map(|v| v.version.to_string())
+            .collect_vec()
+    };
+    assert_eq!(
+        matching("0.1"),
+        ["0.1.0", "0.10.0", "0.10.1-nightly-2023-10-05"]
+    );
+    assert_eq!(matching("v0.9"), ["0.9.0"]);
+    assert!(matching("1.").is_empty());
+}
+
+#[test]
+fn test_matching_versions() {
+    let versions = runtime_versions(&[
+        "0.3.0",
+        "0.3.0-nightly-2023-10-01",
+        "0.3.0-nightly-2023-10-02",
+        "0.3.1",
+        "0.3.2-nightly-2023-10-05",
+        "0.30.0",
+    ]);
+    let matching = |input| {
+        matching_versions(input, versions.clone())
+            .map(|versions| versions.iter().map(|v| v.version.to_string()).collect_vec())
+    };
+    assert_eq!(matching("0.3.0").unwrap(), ["0.3.0"]);
+    assert_eq!(matching("0.3").unwrap(), ["0.3.0", "0.3.1"]);
+    assert_eq!(matching("0").unwrap(), ["0.3.0", "0.3.1", "0.30.0"]);
+    assert_eq!(
+        matching("0.3.0-nightly-2023-10-02").unwrap(),
+        ["0.3.0-nightly-2023-10-02"]
+    );
+    // The newest match comes last, which is the one that gets selected
+    assert_eq!(
+        matching("0.3.0-nightly").unwrap(),
+        ["0.3.0-nightly-2023-10-01", "0.3.0-nightly-2023-10-02"]
+    );
+    assert_eq!(
+        matching("0.3.2-nightly").unwrap(),
+        ["0.3.2-nightly-2023-10-05"]
+    );
+    assert!(matching("0.3.5").is_err());
+    assert!(matching("0.4").is_err());
+}
+
+#[test]
+fn test_runtime_version_serde_roundtrip() {
+    let runtime = RuntimeVersion {
+        version: semver::Version::parse("0.3.1-nightly-2023-10-05").unwrap(),
+        builds: vec![
+            Build {
+                os: Os::Linux,
+                url: "https://example.com/ubuntu-22.04/ambient.zip".to_string(),
+                size: Some(1024),
+                md5: Some("abc==".to_string()),
+            },
+            Build {
+                os: Os::Windows,
+                url: "https://example.com/windows-latest/ambient.zip".to_string(),
+                size: None,
+                md5: None,
+            },
+        ],
+    };
+    let json = serde_json::to_value(&runtime).unwrap();
+    assert_eq!(json["version"], "0.3.1-nightly-2023-10-05");
+    assert_eq!(json["builds"][0]["os"], "ubuntu-22.04");
+    assert_eq!(json["builds"][1]["os"], "windows-latest");
+    let roundtrip: RuntimeVersion = serde_json::from_value(json).unwrap();
+    assert_eq!(roundtrip.version, runtime.version);
+    assert_eq!(roundtrip.builds.len(), 2);
+    for (build, expected) in roundtrip.builds.iter().zip(&runtime.builds) {
+        assert_eq!(build.os, expected.os);
+        assert_eq!(build.url, expected.url);
+        assert_eq!(build.size, expected.size);
+        assert_eq!(build.md5, expected.md5);
+    }
+}
+
+#[test]
+fn test_versions_from_items_sorted_by_semver() {
+    // Google Storage lists the items by name, so 0.10 comes before 0.9
+    let items = ["0.10.0", "0.10.1", "0.9.0"]
+        .iter()
+        .map(|version| BucketItem {
+            name: format!("ambient-builds/{}/ubuntu-22.04/ambient.zip", version),
+            media_link: String::new(),
+            size: None,
+            md5_hash: None,
+        })
+        .collect_vec();
+    let versions = versions_from_items(items, VersionsFilter::all()).unwrap();
+    assert_eq!(
+        versions.iter().map(|v| v.version.to_string()).collect_vec(),
+        ["0.9.0", "0.10.0", "0.10.1"]
+    );
+}
+
+#[test]
+fn test_version_index_items_with_prefix() {
+    let item = |name: &str| BucketItem {
+        name: name.to_string(),
+        media_link: String::new(),
+        size: None,
+        md5_hash: None,
+    };
+    let items = vec![
+        item("ambient-builds/0.3.0/ubuntu-22.04/ambient.zip"),
+        item("ambient-builds/0.3.1/ubuntu-22.04/ambient.zip"),
+    ];
+    let cached = VersionIndex {
+        items: items.clone(),
+        deprecations: Deprecations::default(),
+        live: false,
+    };
+    assert_eq!(cached.items_with_prefix("").map(|i| i.len()), Some(2));
+    assert_eq!(cached.items_with_prefix("0.3.1").map(|i| i.len()), Some(1));
+    // The cache may predate 0.4.0, so it can't rule it out
+    assert!(cached.items_with_prefix("0.4.0").is_none());
+
+    let live = VersionIndex {
+        items,
+        deprecations: Deprecations::default(),
+        live: true,
+    };
+    assert_eq!(live.items_with_prefix("0.4.0").map(|i| i.len()), Some(0));
+}
+
+#[test]
+fn test_bucket_list_url() {
+    let mut settings = Settings {
+        mirror_url: Some("https://mirror.example.com/ambient/".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        bucket_list_url(&settings),
+        "https://mirror.example.com/ambient/storage/v1/b/ambient-artifacts/o"
+    );
+    settings.mirror_url = None;
+    assert_eq!(
+        bucket_list_url(&settings),
+        "https://storage.googleapis.com/storage/v1/b/ambient-artifacts/o"
+    );
+}
+
+#[test]
+fn test_parse_mirror_url() {
+    assert!(parse_mirror_url("https://mirror.example.com/").is_ok());
+    assert!(parse_mirror_url("http://10.0.0.5:8080/ambient/").is_ok());
+    assert!(parse_mirror_url("ftp://mirror.example.com/").is_err());
+    assert!(parse_mirror_url("mirror.example.com").is_err());
+}
+
+#[test]
+fn test_fetch_bucket_items_pagination() {
+    use std::io::{BufRead, BufReader, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/o", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let mut request_lines = Vec::new();
+        for _ in 0..2 {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request_line = String::new();
+            BufReader::new(&stream)
+                .read_line(&mut request_line)
+                .unwrap();
+            let body = if request_line.contains("pageToken=page2") {
+                r#"{ "items": [{ "name": "ambient-builds/0.3.1/ubuntu-22.04/ambient.zip", "mediaLink": "b" }] }"#
+            } else {
+                r#"{ "items": [{ "name": "ambient-builds/0.3.0/ubuntu-22.04/ambient.zip", "mediaLink": "a" }], "nextPageToken": "page2" }"#
+            };
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+            request_lines.push(request_line);
+        }
+        request_lines
+    });
+
+    let items = fetch_bucket_items_from(&Settings::default(), &url, "").unwrap();
+    assert_eq!(
+        items.iter().map(|i| i.media_link.as_str()).collect_vec(),
+        ["a", "b"]
+    );
+    let request_lines = server.join().unwrap();
+    assert!(!request_lines[0].contains("pageToken"));
+    assert!(request_lines[1].contains("pageToken=page2"));
+}
+
+#[test]
+fn test_runtime_version_display() {
+    let display =
+        |v: &str| RuntimeVersion::without_builds(semver::Version::parse(v).unwrap()).to_string();
+    assert_eq!(display("0.3.0"), "0.3.0");
+    assert_eq!(
+        display("0.3.0-nightly-2023-09-16"),
+        "0.3.0-nightly-2023-09-16 (nightly)"
+    );
+    assert_eq!(display("0.4.0-rc.1"), "0.4.0-rc.1 (beta)");
 }